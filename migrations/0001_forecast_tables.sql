@@ -0,0 +1,45 @@
+-- Forecast and observation tables as they were first created by hand, IF NOT EXISTS adopts those databases as-is
+CREATE TABLE IF NOT EXISTS hourly_weather(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ(6) NOT NULL DEFAULT now(),
+    weather_time TIMESTAMP(6) NOT NULL,
+    temp DOUBLE PRECISION NOT NULL,
+    feels_like DOUBLE PRECISION NOT NULL,
+    weather_code VARCHAR(255) NOT NULL,
+    precipitation_type VARCHAR(255) NOT NULL,
+    precipitation_chance DOUBLE PRECISION NOT NULL,
+    humidity DOUBLE PRECISION,
+    dew_point DOUBLE PRECISION
+);
+
+CREATE TABLE IF NOT EXISTS daily_weather(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ(6) NOT NULL DEFAULT now(),
+    weather_time TIMESTAMP(6) NOT NULL,
+    high DOUBLE PRECISION NOT NULL,
+    low DOUBLE PRECISION NOT NULL,
+    weather_code VARCHAR(255) NOT NULL,
+    moon_phase VARCHAR(255) NOT NULL,
+    sunrise_time TIMESTAMP(6),
+    sunset_time TIMESTAMP(6)
+);
+
+CREATE TABLE IF NOT EXISTS historical_weather(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ(6) NOT NULL DEFAULT now(),
+    obs_time_utc TIMESTAMPTZ(6) NOT NULL,
+    obs_time_local TIMESTAMP(6) NOT NULL,
+    temp INTEGER NOT NULL,
+    heat_index INTEGER NOT NULL,
+    wind_chill INTEGER NOT NULL,
+    dew_point INTEGER NOT NULL,
+    humidity INTEGER NOT NULL,
+    precip_rate DOUBLE PRECISION NOT NULL,
+    precip_total DOUBLE PRECISION NOT NULL,
+    wind_speed INTEGER NOT NULL,
+    wind_dir INTEGER NOT NULL,
+    wind_gust INTEGER NOT NULL,
+    pressure DOUBLE PRECISION NOT NULL,
+    solar_radiation DOUBLE PRECISION NOT NULL,
+    uv_index DOUBLE PRECISION NOT NULL
+);
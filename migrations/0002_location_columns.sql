@@ -0,0 +1,5 @@
+-- Rows from before multi-location support belong to the "default" location, which is what
+-- a config with a single [location] table gets called
+ALTER TABLE hourly_weather ADD COLUMN IF NOT EXISTS location VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE daily_weather ADD COLUMN IF NOT EXISTS location VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE historical_weather ADD COLUMN IF NOT EXISTS location VARCHAR NOT NULL DEFAULT 'default';
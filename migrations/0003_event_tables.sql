@@ -0,0 +1,14 @@
+-- Tempest lightning strikes and rain start events
+CREATE TABLE IF NOT EXISTS lightning_strikes(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    strike_time TIMESTAMPTZ NOT NULL,
+    distance_km DOUBLE PRECISION NOT NULL,
+    energy DOUBLE PRECISION NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS precip_events(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    event_time TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Provider request counts, one row per provider per hour and per day (period is 'hour' or 'day')
+CREATE TABLE IF NOT EXISTS api_usage(
+    provider VARCHAR NOT NULL,
+    period VARCHAR NOT NULL,
+    period_start TIMESTAMPTZ NOT NULL,
+    requests INTEGER NOT NULL,
+    PRIMARY KEY (provider, period, period_start)
+);
@@ -0,0 +1,24 @@
+-- One row per stored forecast pull, its hourly or daily rows point back at it so the latest run is
+-- picked by id instead of guessed from created_at and a row count
+CREATE TABLE IF NOT EXISTS forecast_runs(
+    id SERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    location VARCHAR NOT NULL,
+    dataset VARCHAR NOT NULL,
+    provider VARCHAR NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS forecast_runs_latest ON forecast_runs(location, dataset, id DESC);
+
+-- Rows stored before runs existed keep a NULL run_id
+ALTER TABLE hourly_weather ADD COLUMN IF NOT EXISTS run_id INTEGER REFERENCES forecast_runs(id);
+ALTER TABLE daily_weather ADD COLUMN IF NOT EXISTS run_id INTEGER REFERENCES forecast_runs(id);
+
+CREATE UNIQUE INDEX IF NOT EXISTS hourly_weather_run_time ON hourly_weather(run_id, weather_time);
+CREATE UNIQUE INDEX IF NOT EXISTS daily_weather_run_time ON daily_weather(run_id, weather_time);
+
+-- An observation time is stored once per location, duplicates already stored keep their first row
+DELETE FROM historical_weather dup USING historical_weather kept
+    WHERE dup.location = kept.location AND dup.obs_time_utc = kept.obs_time_utc AND dup.id > kept.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS historical_weather_location_time ON historical_weather(location, obs_time_utc);
@@ -0,0 +1,6 @@
+-- Every run is kept, issued_at is when it was pulled and what lead times are measured from
+ALTER TABLE forecast_runs RENAME COLUMN created_at TO issued_at;
+
+-- Looking up one target hour or day across all the runs that forecast it
+CREATE INDEX IF NOT EXISTS hourly_weather_location_time ON hourly_weather(location, weather_time);
+CREATE INDEX IF NOT EXISTS daily_weather_location_time ON daily_weather(location, weather_time);
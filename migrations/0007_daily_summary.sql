@@ -0,0 +1,24 @@
+-- One row per location and local day, rolled up from historical_weather by the summary job
+CREATE TABLE IF NOT EXISTS daily_summary(
+    location VARCHAR NOT NULL,
+    day DATE NOT NULL,
+    high_temp INTEGER NOT NULL,
+    high_time TIMESTAMPTZ NOT NULL,
+    low_temp INTEGER NOT NULL,
+    low_time TIMESTAMPTZ NOT NULL,
+    mean_temp DOUBLE PRECISION NOT NULL,
+    max_gust INTEGER NOT NULL,
+    max_gust_dir INTEGER NOT NULL,
+    max_gust_time TIMESTAMPTZ NOT NULL,
+    precip_total DOUBLE PRECISION NOT NULL,
+    max_uv DOUBLE PRECISION NOT NULL,
+    mean_pressure DOUBLE PRECISION,
+    min_humidity INTEGER NOT NULL,
+    observations INTEGER NOT NULL,
+    observed_hours INTEGER NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (location, day)
+);
+
+-- The rollup reads a couple of days of one location's observations at a time
+CREATE INDEX IF NOT EXISTS historical_weather_location_local_time ON historical_weather(location, obs_time_local);
@@ -0,0 +1,7 @@
+-- Tempest events belong to the location the hub reports for. Rows from before this belong to the
+-- "default" location, like the forecast and observation rows in 0002
+ALTER TABLE lightning_strikes ADD COLUMN IF NOT EXISTS location VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE precip_events ADD COLUMN IF NOT EXISTS location VARCHAR NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS lightning_strikes_location_time ON lightning_strikes(location, strike_time);
+CREATE INDEX IF NOT EXISTS precip_events_location_time ON precip_events(location, event_time);
@@ -0,0 +1,3 @@
+-- The provider's own weather code when it's one we don't know, stored as sent
+ALTER TABLE hourly_weather ADD COLUMN IF NOT EXISTS raw_weather_code VARCHAR;
+ALTER TABLE daily_weather ADD COLUMN IF NOT EXISTS raw_weather_code VARCHAR;
@@ -0,0 +1,18 @@
+-- weather_time, sunrise_time and sunset_time were written as UTC wall time but read back as local,
+-- so move the stored rows to local wall time. The runner sets TimeZone to this host's zone first.
+-- An hour repeated by the fall DST change collapses into one, the later row is kept like a pull does
+DROP INDEX IF EXISTS hourly_weather_run_time;
+DROP INDEX IF EXISTS daily_weather_run_time;
+
+UPDATE hourly_weather SET weather_time = (weather_time AT TIME ZONE 'UTC')::timestamp;
+UPDATE daily_weather SET weather_time = (weather_time AT TIME ZONE 'UTC')::timestamp,
+    sunrise_time = (sunrise_time AT TIME ZONE 'UTC')::timestamp,
+    sunset_time = (sunset_time AT TIME ZONE 'UTC')::timestamp;
+
+DELETE FROM hourly_weather dup USING hourly_weather kept
+    WHERE dup.run_id = kept.run_id AND dup.weather_time = kept.weather_time AND dup.id < kept.id;
+DELETE FROM daily_weather dup USING daily_weather kept
+    WHERE dup.run_id = kept.run_id AND dup.weather_time = kept.weather_time AND dup.id < kept.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS hourly_weather_run_time ON hourly_weather(run_id, weather_time);
+CREATE UNIQUE INDEX IF NOT EXISTS daily_weather_run_time ON daily_weather(run_id, weather_time);
@@ -0,0 +1,69 @@
+//Derived weather values for sources that only report the raw readings (all imperial)
+
+pub fn c_to_f(celsius: f64) -> f64 {
+    celsius * 9.0 / 5.0 + 32.0
+}
+
+//Metric output conversions, see config::Units
+pub fn f_to_c(fahrenheit: f64) -> f64 {
+    (fahrenheit - 32.0) * 5.0 / 9.0
+}
+
+//For differences between temperatures (forecast errors), where the 32 degree offset cancels out
+pub fn f_delta_to_c(fahrenheit: f64) -> f64 {
+    fahrenheit * 5.0 / 9.0
+}
+
+pub fn mph_to_kmh(mph: f64) -> f64 {
+    mph * 1.609344
+}
+
+pub fn in_to_mm(inches: f64) -> f64 {
+    inches * 25.4
+}
+
+pub fn inhg_to_hpa(inhg: f64) -> f64 {
+    inhg * 33.8639
+}
+
+//NWS Rothfusz regression, only meaningful from 80F up
+pub fn heat_index(temp_f: f64, humidity: f64) -> f64 {
+    if temp_f < 80.0 {
+        return temp_f;
+    }
+
+    -42.379 + 2.04901523 * temp_f + 10.14333127 * humidity - 0.22475541 * temp_f * humidity - 0.00683783 * temp_f * temp_f -
+        0.05481717 * humidity * humidity +
+        0.00122874 * temp_f * temp_f * humidity +
+        0.00085282 * temp_f * humidity * humidity -
+        0.00000199 * temp_f * temp_f * humidity * humidity
+}
+
+//NWS wind chill, only defined at or below 50F with wind over 3mph
+pub fn wind_chill(temp_f: f64, wind_mph: f64) -> f64 {
+    if temp_f > 50.0 || wind_mph <= 3.0 {
+        return temp_f;
+    }
+
+    let wind_factor = wind_mph.powf(0.16);
+
+    35.74 + 0.6215 * temp_f - 35.75 * wind_factor + 0.4275 * temp_f * wind_factor
+}
+
+//Feels-like temperature, whichever of heat index / wind chill applies. None when it's the heat index
+//and there's no humidity to work it out from
+pub fn apparent_temp(temp_f: f64, humidity: Option<f64>, wind_mph: f64) -> Option<f64> {
+    if temp_f >= 80.0 {
+        humidity.map(|humidity| heat_index(temp_f, humidity))
+    } else {
+        Some(wind_chill(temp_f, wind_mph))
+    }
+}
+
+//Magnus approximation, for stations that don't send a dew point
+pub fn dew_point(temp_f: f64, humidity: f64) -> f64 {
+    let temp_c = (temp_f - 32.0) * 5.0 / 9.0;
+    let gamma = (humidity.max(1.0) / 100.0).ln() + (17.62 * temp_c) / (243.12 + temp_c);
+
+    c_to_f((243.12 * gamma) / (17.62 - gamma))
+}
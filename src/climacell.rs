@@ -3,3 +3,75 @@ mod precipitation_type;
 mod weather_code;
 pub mod models;
 pub mod webmodels;
+
+//The codes every provider maps its own onto
+pub use moon_phase::MoonPhase;
+pub use precipitation_type::PrecipitationType;
+pub use weather_code::WeatherCode;
+
+use crate::{ config::{ Config, LocationConfig }, error::Error, forecast_db, provider::{ self, ForecastProvider }, quota };
+use models::{ DailyWeather, HourlyWeather };
+use webmodels::{ DailyRoot, HourlyRoot };
+
+//tomorrow.io (formerly climacell) timelines API
+pub struct ClimacellProvider {
+    lat_long: String,
+    api_key: String,
+    hourly_hours: i64,
+    daily_days: i64,
+}
+
+impl ClimacellProvider {
+    pub fn from_config(config: &Config, location: &LocationConfig) -> Result<ClimacellProvider, Error> {
+        Ok(ClimacellProvider {
+            lat_long: location.lat_long.clone(),
+            api_key: config.providers.climacell_api_key.clone().ok_or_else(|| Error::Config("Climacell API key not set".to_string()))?,
+            hourly_hours: config.horizons.hourly_hours,
+            daily_days: config.horizons.daily_days,
+        })
+    }
+}
+
+impl ForecastProvider for ClimacellProvider {
+    fn name(&self) -> &'static str {
+        "climacell"
+    }
+
+    fn get_hourly(&self) -> Result<Vec<HourlyWeather>, Error> {
+        println!("hitting hourly web API");
+        let client = reqwest::blocking::Client::new();
+
+        let params = [
+            ("location", self.lat_long.as_str()),
+            ("fields", "temperature,temperatureApparent,weatherCode,precipitationType,precipitationProbability,humidity,dewPoint"),
+            ("timesteps", "1h"),
+            ("endTime", &forecast_db::get_hourly_timestamp(self.hourly_hours)),
+            ("units", "imperial"),
+            ("apikey", &self.api_key),
+        ];
+
+        let resp = quota::send(self.name(), client.get("https://api.tomorrow.io/v4/timelines").query(&params))?;
+        let root = provider::parse_json::<HourlyRoot>(self.name(), resp)?;
+
+        HourlyWeather::convert(root).map_err(|e| Error::upstream_decode(self.name(), e))
+    }
+
+    fn get_daily(&self) -> Result<Vec<DailyWeather>, Error> {
+        println!("hitting daily web API");
+        let client = reqwest::blocking::Client::new();
+
+        let params = [
+            ("location", self.lat_long.as_str()),
+            ("units", "imperial"),
+            ("timesteps", "1d"),
+            ("endTime", &forecast_db::get_weekly_timestamp(self.daily_days)),
+            ("fields", "temperatureMin,temperatureMax,moonPhase,weatherCode,sunsetTime,sunriseTime"),
+            ("apikey", &self.api_key),
+        ];
+
+        let resp = quota::send(self.name(), client.get("https://api.tomorrow.io/v4/timelines").query(&params))?;
+        let root = provider::parse_json::<DailyRoot>(self.name(), resp)?;
+
+        DailyWeather::convert(root).map_err(|e| Error::upstream_decode(self.name(), e))
+    }
+}
@@ -1,86 +1,217 @@
+use crate::{calc, config::Units};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use super::webmodels::{DailyRoot, HourlyRoot};
+use super::{moon_phase::MoonPhase, precipitation_type::PrecipitationType, weather_code::WeatherCode, webmodels::{DailyRoot, HourlyRoot, Lenient}};
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct DailyWeather {
     pub id: i32,
     pub created_at: chrono::DateTime<Utc>,
     pub weather_time: chrono::DateTime<Utc>,
     pub high: f64,
     pub low: f64,
-    pub weather_code: crate::climacell::weather_code::WeatherCode,
-    pub moon_phase: crate::climacell::moon_phase::MoonPhase,
+    pub weather_code: WeatherCode,
+    pub moon_phase: MoonPhase,
     pub sunrise_time: Option<chrono::DateTime<Utc>>,
     pub sunset_time: Option<chrono::DateTime<Utc>>,
+    //What the provider sent when weather_code is Unknown because we don't know the code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_weather_code: Option<String>,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct HourlyWeather {
     pub id: i32,
     pub created_at: chrono::DateTime<Utc>,
     pub weather_time: chrono::DateTime<Utc>,
     pub temp: f64,
     pub feels_like: f64,
-    pub weather_code: crate::climacell::weather_code::WeatherCode,
-    pub precipitation_type: crate::climacell::precipitation_type::PrecipitationType,
+    pub weather_code: WeatherCode,
+    pub precipitation_type: PrecipitationType,
     pub precipitation_chance: f64,
     pub humidity: Option<f64>,
     pub dew_point: Option<f64>,
+    //What the provider sent when weather_code is Unknown because we don't know the code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_weather_code: Option<String>,
 }
 
 impl HourlyWeather {
-    pub fn convert(hourly: HourlyRoot) -> Vec<HourlyWeather> {
+    //Intervals without a time or temperature are skipped, anything else missing gets a stand-in
+    pub fn convert(hourly: HourlyRoot) -> Result<Vec<HourlyWeather>, String> {
         let mut hourlyvec = vec![];
+        let mut skipped = vec![];
+
+        let timeline = hourly.data.timelines.first().ok_or_else(|| "No hourly timeline in response".to_string())?;
+
+        for (i, interval) in timeline.intervals.iter().enumerate() {
+            let values = &interval.values;
+
+            let (weather_time, temp) = match (interval.start_time.as_deref().map(parse_time), values.temperature) {
+                (Some(Ok(weather_time)), Some(temp)) => (weather_time, temp),
+                (Some(Err(e)), _) => {
+                    skipped.push(format!("#{i}: {e}"));
+                    continue;
+                }
+                _ => {
+                    skipped.push(format!("#{i}: missing startTime or temperature"));
+                    continue;
+                }
+            };
+
+            let at = weather_time.to_rfc3339();
+            let weather_code = values.weather_code.as_ref().map_or(WeatherCode::Unknown, |code| code.or_log(WeatherCode::Unknown, "weatherCode", &at));
 
-        for interval in hourly.data.timelines[0].intervals.iter() {
             hourlyvec.push(HourlyWeather {
                 id: 0,
                 created_at: Utc::now(),
-                weather_time: DateTime::parse_from_rfc3339(&interval.start_time)
-                    .unwrap()
-                    .into(),
-                temp: interval.values.temperature,
-                feels_like: interval.values.temperature_apparent,
-                weather_code: interval.values.weather_code,
-                precipitation_chance: interval.values.precipitation_probability as f64,
-                precipitation_type: interval.values.precipitation_type,
-                humidity: interval.values.humidity,
-                dew_point: interval.values.dew_point,
+                weather_time,
+                temp,
+                feels_like: values.temperature_apparent.unwrap_or(temp),
+                weather_code,
+                precipitation_chance: values.precipitation_probability.unwrap_or(0.0),
+                precipitation_type: match &values.precipitation_type {
+                    Some(precipitation_type) => precipitation_type.or_log(PrecipitationType::NA, "precipitationType", &at),
+                    None => PrecipitationType::from_weather_code(weather_code),
+                },
+                humidity: values.humidity,
+                dew_point: values.dew_point,
+                raw_weather_code: values.weather_code.as_ref().and_then(Lenient::raw),
             });
         }
 
-        hourlyvec
+        if !skipped.is_empty() {
+            println!("Skipped {} unusable hourly intervals from tomorrow.io: {}", skipped.len(), skipped.join(", "));
+        }
+
+        Ok(hourlyvec)
     }
 }
 impl DailyWeather {
-    pub fn convert(daily: DailyRoot) -> Vec<DailyWeather> {
+    //Intervals without a time or high/low are skipped, anything else missing gets a stand-in
+    pub fn convert(daily: DailyRoot) -> Result<Vec<DailyWeather>, String> {
         let mut dailyvec = vec![];
+        let mut skipped = vec![];
+
+        let timeline = daily.data.timelines.first().ok_or_else(|| "No daily timeline in response".to_string())?;
+
+        for (i, interval) in timeline.intervals.iter().enumerate() {
+            let values = &interval.values;
+
+            let (weather_time, high, low) = match (interval.start_time.as_deref().map(parse_time), values.temperature_max, values.temperature_min) {
+                (Some(Ok(weather_time)), Some(high), Some(low)) => (weather_time, high, low),
+                (Some(Err(e)), _, _) => {
+                    skipped.push(format!("#{i}: {e}"));
+                    continue;
+                }
+                _ => {
+                    skipped.push(format!("#{i}: missing startTime, temperatureMax or temperatureMin"));
+                    continue;
+                }
+            };
+
+            let at = weather_time.to_rfc3339();
 
-        for interval in daily.data.timelines[0].intervals.iter() {
             dailyvec.push(DailyWeather {
                 id: 0,
                 created_at: Utc::now(),
-                weather_time: DateTime::parse_from_rfc3339(&interval.start_time)
-                    .unwrap()
-                    .into(),
-                high: interval.values.temperature_max,
-                low: interval.values.temperature_min,
-                weather_code: interval.values.weather_code,
-                sunrise_time: Some(
-                    DateTime::parse_from_rfc3339(&interval.values.sunrise_time)
-                        .unwrap()
-                        .into(),
-                ),
-                sunset_time: Some(
-                    DateTime::parse_from_rfc3339(&interval.values.sunset_time)
-                        .unwrap()
-                        .into(),
-                ),
-                moon_phase: interval.values.moon_phase,
+                weather_time,
+                high,
+                low,
+                weather_code: values.weather_code.as_ref().map_or(WeatherCode::Unknown, |code| code.or_log(WeatherCode::Unknown, "weatherCode", &at)),
+                //A bad sunrise/sunset only loses that value, not the day
+                sunrise_time: values.sunrise_time.as_deref().and_then(|t| parse_time(t).ok()),
+                sunset_time: values.sunset_time.as_deref().and_then(|t| parse_time(t).ok()),
+                moon_phase: match &values.moon_phase {
+                    Some(moon_phase) => moon_phase.or_log(MoonPhase::from_date(weather_time), "moonPhase", &at),
+                    None => MoonPhase::from_date(weather_time),
+                },
+                raw_weather_code: values.weather_code.as_ref().and_then(Lenient::raw),
             });
         }
 
-        dailyvec
+        if !skipped.is_empty() {
+            println!("Skipped {} unusable daily intervals from tomorrow.io: {}", skipped.len(), skipped.join(", "));
+        }
+
+        Ok(dailyvec)
+    }
+}
+
+impl HourlyWeather {
+    pub fn in_units(mut self, units: Units) -> HourlyWeather {
+        if units == Units::Metric {
+            self.temp = calc::f_to_c(self.temp);
+            self.feels_like = calc::f_to_c(self.feels_like);
+            self.dew_point = self.dew_point.map(calc::f_to_c);
+        }
+
+        self
+    }
+}
+
+impl DailyWeather {
+    pub fn in_units(mut self, units: Units) -> DailyWeather {
+        if units == Units::Metric {
+            self.high = calc::f_to_c(self.high);
+            self.low = calc::f_to_c(self.low);
+        }
+
+        self
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|t| t.into())
+        .map_err(|e| format!("Couldn't parse tomorrow.io time '{time}', Reason: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_codes_are_kept_raw() {
+        let hourly: HourlyRoot = serde_json::from_str(
+            r#"{"data": {"timelines": [{"timestep": "1h", "intervals": [
+                {"startTime": "2024-04-12T14:00:00Z", "values": {"temperature": 71.2, "weatherCode": 1000, "precipitationType": 0}},
+                {"startTime": "2024-04-12T15:00:00Z", "values": {"temperature": 72.5, "weatherCode": 2301, "precipitationType": 9}},
+                {"startTime": "2024-04-12T16:00:00Z", "values": {"temperature": 73.0, "weatherCode": "hail"}}
+            ]}]}}"#
+        ).unwrap();
+
+        let hourly = HourlyWeather::convert(hourly).unwrap();
+        assert_eq!(hourly.len(), 3);
+
+        assert_eq!(hourly[0].weather_code, WeatherCode::Clear);
+        assert_eq!(hourly[0].raw_weather_code, None);
+
+        assert_eq!(hourly[1].weather_code, WeatherCode::Unknown);
+        assert_eq!(hourly[1].raw_weather_code.as_deref(), Some("2301"));
+        assert_eq!(hourly[1].precipitation_type, PrecipitationType::NA);
+
+        assert_eq!(hourly[2].weather_code, WeatherCode::Unknown);
+        assert_eq!(hourly[2].raw_weather_code.as_deref(), Some("hail"));
+
+        //Served alongside the Unknown code, left out when there's nothing to add
+        let served = serde_json::to_value(&hourly[1]).unwrap();
+        assert_eq!(served["raw_weather_code"], "2301");
+        assert!(serde_json::to_value(&hourly[0]).unwrap().get("raw_weather_code").is_none());
+    }
+
+    #[test]
+    fn unknown_daily_codes_are_kept_raw() {
+        let daily: DailyRoot = serde_json::from_str(
+            r#"{"data": {"timelines": [{"timestep": "1d", "intervals": [
+                {"startTime": "2024-04-12T10:00:00Z", "values": {"temperatureMax": 80.1, "temperatureMin": 55.3, "weatherCode": 8888, "moonPhase": 42}}
+            ]}]}}"#
+        ).unwrap();
+
+        let daily = DailyWeather::convert(daily).unwrap();
+
+        assert_eq!(daily[0].weather_code, WeatherCode::Unknown);
+        assert_eq!(daily[0].raw_weather_code.as_deref(), Some("8888"));
+        assert_eq!(daily[0].moon_phase, MoonPhase::from_date(daily[0].weather_time));
     }
 }
@@ -6,9 +6,6 @@ use std::error::Error;
 use std::str::FromStr;
 use strum_macros::{Display, EnumString};
 
-
-#[allow(non_camel_case_types)]
-
 #[derive(
     Default, Debug, Clone, PartialEq, Serialize, Deserialize_repr, Copy, EnumString, Display,
 )]
@@ -26,16 +23,39 @@ pub enum MoonPhase {
     Waning_Crescent = 7,
 }
 
+//Mean synodic month and a known new moon (2000-01-06 18:14 UTC)
+const SYNODIC_MONTH_DAYS: f64 = 29.530588853;
+const REFERENCE_NEW_MOON_EPOCH: i64 = 947182440;
+
+impl MoonPhase {
+    //For providers that don't report a moon phase, bucketed the same way tomorrow.io does
+    pub fn from_date(date: chrono::DateTime<chrono::Utc>) -> MoonPhase {
+        let days = (date.timestamp() - REFERENCE_NEW_MOON_EPOCH) as f64 / 86400.0;
+        let age = days.rem_euclid(SYNODIC_MONTH_DAYS) / SYNODIC_MONTH_DAYS;
+
+        match ((age * 8.0).round() as u8) % 8 {
+            1 => MoonPhase::Waxing_Crescent,
+            2 => MoonPhase::First_Quarter,
+            3 => MoonPhase::Waxing_Gibbous,
+            4 => MoonPhase::Full,
+            5 => MoonPhase::Waning_Gibbous,
+            6 => MoonPhase::Third_Quarter,
+            7 => MoonPhase::Waning_Crescent,
+            _ => MoonPhase::New,
+        }
+    }
+}
+
 impl<'a> FromSql<'a> for MoonPhase {
     fn from_sql(
         _ty: &postgres::types::Type,
         raw: &'a [u8],
     ) -> Result<
         MoonPhase,
-        Box<(dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static)>,
+        Box<dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static>,
     > {
         let text = String::from_utf8_lossy(raw);
-        Ok(MoonPhase::from_str(&text).unwrap())
+        MoonPhase::from_str(&text).map_err(|e| format!("Couldn't read moon phase '{text}' from DB, Reason: {e}").into())
     }
 
     fn accepts(ty: &postgres::types::Type) -> bool {
@@ -49,12 +69,40 @@ impl ToSql for MoonPhase {
         ty: &Type,
         out: &mut postgres_types::private::BytesMut,
     ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
-        return self.to_string().to_sql(ty, out);
+        self.to_string().to_sql(ty, out)
     }
 
     to_sql_checked!();
 
     fn accepts(ty: &Type) -> bool {
-        return ty.name() == "varchar";
+        ty.name() == "varchar"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn phases_follow_the_published_ones() {
+        let at = |month, day, hour| MoonPhase::from_date(Utc.with_ymd_and_hms(2024, month, day, hour, 0, 0).unwrap());
+
+        assert_eq!(at(4, 8, 18), MoonPhase::New);
+        assert_eq!(at(4, 11, 12), MoonPhase::Waxing_Crescent);
+        assert_eq!(at(4, 15, 19), MoonPhase::First_Quarter);
+        assert_eq!(at(4, 19, 12), MoonPhase::Waxing_Gibbous);
+        assert_eq!(at(4, 23, 23), MoonPhase::Full);
+        assert_eq!(at(4, 27, 12), MoonPhase::Waning_Gibbous);
+        assert_eq!(at(5, 1, 11), MoonPhase::Third_Quarter);
+        assert_eq!(at(5, 4, 12), MoonPhase::Waning_Crescent);
+        assert_eq!(at(5, 7, 18), MoonPhase::New);
+    }
+
+    #[test]
+    fn dates_before_the_reference_new_moon_still_work() {
+        assert_eq!(MoonPhase::from_date(Utc.timestamp_opt(REFERENCE_NEW_MOON_EPOCH, 0).unwrap()), MoonPhase::New);
+        //Full moon of 1999-12-22
+        assert_eq!(MoonPhase::from_date(Utc.with_ymd_and_hms(1999, 12, 22, 17, 0, 0).unwrap()), MoonPhase::Full);
     }
 }
@@ -5,6 +5,7 @@ use serde_repr::Deserialize_repr;
 use std::error::Error;
 use std::str::FromStr;
 use strum_macros::{Display, EnumString};
+use super::weather_code::WeatherCode;
 
 
 #[allow(non_camel_case_types)]
@@ -24,16 +25,33 @@ pub enum PrecipitationType {
     Ice_Pellets = 4,
 }
 
+impl PrecipitationType {
+    //For providers that only give a condition code
+    pub fn from_weather_code(code: WeatherCode) -> PrecipitationType {
+        match code {
+            WeatherCode::Snow | WeatherCode::Flurries | WeatherCode::Light_Snow | WeatherCode::Heavy_Snow => PrecipitationType::Snow,
+            WeatherCode::Freezing_Drizzle | WeatherCode::Freezing_Rain | WeatherCode::Light_Freezing_Rain | WeatherCode::Heavy_Freezing_Rain => {
+                PrecipitationType::Freezing_Rain
+            }
+            WeatherCode::Ice_Pellets | WeatherCode::Heavy_Ice_Pellets | WeatherCode::Light_Ice_Pellets => PrecipitationType::Ice_Pellets,
+            WeatherCode::Drizzle | WeatherCode::Rain | WeatherCode::Light_Rain | WeatherCode::Heavy_Rain | WeatherCode::Thunderstorm => {
+                PrecipitationType::Rain
+            }
+            _ => PrecipitationType::NA,
+        }
+    }
+}
+
 impl<'a> FromSql<'a> for PrecipitationType {
     fn from_sql(
         _ty: &postgres::types::Type,
         raw: &'a [u8],
     ) -> Result<
         PrecipitationType,
-        Box<(dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static)>,
+        Box<dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static>,
     > {
         let text = String::from_utf8_lossy(raw);
-        Ok(PrecipitationType::from_str(&text).unwrap())
+        PrecipitationType::from_str(&text).map_err(|e| format!("Couldn't read precipitation type '{text}' from DB, Reason: {e}").into())
     }
 
     fn accepts(ty: &postgres::types::Type) -> bool {
@@ -46,12 +64,12 @@ impl ToSql for PrecipitationType {
         ty: &Type,
         out: &mut postgres_types::private::BytesMut,
     ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
-        return self.to_string().to_sql(ty, out);
+        self.to_string().to_sql(ty, out)
     }
 
     to_sql_checked!();
 
     fn accepts(ty: &Type) -> bool {
-        return ty.name() == "varchar";
+        ty.name() == "varchar"
     }
 }
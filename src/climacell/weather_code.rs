@@ -66,10 +66,10 @@ impl<'a> FromSql<'a> for WeatherCode {
         raw: &'a [u8],
     ) -> Result<
         WeatherCode,
-        Box<(dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static)>,
+        Box<dyn std::error::Error + std::marker::Send + std::marker::Sync + 'static>,
     > {
         let text = String::from_utf8_lossy(raw);
-        Ok(WeatherCode::from_str(&text).unwrap())
+        WeatherCode::from_str(&text).map_err(|e| format!("Couldn't read weather code '{text}' from DB, Reason: {e}").into())
     }
 
     fn accepts(ty: &postgres::types::Type) -> bool {
@@ -83,12 +83,12 @@ impl ToSql for WeatherCode {
         ty: &Type,
         out: &mut postgres_types::private::BytesMut,
     ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
-        return self.to_string().to_sql(ty, out);
+        self.to_string().to_sql(ty, out)
     }
 
     to_sql_checked!();
 
     fn accepts(ty: &Type) -> bool {
-        return ty.name() == "varchar";
+        ty.name() == "varchar"
     }
 }
@@ -1,42 +1,51 @@
-use serde::{Deserialize, Serialize};
-use super::{moon_phase::MoonPhase, weather_code::WeatherCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt::Display;
+use super::{moon_phase::MoonPhase, precipitation_type::PrecipitationType, weather_code::WeatherCode};
+
+//tomorrow.io adds fields and codes over time and leaves values out, so nothing here is required.
+//Intervals that end up unusable are skipped in models.rs instead of failing the whole response
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "root")]
 pub struct HourlyRoot {
+    #[serde(default)]
     pub data: Data,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Data {
+    #[serde(default)]
     pub timelines: Vec<Timeline>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Timeline {
-    pub timestep: String,
-    pub end_time: String,
-    pub start_time: String,
+    pub timestep: Option<String>,
+    pub end_time: Option<String>,
+    pub start_time: Option<String>,
+    #[serde(default)]
     pub intervals: Vec<Interval>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Interval {
-    pub start_time: String,
+    pub start_time: Option<String>,
+    #[serde(default)]
     pub values: Values,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Values {
-    pub precipitation_probability: i64,
-    pub precipitation_type: crate::climacell::precipitation_type::PrecipitationType,
-    pub temperature: f64,
-    pub temperature_apparent: f64,
-    pub weather_code: crate::climacell::weather_code::WeatherCode,
+    pub precipitation_probability: Option<f64>,
+    pub precipitation_type: Option<Lenient<PrecipitationType>>,
+    pub temperature: Option<f64>,
+    pub temperature_apparent: Option<f64>,
+    pub weather_code: Option<Lenient<WeatherCode>>,
     pub humidity: Option<f64>,
     pub dew_point: Option<f64>,
 }
@@ -44,38 +53,85 @@ pub struct Values {
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "root")]
 pub struct DailyRoot {
+    #[serde(default)]
     pub data: DailyData,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "data")]
 pub struct DailyData {
+    #[serde(default)]
     pub timelines: Vec<DailyTimelines>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "timeline")]
 pub struct DailyTimelines {
-    pub timestep: String,
-    pub end_time: String,
-    pub start_time: String,
+    pub timestep: Option<String>,
+    pub end_time: Option<String>,
+    pub start_time: Option<String>,
+    #[serde(default)]
     pub intervals: Vec<DailyIntervals>,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "interval")]
 pub struct DailyIntervals {
-    pub start_time: String,
+    pub start_time: Option<String>,
+    #[serde(default)]
     pub values: DailyValues,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase", rename = "value")]
 pub struct DailyValues {
-    pub moon_phase: MoonPhase,
-    pub sunrise_time: String,
-    pub sunset_time: String,
-    pub temperature_max: f64,
-    pub temperature_min: f64,
-    pub weather_code: WeatherCode,
-}
\ No newline at end of file
+    pub moon_phase: Option<Lenient<MoonPhase>>,
+    pub sunrise_time: Option<String>,
+    pub sunset_time: Option<String>,
+    pub temperature_max: Option<f64>,
+    pub temperature_min: Option<f64>,
+    pub weather_code: Option<Lenient<WeatherCode>>,
+}
+
+//A code we know, or whatever was sent in its place, kept as is so it can be logged
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Lenient<T> {
+    Known(T),
+    Unknown(serde_json::Value),
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Lenient<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = serde_json::Value::deserialize(deserializer)?;
+
+        Ok(match T::deserialize(&raw) {
+            Ok(known) => Lenient::Known(known),
+            Err(_) => Lenient::Unknown(raw),
+        })
+    }
+}
+
+impl<T> Lenient<T> {
+    //An unknown code as it was sent, strings without their quotes
+    pub fn raw(&self) -> Option<String> {
+        match self {
+            Lenient::Known(_) => None,
+            Lenient::Unknown(serde_json::Value::String(raw)) => Some(raw.clone()),
+            Lenient::Unknown(raw) => Some(raw.to_string()),
+        }
+    }
+}
+
+impl<T: Copy + Display> Lenient<T> {
+    //Unknown codes become the fallback, logged with the field and interval they came from
+    pub fn or_log(&self, fallback: T, field: &str, at: &str) -> T {
+        match self {
+            Lenient::Known(known) => *known,
+            Lenient::Unknown(raw) => {
+                println!("Unknown tomorrow.io {field} {raw} at {at}, using {fallback}");
+                fallback
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use crate::{ calc, climacell::models::HourlyWeather, config::{ CorrectionConfig, Units }, error::Error, forecast_db, zone::{ self, Zone } };
+use chrono::Timelike;
+use serde::Serialize;
+use std::collections::HashMap;
+
+//Local hour of day and lead time bucket
+pub type CellKey = (u32, i32);
+
+//Observed minus forecast, averaged over the pairs behind it
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cell {
+    pub temp: f64,
+    pub temp_samples: i64,
+    pub dew_point: f64,
+    pub dew_point_samples: i64,
+}
+
+//What the station saw against what the location's forecast provider said, by local hour of day and lead time.
+//A valley station whose lows run cold ends up with negative temp cells overnight
+#[derive(Debug, Default)]
+pub struct Corrections {
+    by_hour_lead: HashMap<CellKey, Cell>,
+    //Every lead time together, for cells with too few pairs
+    by_hour: HashMap<u32, Cell>,
+    lead_bucket_hours: i32,
+    min_samples: i64,
+    //The location's, whose local hours the cells are keyed by
+    zone: Zone,
+}
+
+//An hour of the raw forecast with the correction applied, the raw one stays on /forecast/hourly
+#[derive(Debug, Serialize)]
+pub struct CorrectedHourly {
+    #[serde(flatten)]
+    pub hourly: HourlyWeather,
+    //Added to temp and feels_like, 0 without enough history
+    pub temp_correction: f64,
+    pub dew_point_correction: f64,
+    //Forecast and observation pairs the temp correction comes from
+    pub correction_samples: i64,
+}
+
+impl Cell {
+    //Weighted by samples, so merging cells gives the same mean as pooling their pairs
+    fn merge(self, other: Cell) -> Cell {
+        let mean = |a: f64, a_n: i64, b: f64, b_n: i64| if a_n + b_n == 0 { 0.0 } else { (a * a_n as f64 + b * b_n as f64) / (a_n + b_n) as f64 };
+
+        Cell {
+            temp: mean(self.temp, self.temp_samples, other.temp, other.temp_samples),
+            temp_samples: self.temp_samples + other.temp_samples,
+            dew_point: mean(self.dew_point, self.dew_point_samples, other.dew_point, other.dew_point_samples),
+            dew_point_samples: self.dew_point_samples + other.dew_point_samples,
+        }
+    }
+}
+
+impl Corrections {
+    //From the provider's forecasts for the location over the last window_days
+    pub fn learn(location: &str, provider: &str, config: &CorrectionConfig) -> Result<Corrections, Error> {
+        let zone = zone::of(location);
+        let cells = forecast_db::get_correction_cells_db(location, provider, config.window_days, config.lead_bucket_hours, zone.now())?;
+
+        let mut by_hour: HashMap<u32, Cell> = HashMap::new();
+        for ((hour, _), cell) in cells.iter() {
+            let merged = by_hour.get(hour).copied().unwrap_or_default().merge(*cell);
+            by_hour.insert(*hour, merged);
+        }
+
+        Ok(Corrections {
+            by_hour_lead: cells.into_iter().collect(),
+            by_hour,
+            lead_bucket_hours: config.lead_bucket_hours,
+            min_samples: config.min_samples,
+            zone,
+        })
+    }
+
+    pub fn apply(&self, hourly: &HourlyWeather, units: Units) -> CorrectedHourly {
+        let hour = self.zone.at(hourly.weather_time).hour();
+        let lead_hours = (hourly.weather_time - hourly.created_at).num_hours().max(0);
+        let bucket = lead_hours.checked_div(self.lead_bucket_hours as i64).unwrap_or(0) as i32;
+
+        //The hour and lead time cell when it has the samples, otherwise the hour across all lead times
+        let pick = |samples: fn(&Cell) -> i64| {
+            [self.by_hour_lead.get(&(hour, bucket)), self.by_hour.get(&hour)]
+                .into_iter()
+                .flatten()
+                .find(|cell| samples(cell) >= self.min_samples)
+                .copied()
+        };
+
+        let (temp_correction, correction_samples) = pick(|c| c.temp_samples).map_or((0.0, 0), |c| (c.temp, c.temp_samples));
+        let dew_point_correction = pick(|c| c.dew_point_samples).map_or(0.0, |c| c.dew_point);
+
+        let mut corrected = hourly.clone();
+        corrected.temp += temp_correction;
+        corrected.feels_like += temp_correction;
+        corrected.dew_point = corrected.dew_point.map(|d| d + dew_point_correction);
+
+        let (temp_correction, dew_point_correction) = match units {
+            Units::Imperial => (temp_correction, dew_point_correction),
+            Units::Metric => (calc::f_delta_to_c(temp_correction), calc::f_delta_to_c(dew_point_correction)),
+        };
+
+        CorrectedHourly { hourly: corrected.in_units(units), temp_correction, dew_point_correction, correction_samples }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{ DurationRound, Local, TimeZone, Utc };
+
+    fn cell(temp: f64, temp_samples: i64) -> Cell {
+        Cell { temp, temp_samples, ..Cell::default() }
+    }
+
+    fn corrections(by_hour_lead: &[(CellKey, Cell)], by_hour: &[(u32, Cell)]) -> Corrections {
+        Corrections { by_hour_lead: by_hour_lead.iter().copied().collect(), by_hour: by_hour.iter().copied().collect(), lead_bucket_hours: 6, min_samples: 5, zone: Zone::Host }
+    }
+
+    //6am local, forecast 9 hours ahead so it lands in lead bucket 1
+    fn six_am() -> HourlyWeather {
+        let weather_time = Local.with_ymd_and_hms(2024, 4, 12, 6, 0, 0).unwrap().with_timezone(&Utc);
+        HourlyWeather { weather_time, created_at: weather_time - chrono::Duration::hours(9), temp: 50.0, feels_like: 48.0, dew_point: Some(40.0), ..HourlyWeather::default() }
+    }
+
+    #[test]
+    fn merged_cells_weigh_by_samples() {
+        let merged = cell(2.0, 1).merge(cell(-1.0, 3));
+
+        assert_eq!(merged.temp, -0.25);
+        assert_eq!(merged.temp_samples, 4);
+        assert_eq!(merged.dew_point, 0.0);
+        assert_eq!(merged.dew_point_samples, 0);
+        assert_eq!(cell(2.0, 4).merge(Cell::default()).temp, 2.0);
+    }
+
+    #[test]
+    fn hour_and_lead_cell_is_used_once_it_has_min_samples() {
+        let corrected = corrections(&[((6, 1), cell(-3.0, 5))], &[(6, cell(-1.0, 40))]).apply(&six_am(), Units::Imperial);
+
+        assert_eq!(corrected.temp_correction, -3.0);
+        assert_eq!(corrected.correction_samples, 5);
+        assert_eq!(corrected.hourly.temp, 47.0);
+        assert_eq!(corrected.hourly.feels_like, 45.0);
+    }
+
+    #[test]
+    fn thin_cells_fall_back_to_the_hour_of_day() {
+        let corrected = corrections(&[((6, 1), cell(-3.0, 4))], &[(6, cell(-1.0, 40))]).apply(&six_am(), Units::Imperial);
+
+        assert_eq!(corrected.temp_correction, -1.0);
+        assert_eq!(corrected.correction_samples, 40);
+        assert_eq!(corrected.hourly.temp, 49.0);
+    }
+
+    #[test]
+    fn no_correction_below_min_samples() {
+        let corrected = corrections(&[((6, 1), cell(-3.0, 4))], &[(6, cell(-1.0, 4)), (7, cell(-2.0, 40))]).apply(&six_am(), Units::Imperial);
+
+        assert_eq!(corrected.temp_correction, 0.0);
+        assert_eq!(corrected.correction_samples, 0);
+        assert_eq!(corrected.hourly.temp, 50.0);
+        assert_eq!(corrected.hourly.dew_point, Some(40.0));
+    }
+
+    #[test]
+    fn corrections_are_learned_for_the_local_hour_observed() {
+        if !forecast_db::test_db() {
+            return;
+        }
+
+        let location = forecast_db::test_location("correction");
+        let hour = Utc::now().duration_trunc(chrono::Duration::hours(1)).unwrap() - chrono::Duration::hours(3);
+        let hourly = HourlyWeather { weather_time: hour, temp: 50.0, ..HourlyWeather::default() };
+
+        forecast_db::dump_hourly_db(&location, "test", &[hourly]).unwrap();
+        forecast_db::connect()
+            .unwrap()
+            .execute("UPDATE forecast_runs SET issued_at = $2 WHERE location = $1", &[&location, &(hour - chrono::Duration::hours(9))])
+            .unwrap();
+
+        for (minutes, temp) in [(10, 46), (50, 48), (-10, 30), (70, 30)] {
+            let time = hour + chrono::Duration::minutes(minutes);
+            let inst = crate::wunder::models::InstModel { obs_time_utc: time, obs_time_local: time.with_timezone(&Local).naive_local(), temp, ..Default::default() };
+            forecast_db::dump_inst_db(&location, &inst).unwrap();
+        }
+
+        let config = CorrectionConfig { window_days: 2, min_samples: 1, lead_bucket_hours: 6 };
+        let learned = Corrections::learn(&location, "test", &config).unwrap();
+        let local_hour = hour.with_timezone(&Local).hour();
+
+        assert_eq!(learned.by_hour_lead.len(), 1);
+        assert_eq!(learned.by_hour_lead[&(local_hour, 1)].temp, -3.0);
+        assert_eq!(learned.by_hour[&local_hour].temp_samples, 1);
+    }
+}
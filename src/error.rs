@@ -0,0 +1,130 @@
+use chrono::{ DateTime, Utc };
+use nickel::status::StatusCode;
+use serde::Serialize;
+use std::fmt::{ self, Display };
+
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+//What went wrong, with the underlying error kept as the source. Routes answer with the status
+//and an ErrorBody, the cache loops log it, and every one that's handled is counted on /forecast/metrics
+#[derive(Debug)]
+pub enum Error {
+    //Missing or unusable settings
+    Config(String),
+    //The provider couldn't be reached, refused us, or answered with an error status
+    UpstreamHttp {
+        provider: &'static str,
+        source: Source,
+    },
+    //The provider answered with something we couldn't use
+    UpstreamDecode {
+        provider: &'static str,
+        source: Source,
+    },
+    //Our limits or the provider's are used up, nothing was sent
+    QuotaExceeded {
+        provider: &'static str,
+        until: DateTime<Utc>,
+    },
+    //No pooled connection within database.connect_timeout_seconds
+    DbConnect(r2d2::Error),
+    DbQuery {
+        context: &'static str,
+        source: postgres::Error,
+    },
+    //Bad request parameters, station uploads, or provider data that failed our checks
+    Validation(String),
+    NotFound(String),
+    Unauthorized(String),
+}
+
+//Every kind, so the metrics list them before they first happen
+pub const KINDS: [&str; 9] = [
+    "config",
+    "upstream_http",
+    "upstream_decode",
+    "quota_exceeded",
+    "db_connect",
+    "db_query",
+    "validation",
+    "not_found",
+    "unauthorized",
+];
+
+//JSON body of a failed request
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl Error {
+    pub fn upstream_http(provider: &'static str, source: impl Into<Source>) -> Error {
+        Error::UpstreamHttp { provider, source: source.into() }
+    }
+
+    pub fn upstream_decode(provider: &'static str, source: impl Into<Source>) -> Error {
+        Error::UpstreamDecode { provider, source: source.into() }
+    }
+
+    //For map_err, e.g. .map_err(Error::db_query("Couldn't retrieve daily data from DB"))
+    pub fn db_query(context: &'static str) -> impl FnOnce(postgres::Error) -> Error {
+        move |source| Error::DbQuery { context, source }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::UpstreamHttp { .. } => "upstream_http",
+            Error::UpstreamDecode { .. } => "upstream_decode",
+            Error::QuotaExceeded { .. } => "quota_exceeded",
+            Error::DbConnect(_) => "db_connect",
+            Error::DbQuery { .. } => "db_query",
+            Error::Validation(_) => "validation",
+            Error::NotFound(_) => "not_found",
+            Error::Unauthorized(_) => "unauthorized",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::Config(_) | Error::DbQuery { .. } => StatusCode::InternalServerError,
+            Error::UpstreamHttp { .. } | Error::UpstreamDecode { .. } => StatusCode::BadGateway,
+            Error::QuotaExceeded { .. } => StatusCode::TooManyRequests,
+            Error::DbConnect(_) => StatusCode::ServiceUnavailable,
+            Error::Validation(_) => StatusCode::BadRequest,
+            Error::NotFound(_) => StatusCode::NotFound,
+            Error::Unauthorized(_) => StatusCode::Unauthorized,
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody { error: self.kind(), message: self.to_string() }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Config(message) | Error::Validation(message) | Error::NotFound(message) | Error::Unauthorized(message) => {
+                write!(f, "{message}")
+            }
+            Error::UpstreamHttp { provider, source } => write!(f, "Couldn't retrieve data from {provider}, Reason: {source}"),
+            Error::UpstreamDecode { provider, source } => write!(f, "Couldn't deserialize data from {provider}, Reason: {source}"),
+            Error::QuotaExceeded { provider, until } => write!(f, "{provider} quota used up until {until}"),
+            Error::DbConnect(source) => write!(f, "Couldn't connect to DB, Reason: {source}"),
+            Error::DbQuery { context, source } => write!(f, "{context}, Reason: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::UpstreamHttp { source, .. } | Error::UpstreamDecode { source, .. } => Some(source.as_ref()),
+            Error::DbConnect(source) => Some(source),
+            Error::DbQuery { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
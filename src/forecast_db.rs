@@ -1,202 +1,752 @@
-use chrono::{ DateTime, Local, TimeZone, Utc };
-use postgres::{ Client, NoTls };
+use chrono::{ DateTime, FixedOffset, NaiveDate, NaiveTime, Utc };
+use native_tls::{ Certificate, Identity, TlsConnector };
+use postgres_native_tls::MakeTlsConnector;
+use r2d2::{ event::{ CheckoutEvent, TimeoutEvent }, HandleError, HandleEvent, Pool, PooledConnection };
+use r2d2_postgres::PostgresConnectionManager;
+use serde::Serialize;
 use std::str::FromStr;
+use std::sync::{ atomic::{ AtomicU64, Ordering }, OnceLock };
+use std::time::Duration;
 
-use crate::{ climacell::{ self, models::{ DailyWeather, HourlyWeather } }, wunder::{ self, models::InstModel } };
+use crate::{
+    calc,
+    config::{ DatabaseConfig, SslMode, Units },
+    correction::{ Cell, CellKey },
+    error::Error,
+    climacell::{ self, models::{ DailyWeather, HourlyWeather }, PrecipitationType, WeatherCode },
+    summary::DailySummary,
+    tempest::models::{ Events, LightningStrike, PrecipEvent },
+    verification::{ DailyVerification, HourlyVerification, MIN_DAILY_OBSERVED_HOURS, Period, PrecipScores, Scores },
+    wunder::{ self, models::InstModel },
+    zone::{ self, Zone },
+};
 
-pub fn poke_db_timestamps() -> Result<(DateTime<Utc>, DateTime<Utc>), String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            let new_hourly = match client.query("SELECT created_at FROM hourly_weather ORDER BY created_at DESC", &[]) {
-                Ok(t) => t[0].get(0),
-                Err(_) => DateTime::<Utc>::MIN_UTC,
-            };
+//Issue time of the latest hourly and daily runs. A location with no runs yet (just added to the config) counts as expired
+pub fn poke_db_timestamps(location: &str) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
+    let mut client = connect()?;
+    let mut latest = |dataset: &str| {
+        match client.query_opt("SELECT max(issued_at) FROM forecast_runs WHERE location = $1 AND dataset = $2", &[&location, &dataset]) {
+            Ok(Some(t)) => t.get::<_, Option<DateTime<Utc>>>(0).unwrap_or(DateTime::<Utc>::MIN_UTC),
+            _ => DateTime::<Utc>::MIN_UTC,
+        }
+    };
 
-            let new_daily = match client.query("SELECT created_at FROM daily_weather ORDER BY created_at DESC", &[]) {
-                Ok(t) => t[0].get(0),
-                Err(_) => DateTime::<Utc>::MIN_UTC,
-            };
+    let new_hourly = latest("hourly");
+    let new_daily = latest("daily");
 
-            Ok((new_hourly, new_daily))
-        }
-        Err(e) => Err(format!("Couldn't connect to DB, Reason: {e}")),
+    Ok((new_hourly, new_daily))
+}
+
+type Manager = PostgresConnectionManager<MakeTlsConnector>;
+
+//One pool for the whole process, the cache loops, routes and station listeners all check out of it.
+//Connections are opened as they're needed and reopened in the background when the server drops them
+static POOL: OnceLock<Pool<Manager>> = OnceLock::new();
+
+static EVENTS: PoolEvents = PoolEvents {
+    checkouts: AtomicU64::new(0),
+    timeouts: AtomicU64::new(0),
+    connection_errors: AtomicU64::new(0),
+};
+
+struct PoolEvents {
+    checkouts: AtomicU64,
+    timeouts: AtomicU64,
+    connection_errors: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolStats {
+    pub max_size: u32,
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub in_use: u32,
+    //Since startup
+    pub checkouts: u64,
+    pub timeouts: u64,
+    pub connection_errors: u64,
+}
+
+#[derive(Debug)]
+struct PoolEventCounter;
+
+impl HandleEvent for PoolEventCounter {
+    fn handle_checkout(&self, _event: CheckoutEvent) {
+        EVENTS.checkouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn handle_timeout(&self, _event: TimeoutEvent) {
+        EVENTS.timeouts.fetch_add(1, Ordering::Relaxed);
     }
 }
 
-pub fn get_conn_str() -> String {
-    let conn_str = format!(
-        "host='{db_hostname}' dbname='{db_name}' port={db_port} user='{db_username}' password='{db_password}'",
+//r2d2 keeps retrying in the background, each failed attempt ends up here
+#[derive(Debug)]
+struct PoolErrorLogger;
 
-        db_hostname = std::env::var("RUSTYFORECAST_DBHOSTNAME").expect("DB Hostname not set"),
-        db_name = std::env::var("RUSTYFORECAST_DBNAME").expect("DB Name not set"),
-        db_port = std::env::var("RUSTYFORECAST_DBPORT").expect("DB Port not set"),
-        db_username = std::env::var("RUSTYFORECAST_DBUSER").expect("DB Username not set"),
-        db_password = std::env::var("RUSTYFORECAST_DBPASS").expect("DB Pass not set")
-    );
+impl HandleError<postgres::Error> for PoolErrorLogger {
+    fn handle_error(&self, e: postgres::Error) {
+        EVENTS.connection_errors.fetch_add(1, Ordering::Relaxed);
+        println!("DB pool couldn't open a connection, Reason: {e}");
+    }
+}
 
-    conn_str
+//Where to connect and how, from database.url or the separate keys
+pub struct Connection {
+    pub pg_config: postgres::Config,
+    pub sslmode: SslMode,
+    pub ssl_root_cert: Option<String>,
+    pub ssl_cert: Option<String>,
+    pub ssl_key: Option<String>,
 }
 
-pub fn get_daily_db() -> Result<Vec<DailyWeather>, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            match
-                client.query(
-                    "SELECT * FROM(SELECT id, created_at, weather_time, high, low, weather_code, moon_phase, sunrise_time, sunset_time FROM daily_weather ORDER BY created_at DESC LIMIT 8) as Recent ORDER BY weather_time ASC;",
-                    &[]
-                )
-            {
-                Ok(dailies) => {
-                    let mut return_vec = vec![];
-
-                    for row in dailies {
-                        let weather_convert: chrono::NaiveDateTime = row.get(2);
-
-                        let daily = climacell::models::DailyWeather {
-                            id: row.get(0),
-                            created_at: row.get(1),
-                            weather_time: Local.from_local_datetime(&weather_convert).unwrap().into(),
-                            high: row.get(3),
-                            low: row.get(4),
-                            weather_code: row.get(5),
-                            moon_phase: row.get(6),
-                            sunrise_time: match row.try_get(7) {
-                                Ok(t) => Some(DateTime::<Utc>::from_local(t, Utc)),
-                                Err(_) => None,
-                            },
-                            sunset_time: match row.try_get(8) {
-                                Ok(t) => Some(DateTime::<Utc>::from_local(t, Utc)),
-                                Err(_) => None,
-                            },
-                        };
-
-                        return_vec.push(daily);
-                    }
+//Also run by config validation, so a bad URL or sslmode is reported with the rest of the config
+pub fn connection(database: &DatabaseConfig) -> Result<Connection, String> {
+    let mut connection = Connection {
+        pg_config: postgres::Config::new(),
+        sslmode: database.sslmode,
+        ssl_root_cert: database.ssl_root_cert.clone(),
+        ssl_cert: database.ssl_cert.clone(),
+        ssl_key: database.ssl_key.clone(),
+    };
 
-                    Ok(return_vec)
+    match &database.url {
+        Some(url) => {
+            //postgres doesn't know verify-full, so the ssl parameters are taken out before it parses the rest
+            let (base, query) = url.split_once('?').unwrap_or((url, ""));
+            let mut rest = vec![];
+
+            for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+                match pair.split_once('=') {
+                    Some(("sslmode", mode)) => {
+                        connection.sslmode = mode.parse::<SslMode>().map_err(|e| format!("database.url: {e}"))?;
+                    }
+                    Some(("sslrootcert", path)) => connection.ssl_root_cert = Some(path.to_string()),
+                    Some(("sslcert", path)) => connection.ssl_cert = Some(path.to_string()),
+                    Some(("sslkey", path)) => connection.ssl_key = Some(path.to_string()),
+                    _ => rest.push(pair),
                 }
-                Err(e) => Err(format!("Couldn't retrieve Daily Data from DB, Reason: {e}")),
             }
+
+            let url = if rest.is_empty() { base.to_string() } else { format!("{base}?{}", rest.join("&")) };
+
+            connection.pg_config = url.parse::<postgres::Config>().map_err(|e| format!("Couldn't parse database.url, Reason: {e}"))?;
+        }
+        None => {
+            connection.pg_config
+                .host(&database.hostname)
+                .port(database.port)
+                .dbname(&database.name)
+                .user(&database.user)
+                .password(&database.password);
         }
-        Err(e) => Err(format!("Couldn't connect to DB, Reason: {e}")),
     }
+
+    if connection.pg_config.get_connect_timeout().is_none() {
+        connection.pg_config.connect_timeout(Duration::from_secs(database.connect_timeout_seconds));
+    }
+
+    connection.pg_config.ssl_mode(match connection.sslmode {
+        SslMode::Disable => postgres::config::SslMode::Disable,
+        SslMode::Prefer => postgres::config::SslMode::Prefer,
+        SslMode::Require | SslMode::VerifyFull => postgres::config::SslMode::Require,
+    });
+
+    if connection.ssl_cert.is_some() != connection.ssl_key.is_some() {
+        return Err("database.ssl_cert and database.ssl_key go together".to_string());
+    }
+
+    Ok(connection)
 }
 
-pub fn get_hourly_db() -> Result<Vec<HourlyWeather>, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            match
-                client.query(
-                    "SELECT * FROM (SELECT id, created_at, weather_time, temp, feels_like, weather_code, precipitation_type, precipitation_chance, humidity, dew_point from hourly_weather ORDER BY created_at DESC LIMIT 25) as Recent ORDER BY weather_time ASC;",
-                    &[]
-                )
-            {
-                Ok(hourlies) => {
-                    let mut return_vec = vec![];
-
-                    for row in hourlies.iter() {
-                        let weather_convert: chrono::NaiveDateTime = row.get(2);
-
-                        let hourly = climacell::models::HourlyWeather {
-                            id: row.get(0),
-                            created_at: row.get(1),
-                            weather_time: Local.from_local_datetime(&weather_convert).unwrap().into(),
-                            temp: row.get(3),
-                            feels_like: row.get(4),
-                            weather_code: row.get(5),
-                            precipitation_type: row.get(6),
-                            precipitation_chance: row.get(7),
-                            humidity: row.get(8),
-                            dew_point: row.get(9),
-                        };
-
-                        return_vec.push(hourly);
-                    }
+//Same checks as libpq: prefer and require take any certificate unless require has a CA to check against,
+//verify-full checks the certificate and that it's for the host we asked for
+fn tls_connector(connection: &Connection) -> Result<MakeTlsConnector, String> {
+    let mut builder = TlsConnector::builder();
 
-                    Ok(return_vec)
-                }
-                Err(e) => Err(format!("Couldn't deserialize Hourly data from DB, Reason: {e}")),
-            }
+    if let Some(path) = &connection.ssl_root_cert {
+        let pem = std::fs::read(path).map_err(|e| format!("Couldn't read database.ssl_root_cert {path}, Reason: {e}"))?;
+        let certs = pem_certificates(&pem);
+
+        if certs.is_empty() {
+            return Err(format!("Couldn't load CA bundle {path}, Reason: no certificates in it"));
+        }
+
+        for cert in certs {
+            let cert = Certificate::from_pem(cert.as_bytes()).map_err(|e| format!("Couldn't load CA bundle {path}, Reason: {e}"))?;
+            builder.add_root_certificate(cert);
         }
-        Err(e) => Err(format!("Couldn't connect to DB, Reason: {e}")),
     }
-}
 
-pub fn get_historical_db(day: &str) -> Result<Vec<InstModel>, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            let query_str = format!("SELECT * FROM historical_weather WHERE date(obs_time_local) = '{day}'");
-
-            match client.query(&query_str, &[]) {
-                Ok(rows) => {
-                    let mut returns = vec![];
-
-                    for row in rows.iter() {
-                        returns.push(InstModel {
-                            obs_time_utc: row.get(2),
-                            obs_time_local: row.get(3),
-                            temp: row.get(4),
-                            heat_index: row.get(5),
-                            wind_chill: row.get(6),
-                            dewpt: row.get(7),
-                            humidity: row.get(8),
-                            precip_rate: row.get(9),
-                            precip_total: row.get(10),
-                            wind_speed: row.get(11),
-                            winddir: row.get(12),
-                            wind_gust: row.get(13),
-                            pressure: row.get(14),
-                            solar_radiation: row.get(15),
-                            uv: row.get(16),
-                        });
-                    }
+    if let (Some(cert_path), Some(key_path)) = (&connection.ssl_cert, &connection.ssl_key) {
+        let cert = std::fs::read(cert_path).map_err(|e| format!("Couldn't read database.ssl_cert {cert_path}, Reason: {e}"))?;
+        let key = std::fs::read(key_path).map_err(|e| format!("Couldn't read database.ssl_key {key_path}, Reason: {e}"))?;
+        let identity = Identity::from_pkcs8(&cert, &key).map_err(|e| format!("Couldn't load client certificate {cert_path}, Reason: {e}"))?;
 
-                    Ok(returns)
-                }
-                Err(e) => Err(format!("Couldn't deserialize the historical day data as a vector, Reason: {e}")),
-            }
+        builder.identity(identity);
+    }
+
+    match connection.sslmode {
+        SslMode::Disable | SslMode::Prefer => {
+            builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+        SslMode::Require => {
+            builder.danger_accept_invalid_certs(connection.ssl_root_cert.is_none()).danger_accept_invalid_hostnames(true);
         }
-        Err(e) => Err(format!("Client couldn't connect to DB, Reason: {e}")),
+        SslMode::VerifyFull => {}
     }
+
+    let connector = builder.build().map_err(|e| format!("Couldn't set up TLS for the DB, Reason: {e}"))?;
+
+    Ok(MakeTlsConnector::new(connector))
 }
 
-pub fn get_historical_range_db(day1: &str, day2: &str) -> Result<Vec<InstModel>, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            let query_str = format!("SELECT * FROM historical_weather WHERE date(obs_time_local) BETWEEN '{day1}' AND '{day2}'");
-
-            match client.query(&query_str, &[]) {
-                Ok(rows) => {
-                    let mut returns = vec![];
-                    for row in rows.iter() {
-                        returns.push(InstModel {
-                            obs_time_utc: row.get(2),
-                            obs_time_local: row.get(3),
-                            temp: row.get(4),
-                            heat_index: row.get(5),
-                            wind_chill: row.get(6),
-                            dewpt: row.get(7),
-                            humidity: row.get(8),
-                            precip_rate: row.get(9),
-                            precip_total: row.get(10),
-                            wind_speed: row.get(11),
-                            winddir: row.get(12),
-                            wind_gust: row.get(13),
-                            pressure: row.get(14),
-                            solar_radiation: row.get(15),
-                            uv: row.get(16),
-                        });
-                    }
+//Each certificate block of a PEM bundle, Certificate::from_pem only reads the first
+fn pem_certificates(pem: &[u8]) -> Vec<String> {
+    const END: &str = "-----END CERTIFICATE-----";
 
-                    Ok(returns)
-                }
-                Err(e) => Err(format!("Couldn't deserialize the historical range data as a vector, Reason: {e}")),
-            }
+    String::from_utf8_lossy(pem)
+        .split_inclusive(END)
+        .filter_map(|block| block.find("-----BEGIN CERTIFICATE-----").map(|start| &block[start..]))
+        .filter(|block| block.ends_with(END))
+        .map(str::to_string)
+        .collect()
+}
+
+//Called once from main before anything touches the DB. Doesn't connect, so a DB that's down
+//at startup only fails the queries made while it stays down
+pub fn init_pool(database: &DatabaseConfig) -> Result<(), Error> {
+    let connection = connection(database).map_err(Error::Config)?;
+    let tls = tls_connector(&connection).map_err(Error::Config)?;
+
+    let pool = Pool::builder()
+        .max_size(database.pool_size)
+        //One kept warm, the rest opened on demand
+        .min_idle(Some(1))
+        .connection_timeout(Duration::from_secs(database.connect_timeout_seconds))
+        .test_on_check_out(database.test_on_checkout)
+        .event_handler(Box::new(PoolEventCounter))
+        .error_handler(Box::new(PoolErrorLogger))
+        .build_unchecked(PostgresConnectionManager::new(connection.pg_config, tls));
+
+    POOL.set(pool).map_err(|_| Error::Config("DB pool already set up".to_string()))
+}
+
+//A scratch database for the tests that need one, RUSTYFORECAST_TEST_DB holding its URL or connection string.
+//Migrated on first use. Tests store under a location of their own from test_location, false skips them
+#[cfg(test)]
+pub fn test_db() -> bool {
+    static READY: OnceLock<bool> = OnceLock::new();
+
+    *READY.get_or_init(|| {
+        let Ok(url) = std::env::var("RUSTYFORECAST_TEST_DB") else {
+            println!("RUSTYFORECAST_TEST_DB isn't set, skipping DB tests");
+            return false;
+        };
+
+        init_pool(&DatabaseConfig { url: Some(url), ..DatabaseConfig::default() }).unwrap();
+        crate::migrate::run().unwrap();
+        true
+    })
+}
+
+//Unique across runs, the scratch database keeps what earlier runs stored
+#[cfg(test)]
+pub fn test_location(name: &str) -> String {
+    format!("test-{name}-{}", Utc::now().timestamp_nanos_opt().unwrap_or_default())
+}
+
+pub fn connect() -> Result<PooledConnection<Manager>, Error> {
+    //Only the tests run without one, their quota counts then just aren't stored
+    let pool = POOL.get().ok_or_else(|| Error::Config("DB used before forecast_db::init_pool".to_string()))?;
+
+    pool.get().map_err(Error::DbConnect)
+}
+
+pub fn pool_stats() -> Option<PoolStats> {
+    let pool = POOL.get()?;
+    let state = pool.state();
+
+    Some(PoolStats {
+        max_size: pool.max_size(),
+        connections: state.connections,
+        idle_connections: state.idle_connections,
+        in_use: state.connections - state.idle_connections,
+        checkouts: EVENTS.checkouts.load(Ordering::Relaxed),
+        timeouts: EVENTS.timeouts.load(Ordering::Relaxed),
+        connection_errors: EVENTS.connection_errors.load(Ordering::Relaxed),
+    })
+}
+
+//Row::get panics when FromSql fails, on a weather_code or moon_phase this build doesn't know for one,
+//so the readers go through try_get and fail the query instead
+fn column<'a, T: postgres::types::FromSql<'a>>(row: &'a postgres::Row, name: &str) -> Result<T, Error> {
+    row.try_get(name).map_err(Error::db_query("Couldn't read a stored row"))
+}
+
+//weather_time, sunrise_time and sunset_time are stored as wall time in the location's zone, the clock obs_time_local
+//is in, so forecast and observed hours and days pair up in SQL
+pub fn get_daily_db(location: &str) -> Result<Vec<DailyWeather>, Error> {
+    let zone = zone::of(location);
+    let mut client = connect()?;
+
+    let dailies = client
+        .query(
+            "SELECT id, created_at, weather_time, high, low, weather_code, moon_phase, sunrise_time, sunset_time, raw_weather_code FROM daily_weather WHERE run_id = (SELECT id FROM forecast_runs WHERE location = $1 AND dataset = 'daily' ORDER BY id DESC LIMIT 1) ORDER BY weather_time ASC",
+            &[&location]
+        )
+        .map_err(Error::db_query("Couldn't retrieve Daily Data from DB"))?;
+
+    let mut return_vec = vec![];
+
+    for row in dailies {
+        let weather_convert: chrono::NaiveDateTime = column(&row, "weather_time")?;
+
+        let daily = climacell::models::DailyWeather {
+            id: column(&row, "id")?,
+            created_at: column(&row, "created_at")?,
+            weather_time: zone.instant(weather_convert)?,
+            high: column(&row, "high")?,
+            low: column(&row, "low")?,
+            weather_code: column(&row, "weather_code")?,
+            moon_phase: column(&row, "moon_phase")?,
+            sunrise_time: column::<Option<chrono::NaiveDateTime>>(&row, "sunrise_time")?.map(|t| zone.instant(t)).transpose()?,
+            sunset_time: column::<Option<chrono::NaiveDateTime>>(&row, "sunset_time")?.map(|t| zone.instant(t)).transpose()?,
+            raw_weather_code: column(&row, "raw_weather_code")?,
+        };
+
+        return_vec.push(daily);
+    }
+
+    Ok(return_vec)
+}
+
+pub fn get_hourly_db(location: &str) -> Result<Vec<HourlyWeather>, Error> {
+    let zone = zone::of(location);
+    let mut client = connect()?;
+
+    let hourlies = client
+        .query(
+            "SELECT id, created_at, weather_time, temp, feels_like, weather_code, precipitation_type, precipitation_chance, humidity, dew_point, raw_weather_code FROM hourly_weather WHERE run_id = (SELECT id FROM forecast_runs WHERE location = $1 AND dataset = 'hourly' ORDER BY id DESC LIMIT 1) ORDER BY weather_time ASC",
+            &[&location]
+        )
+        .map_err(Error::db_query("Couldn't deserialize Hourly data from DB"))?;
+
+    let mut return_vec = vec![];
+
+    for row in hourlies.iter() {
+        let weather_convert: chrono::NaiveDateTime = column(row, "weather_time")?;
+
+        let hourly = climacell::models::HourlyWeather {
+            id: column(row, "id")?,
+            created_at: column(row, "created_at")?,
+            weather_time: zone.instant(weather_convert)?,
+            temp: column(row, "temp")?,
+            feels_like: column(row, "feels_like")?,
+            weather_code: column(row, "weather_code")?,
+            precipitation_type: column(row, "precipitation_type")?,
+            precipitation_chance: column(row, "precipitation_chance")?,
+            humidity: column(row, "humidity")?,
+            dew_point: column(row, "dew_point")?,
+            raw_weather_code: column(row, "raw_weather_code")?,
+        };
+
+        return_vec.push(hourly);
+    }
+
+    Ok(return_vec)
+}
+
+//What one run forecast for a target hour, lead_hours before it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyRevision {
+    pub run_id: i32,
+    pub issued_at: DateTime<Utc>,
+    pub provider: String,
+    pub lead_hours: f64,
+    pub temp: f64,
+    pub feels_like: f64,
+    pub weather_code: WeatherCode,
+    pub precipitation_type: PrecipitationType,
+    pub precipitation_chance: f64,
+    pub humidity: Option<f64>,
+    pub dew_point: Option<f64>,
+}
+
+//What one run forecast for a target day, lead_days calendar days before it
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyRevision {
+    pub run_id: i32,
+    pub issued_at: DateTime<Utc>,
+    pub provider: String,
+    pub lead_days: i64,
+    pub high: f64,
+    pub low: f64,
+    pub weather_code: WeatherCode,
+}
+
+impl HourlyRevision {
+    pub fn in_units(mut self, units: Units) -> HourlyRevision {
+        if units == Units::Metric {
+            self.temp = calc::f_to_c(self.temp);
+            self.feels_like = calc::f_to_c(self.feels_like);
+            self.dew_point = self.dew_point.map(calc::f_to_c);
+        }
+
+        self
+    }
+}
+
+impl DailyRevision {
+    pub fn in_units(mut self, units: Units) -> DailyRevision {
+        if units == Units::Metric {
+            self.high = calc::f_to_c(self.high);
+            self.low = calc::f_to_c(self.low);
         }
-        Err(e) => Err(format!("Client couldn't connect to DB, Reason: {e}")),
+
+        self
+    }
+}
+
+//Every run that forecast the hour starting at target, oldest first
+pub fn get_hourly_evolution_db(location: &str, target: DateTime<Utc>) -> Result<Vec<HourlyRevision>, Error> {
+    let rows = connect()?
+        .query(
+            "SELECT r.id AS run_id, r.issued_at, r.provider, h.temp, h.feels_like, h.weather_code, h.precipitation_type, h.precipitation_chance, h.humidity, h.dew_point
+             FROM hourly_weather h JOIN forecast_runs r ON r.id = h.run_id WHERE h.location = $1 AND h.weather_time = $2 ORDER BY r.issued_at ASC",
+            &[&location, &zone::of(location).wall_time(target)]
+        )
+        .map_err(Error::db_query("Couldn't retrieve hourly forecast evolution from DB"))?;
+
+    rows
+        .iter()
+        .map(|row| {
+            let issued_at: DateTime<Utc> = column(row, "issued_at")?;
+
+            Ok(HourlyRevision {
+                run_id: column(row, "run_id")?,
+                issued_at,
+                provider: column(row, "provider")?,
+                lead_hours: ((target - issued_at).num_minutes() as f64 / 6.0).round() / 10.0,
+                temp: column(row, "temp")?,
+                feels_like: column(row, "feels_like")?,
+                weather_code: column(row, "weather_code")?,
+                precipitation_type: column(row, "precipitation_type")?,
+                precipitation_chance: column(row, "precipitation_chance")?,
+                humidity: column(row, "humidity")?,
+                dew_point: column(row, "dew_point")?,
+            })
+        })
+        .collect()
+}
+
+//Every run that forecast the local calendar day, oldest first
+pub fn get_daily_evolution_db(location: &str, day: NaiveDate) -> Result<Vec<DailyRevision>, Error> {
+    let zone = zone::of(location);
+    let rows = connect()?
+        .query(
+            "SELECT r.id AS run_id, r.issued_at, r.provider, d.high, d.low, d.weather_code
+             FROM daily_weather d JOIN forecast_runs r ON r.id = d.run_id WHERE d.location = $1 AND d.weather_time >= $2 AND d.weather_time < $3 ORDER BY r.issued_at ASC",
+            &[&location, &day.and_time(NaiveTime::MIN), &(day + chrono::Duration::days(1)).and_time(NaiveTime::MIN)]
+        )
+        .map_err(Error::db_query("Couldn't retrieve daily forecast evolution from DB"))?;
+
+    rows
+        .iter()
+        .map(|row| {
+            let issued_at: DateTime<Utc> = column(row, "issued_at")?;
+
+            Ok(DailyRevision {
+                run_id: column(row, "run_id")?,
+                issued_at,
+                provider: column(row, "provider")?,
+                lead_days: (day - zone.at(issued_at).date_naive()).num_days(),
+                high: column(row, "high")?,
+                low: column(row, "low")?,
+                weather_code: column(row, "weather_code")?,
+            })
+        })
+        .collect()
+}
+
+//Reads {prefix}_count, _bias, _mae and _rmse
+fn scores_from_row(row: &postgres::Row, prefix: &str) -> Scores {
+    Scores {
+        count: row.get(&*format!("{prefix}_count")),
+        bias: row.get(&*format!("{prefix}_bias")),
+        mae: row.get(&*format!("{prefix}_mae")),
+        rmse: row.get(&*format!("{prefix}_rmse")),
     }
 }
 
+//Each hourly forecast against the mean of the station's observations in its hour, for hours that are over.
+//Lead times use today's UTC offset for issued_at, so they're an hour out across a DST change. Observed hours
+//start where the forecast's do, on the half hour in zones like India's
+pub fn get_hourly_verification_db(location: &str, period: Period, lead_bucket_hours: i32, now: DateTime<FixedOffset>) -> Result<Vec<HourlyVerification>, Error> {
+    let (from, to) = period.bounds();
+    let offset_secs = now.offset().local_minus_utc() as f64;
+    let hour_start_secs = now.offset().local_minus_utc().rem_euclid(3600) as f64;
+
+    let rows = connect()?
+        .query(
+            "WITH observed AS (
+                SELECT date_trunc('hour', obs_time_local - make_interval(secs => $7)) + make_interval(secs => $7) AS hour,
+                    avg(temp)::float8 AS temp, avg(dew_point)::float8 AS dew_point, avg(humidity)::float8 AS humidity,
+                    bool_or(precip_rate > 0) OR max(precip_total) > min(precip_total) AS rained
+                FROM historical_weather WHERE location = $1 GROUP BY 1
+            ),
+            pairs AS (
+                SELECT r.provider,
+                    extract(epoch FROM h.weather_time - (r.issued_at AT TIME ZONE 'UTC' + make_interval(secs => $2)))::float8 / 3600 AS lead_hours,
+                    h.temp - o.temp AS temp_err, h.dew_point - o.dew_point AS dew_point_err, h.humidity - o.humidity AS humidity_err,
+                    h.precipitation_chance / 100 - CASE WHEN o.rained THEN 1 ELSE 0 END AS precip_err, o.rained
+                FROM hourly_weather h JOIN forecast_runs r ON r.id = h.run_id JOIN observed o ON o.hour = h.weather_time
+                WHERE h.location = $1 AND ($4::timestamp IS NULL OR h.weather_time >= $4) AND ($5::timestamp IS NULL OR h.weather_time < $5)
+                    AND h.weather_time + interval '1 hour' <= $6
+            )
+            SELECT provider, floor(lead_hours / $3::int)::int AS bucket,
+                count(temp_err) AS temp_count, avg(temp_err) AS temp_bias, avg(abs(temp_err)) AS temp_mae, sqrt(avg(temp_err ^ 2)) AS temp_rmse,
+                count(dew_point_err) AS dew_point_count, avg(dew_point_err) AS dew_point_bias, avg(abs(dew_point_err)) AS dew_point_mae, sqrt(avg(dew_point_err ^ 2)) AS dew_point_rmse,
+                count(humidity_err) AS humidity_count, avg(humidity_err) AS humidity_bias, avg(abs(humidity_err)) AS humidity_mae, sqrt(avg(humidity_err ^ 2)) AS humidity_rmse,
+                count(precip_err) AS precip_count, avg(precip_err ^ 2) AS brier, avg(CASE WHEN rained THEN 1 ELSE 0 END)::float8 AS observed_frequency
+            FROM pairs WHERE lead_hours >= 0 GROUP BY 1, 2 ORDER BY 1, 2",
+            &[&location, &offset_secs, &lead_bucket_hours, &from, &to, &now.naive_local(), &hour_start_secs]
+        )
+        .map_err(Error::db_query("Couldn't score hourly forecasts in DB"))?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| {
+                let bucket: i32 = row.get("bucket");
+
+                HourlyVerification {
+                    provider: row.get("provider"),
+                    lead_hours_from: bucket * lead_bucket_hours,
+                    lead_hours_to: (bucket + 1) * lead_bucket_hours,
+                    temp: scores_from_row(row, "temp"),
+                    dew_point: scores_from_row(row, "dew_point"),
+                    humidity: scores_from_row(row, "humidity"),
+                    precipitation: PrecipScores {
+                        count: row.get("precip_count"),
+                        brier: row.get("brier"),
+                        observed_frequency: row.get("observed_frequency"),
+                    },
+                }
+            })
+            .collect()
+    )
+}
+
+//Each daily forecast against the station's highest and lowest reading of the day, for days before today
+//with enough observations to trust them
+pub fn get_daily_verification_db(location: &str, period: Period, now: DateTime<FixedOffset>) -> Result<Vec<DailyVerification>, Error> {
+    let (from, to) = period.bounds();
+    let offset_secs = now.offset().local_minus_utc() as f64;
+
+    let rows = connect()?
+        .query(
+            "WITH observed AS (
+                SELECT date(obs_time_local) AS day, max(temp)::float8 AS high, min(temp)::float8 AS low, count(DISTINCT date_trunc('hour', obs_time_local)) AS hours
+                FROM historical_weather WHERE location = $1 GROUP BY 1
+            ),
+            pairs AS (
+                SELECT r.provider, o.day - date(r.issued_at AT TIME ZONE 'UTC' + make_interval(secs => $2)) AS lead_days,
+                    d.high - o.high AS high_err, d.low - o.low AS low_err
+                FROM daily_weather d JOIN forecast_runs r ON r.id = d.run_id JOIN observed o ON o.day = date(d.weather_time)
+                WHERE d.location = $1 AND o.hours >= $3 AND o.day < $6
+                    AND ($4::timestamp IS NULL OR d.weather_time >= $4) AND ($5::timestamp IS NULL OR d.weather_time < $5)
+            )
+            SELECT provider, lead_days,
+                count(high_err) AS high_count, avg(high_err) AS high_bias, avg(abs(high_err)) AS high_mae, sqrt(avg(high_err ^ 2)) AS high_rmse,
+                count(low_err) AS low_count, avg(low_err) AS low_bias, avg(abs(low_err)) AS low_mae, sqrt(avg(low_err ^ 2)) AS low_rmse
+            FROM pairs WHERE lead_days >= 0 GROUP BY 1, 2 ORDER BY 1, 2",
+            &[&location, &offset_secs, &MIN_DAILY_OBSERVED_HOURS, &from, &to, &now.date_naive()]
+        )
+        .map_err(Error::db_query("Couldn't score daily forecasts in DB"))?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| DailyVerification {
+                provider: row.get("provider"),
+                lead_days: row.get("lead_days"),
+                high: scores_from_row(row, "high"),
+                low: scores_from_row(row, "low"),
+            })
+            .collect()
+    )
+}
+
+//Observed minus forecast by local hour of day and lead time bucket, pairing hours the same way as
+//get_hourly_verification_db but only over the last window_days and for one provider
+pub fn get_correction_cells_db(location: &str, provider: &str, window_days: i64, lead_bucket_hours: i32, now: DateTime<FixedOffset>) -> Result<Vec<(CellKey, Cell)>, Error> {
+    let since = (now - chrono::Duration::days(window_days)).naive_local();
+    let offset_secs = now.offset().local_minus_utc() as f64;
+    let hour_start_secs = now.offset().local_minus_utc().rem_euclid(3600) as f64;
+
+    let rows = connect()?
+        .query(
+            "WITH observed AS (
+                SELECT date_trunc('hour', obs_time_local - make_interval(secs => $7)) + make_interval(secs => $7) AS hour,
+                    avg(temp)::float8 AS temp, avg(dew_point)::float8 AS dew_point
+                FROM historical_weather WHERE location = $1 AND obs_time_local >= $3 GROUP BY 1
+            ),
+            pairs AS (
+                SELECT extract(hour FROM h.weather_time)::int AS hour_of_day,
+                    extract(epoch FROM h.weather_time - (r.issued_at AT TIME ZONE 'UTC' + make_interval(secs => $4)))::float8 / 3600 AS lead_hours,
+                    o.temp - h.temp AS temp_diff, o.dew_point - h.dew_point AS dew_point_diff
+                FROM hourly_weather h JOIN forecast_runs r ON r.id = h.run_id JOIN observed o ON o.hour = h.weather_time
+                WHERE h.location = $1 AND r.provider = $2 AND h.weather_time >= $3 AND h.weather_time + interval '1 hour' <= $6
+            )
+            SELECT hour_of_day, floor(greatest(lead_hours, 0) / $5::int)::int AS bucket,
+                count(temp_diff) AS temp_samples, coalesce(avg(temp_diff), 0) AS temp,
+                count(dew_point_diff) AS dew_point_samples, coalesce(avg(dew_point_diff), 0) AS dew_point
+            FROM pairs GROUP BY 1, 2",
+            &[&location, &provider, &since, &offset_secs, &lead_bucket_hours, &now.naive_local(), &hour_start_secs]
+        )
+        .map_err(Error::db_query("Couldn't learn forecast corrections from DB"))?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| {
+                let hour: i32 = row.get("hour_of_day");
+
+                (
+                    (hour as u32, row.get("bucket")),
+                    Cell {
+                        temp: row.get("temp"),
+                        temp_samples: row.get("temp_samples"),
+                        dew_point: row.get("dew_point"),
+                        dew_point_samples: row.get("dew_point_samples"),
+                    },
+                )
+            })
+            .collect()
+    )
+}
+
+//Recomputes the last summarized day onward (the newest two days, once it's caught up), so a day that was
+//still going or got late uploads is finished off. Returns the number of days written
+pub fn rollup_daily_summary_db(location: &str) -> Result<u64, Error> {
+    connect()?
+        .execute(
+            "WITH since AS (
+                SELECT coalesce(max(day) - 1, '-infinity'::date) AS day FROM daily_summary WHERE location = $1
+            ),
+            readings AS (
+                SELECT obs_time_utc, obs_time_local, temp, humidity, wind_gust, wind_dir, pressure, uv_index, precip_total,
+                    lag(precip_total) OVER (ORDER BY obs_time_utc) AS prev_total
+                FROM historical_weather WHERE location = $1 AND obs_time_local >= (SELECT day FROM since) - 1
+            ),
+            -- precip_total counts up from the station's daily reset, so the rain is the sum of its increases,
+            -- and a drop means it reset and the new total all fell since
+            increments AS (
+                SELECT *, CASE WHEN prev_total IS NULL OR precip_total < prev_total THEN precip_total ELSE precip_total - prev_total END AS rain
+                FROM readings
+            )
+            INSERT INTO daily_summary(location, day, high_temp, high_time, low_temp, low_time, mean_temp, max_gust, max_gust_dir, max_gust_time,
+                precip_total, max_uv, mean_pressure, min_humidity, observations, observed_hours)
+            SELECT $1, date(obs_time_local),
+                max(temp), (array_agg(obs_time_utc ORDER BY temp DESC, obs_time_utc))[1],
+                min(temp), (array_agg(obs_time_utc ORDER BY temp ASC, obs_time_utc))[1],
+                avg(temp), max(wind_gust), (array_agg(wind_dir ORDER BY wind_gust DESC, obs_time_utc))[1], (array_agg(obs_time_utc ORDER BY wind_gust DESC, obs_time_utc))[1],
+                sum(rain), max(uv_index), avg(pressure) FILTER (WHERE pressure > 0), min(humidity), count(*), count(DISTINCT date_trunc('hour', obs_time_local))
+            FROM increments WHERE obs_time_local >= (SELECT day FROM since)
+            GROUP BY 2
+            ON CONFLICT (location, day) DO UPDATE SET high_temp = EXCLUDED.high_temp, high_time = EXCLUDED.high_time, low_temp = EXCLUDED.low_temp,
+                low_time = EXCLUDED.low_time, mean_temp = EXCLUDED.mean_temp, max_gust = EXCLUDED.max_gust, max_gust_dir = EXCLUDED.max_gust_dir,
+                max_gust_time = EXCLUDED.max_gust_time, precip_total = EXCLUDED.precip_total, max_uv = EXCLUDED.max_uv, mean_pressure = EXCLUDED.mean_pressure,
+                min_humidity = EXCLUDED.min_humidity, observations = EXCLUDED.observations, observed_hours = EXCLUDED.observed_hours, updated_at = now()",
+            &[&location]
+        )
+        .map_err(Error::db_query("Failed to roll up daily summaries in DB"))
+}
+
+//Inclusive local days, oldest first
+pub fn get_daily_summary_db(location: &str, from: NaiveDate, to: NaiveDate) -> Result<Vec<DailySummary>, Error> {
+    let rows = connect()?
+        .query(
+            "SELECT day, high_temp, high_time, low_temp, low_time, mean_temp, max_gust, max_gust_dir, max_gust_time, precip_total, max_uv, mean_pressure,
+                min_humidity, observations, observed_hours
+             FROM daily_summary WHERE location = $1 AND day BETWEEN $2 AND $3 ORDER BY day ASC",
+            &[&location, &from, &to]
+        )
+        .map_err(Error::db_query("Couldn't retrieve daily summaries from DB"))?;
+
+    Ok(
+        rows
+            .iter()
+            .map(|row| {
+                let observed_hours: i32 = row.get("observed_hours");
+
+                DailySummary {
+                    day: row.get("day"),
+                    high_temp: row.get("high_temp"),
+                    high_time: row.get("high_time"),
+                    low_temp: row.get("low_temp"),
+                    low_time: row.get("low_time"),
+                    mean_temp: row.get("mean_temp"),
+                    max_gust: row.get("max_gust"),
+                    max_gust_dir: row.get("max_gust_dir"),
+                    max_gust_time: row.get("max_gust_time"),
+                    precip_total: row.get("precip_total"),
+                    max_uv: row.get("max_uv"),
+                    mean_pressure: row.get("mean_pressure"),
+                    min_humidity: row.get("min_humidity"),
+                    observations: row.get("observations"),
+                    observed_hours,
+                    completeness: (observed_hours as f64 / 24.0).min(1.0),
+                }
+            })
+            .collect()
+    )
+}
+
+pub fn get_historical_db(location: &str, range: &HistoricalRange) -> Result<Vec<InstModel>, Error> {
+    let mut client = connect()?;
+
+    let rows = match range {
+        HistoricalRange::Days(day1, day2) =>
+            client.query(
+                "SELECT obs_time_utc, obs_time_local, temp, heat_index, wind_chill, dew_point, humidity, precip_rate, precip_total, wind_speed, wind_dir, wind_gust, pressure, solar_radiation, uv_index FROM historical_weather WHERE location = $1 AND date(obs_time_local) BETWEEN $2 AND $3",
+                &[&location, day1, day2]
+            ),
+        HistoricalRange::Times(time1, time2) =>
+            client.query(
+                "SELECT obs_time_utc, obs_time_local, temp, heat_index, wind_chill, dew_point, humidity, precip_rate, precip_total, wind_speed, wind_dir, wind_gust, pressure, solar_radiation, uv_index FROM historical_weather WHERE location = $1 AND obs_time_utc BETWEEN $2 AND $3",
+                &[&location, time1, time2]
+            ),
+    };
+
+    rows.map_err(Error::db_query("Couldn't retrieve historical data from DB"))?.iter().map(inst_from_row).collect()
+}
+
+//Columns are read by name, so a reordered table can't shift values into the wrong fields
+fn inst_from_row(row: &postgres::Row) -> Result<InstModel, Error> {
+    Ok(InstModel {
+        obs_time_utc: column(row, "obs_time_utc")?,
+        obs_time_local: column(row, "obs_time_local")?,
+        temp: column(row, "temp")?,
+        heat_index: column(row, "heat_index")?,
+        wind_chill: column(row, "wind_chill")?,
+        dewpt: column(row, "dew_point")?,
+        humidity: column(row, "humidity")?,
+        precip_rate: column(row, "precip_rate")?,
+        precip_total: column(row, "precip_total")?,
+        wind_speed: column(row, "wind_speed")?,
+        winddir: column(row, "wind_dir")?,
+        wind_gust: column(row, "wind_gust")?,
+        pressure: column(row, "pressure")?,
+        solar_radiation: column(row, "solar_radiation")?,
+        uv: column(row, "uv_index")?,
+    })
+}
+
 pub enum HistoricalSearchType {
     Daily,
     TimeRange,
@@ -214,117 +764,448 @@ impl FromStr for HistoricalSearchType {
     }
 }
 
-pub fn get_weekly_timestamp() -> String {
-    let time = chrono::Local::now() + chrono::Duration::days(5);
+//Inclusive bounds parsed from the day/day1/day2 query parameters
+#[derive(Debug, Clone, Copy)]
+pub enum HistoricalRange {
+    //Calendar days, local to the station for historical data
+    Days(NaiveDate, NaiveDate),
+    //Instants, from datetimes with a timezone
+    Times(DateTime<Utc>, DateTime<Utc>),
+}
 
-    time.to_rfc3339()
+enum HistoricalBound {
+    Day(NaiveDate),
+    Time(DateTime<Utc>),
 }
 
-pub fn get_hourly_timestamp() -> String {
-    let time = chrono::Local::now() + chrono::Duration::hours(24);
+//YYYY-MM-DD or RFC 3339, a + in the offset has to be sent as %2B
+fn parse_bound(name: &str, value: &str) -> Result<HistoricalBound, Error> {
+    if let Ok(day) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(HistoricalBound::Day(day));
+    }
 
-    time.to_rfc3339()
+    match DateTime::parse_from_rfc3339(value) {
+        Ok(time) => Ok(HistoricalBound::Time(time.with_timezone(&Utc))),
+        Err(_) => Err(Error::Validation(format!("{name} must be a date (2024-05-01) or a datetime with a timezone (2024-05-01T06:00:00Z), got {value:?}"))),
+    }
 }
 
-pub fn dump_daily_db(daily_data: &mut [DailyWeather]) -> Result<String, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            for interval in daily_data.iter() {
-                match
-                    client.execute(
-                        "INSERT INTO daily_weather(weather_time,high,low,weather_code,moon_phase,sunrise_time,sunset_time) VALUES ($1,$2,$3,$4,$5,$6,$7)",
-                        &[
-                            &interval.weather_time.naive_local(),
-                            &interval.high,
-                            &interval.low,
-                            &interval.weather_code,
-                            &interval.moon_phase,
-                            &interval.sunrise_time.unwrap().naive_local(),
-                            &interval.sunset_time.unwrap().naive_local(),
-                        ]
-                    )
-                {
-                    Ok(row) => {
-                        println!("Successfully dumped interval for {0} to DB, {row} rows affected", interval.weather_time);
-                    }
-                    Err(e) => {
-                        return Err(format!("Failed to dump daily data to DB, Reason: {e}"));
-                    }
-                }
-            }
-            Ok("Successfully dumped data to DB".to_string())
+impl HistoricalRange {
+    pub fn day(day: &str) -> Result<HistoricalRange, Error> {
+        match parse_bound("day", day)? {
+            HistoricalBound::Day(day) => Ok(HistoricalRange::Days(day, day)),
+            HistoricalBound::Time(_) => Err(Error::Validation("day must be a date (2024-05-01), use search_type=timerange for datetimes".to_string())),
         }
-        Err(e) => Err(format!("Client couldn't connect to DB, Reason: {e}")),
     }
-}
 
-pub fn dump_hourly_db(hourly_data: &mut [HourlyWeather]) -> Result<String, String> {
-    match Client::connect(&get_conn_str(), NoTls) {
-        Ok(mut client) => {
-            for interval in hourly_data.iter() {
-                match
-                    client.execute(
-                        "INSERT INTO hourly_weather(weather_time,temp,feels_like,weather_code,precipitation_type,precipitation_chance,humidity,dew_point) VALUES ($1,$2,$3,$4,$5,$6,$7,$8)",
-                        &[
-                            &interval.weather_time.naive_local(),
-                            &interval.temp,
-                            &interval.feels_like,
-                            &interval.weather_code,
-                            &interval.precipitation_type,
-                            &interval.precipitation_chance,
-                            &interval.humidity,
-                            &interval.dew_point,
-                        ]
-                    )
-                {
-                    Ok(row) => {
-                        println!("Successfully dumped interval for {0} to DB, {row} rows affected", interval.weather_time);
-                    }
-                    Err(e) => {
-                        return Err(format!("Failed to dump hourly data to DB, Reason: {e}"));
-                    }
-                }
+    pub fn between(day1: &str, day2: &str) -> Result<HistoricalRange, Error> {
+        let range = match (parse_bound("day1", day1)?, parse_bound("day2", day2)?) {
+            (HistoricalBound::Day(day1), HistoricalBound::Day(day2)) => HistoricalRange::Days(day1, day2),
+            (HistoricalBound::Time(time1), HistoricalBound::Time(time2)) => HistoricalRange::Times(time1, time2),
+            _ => {
+                return Err(Error::Validation("day1 and day2 must both be dates or both be datetimes".to_string()));
             }
-            Ok("Successfully dumped data to DB".to_string())
+        };
+
+        let reversed = match range {
+            HistoricalRange::Days(day1, day2) => day1 > day2,
+            HistoricalRange::Times(time1, time2) => time1 > time2,
+        };
+
+        if reversed {
+            return Err(Error::Validation(format!("day1 ({day1}) is after day2 ({day2})")));
         }
-        Err(e) => Err(format!("Client couldn't connect to DB, Reason: {e}")),
+
+        Ok(range)
     }
 }
 
-pub fn dump_inst_db(inst: wunder::models::InstModel, conn_str: &str) -> Result<String, String> {
-    match Client::connect(conn_str, NoTls) {
-        Ok(mut client) => {
-            match
-                client.execute(
-                    "INSERT INTO historical_weather(obs_time_utc,obs_time_local,temp,heat_index,wind_chill,dew_point,humidity,precip_rate,precip_total,wind_speed,wind_dir,wind_gust,pressure,solar_radiation,uv_index) VALUES($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15)",
-                    &[
-                        &inst.obs_time_utc,
-                        &inst.obs_time_local,
-                        &inst.temp,
-                        &inst.heat_index,
-                        &inst.wind_chill,
-                        &inst.dewpt,
-                        &inst.humidity,
-                        &inst.precip_rate,
-                        &inst.precip_total,
-                        &inst.wind_speed,
-                        &inst.winddir,
-                        &inst.wind_gust,
-                        &inst.pressure,
-                        &inst.solar_radiation,
-                        &inst.uv,
-                    ]
-                )
-            {
-                Ok(row) => {
-                    println!("Successfully dumped interval for {0} to DB, {row} rows affected", &inst.obs_time_utc);
-                    Ok("Successfully dumped inst data to historical table".to_string())
-                }
-                Err(e) => {
-                    return Err(format!("Failed to dump inst data to historical table, Reason: {e}"));
-                }
-            }
+pub fn get_weekly_timestamp(days: i64) -> String {
+    let time = chrono::Local::now() + chrono::Duration::days(days);
+
+    time.to_rfc3339()
+}
+
+pub fn get_hourly_timestamp(hours: i64) -> String {
+    let time = chrono::Local::now() + chrono::Duration::hours(hours);
+
+    time.to_rfc3339()
+}
+
+//Opens the run a pull's rows are stored under, inside the transaction that stores them so readers never
+//see a run without its rows
+fn begin_run(transaction: &mut postgres::Transaction, location: &str, dataset: &str, provider: &str) -> Result<i32, Error> {
+    let row = transaction
+        .query_one("INSERT INTO forecast_runs(location, dataset, provider) VALUES ($1, $2, $3) RETURNING id", &[&location, &dataset, &provider])
+        .map_err(Error::db_query("Failed to start forecast run in DB"))?;
+
+    Ok(row.get("id"))
+}
+
+//A repeated weather_time within the run keeps the last interval
+pub fn dump_daily_db(location: &str, provider: &str, daily_data: &[DailyWeather]) -> Result<(), Error> {
+    let zone = zone::of(location);
+    let mut client = connect()?;
+    let mut transaction = client.transaction().map_err(Error::db_query("Failed to start daily data transaction"))?;
+    let run_id = begin_run(&mut transaction, location, "daily", provider)?;
+
+    for interval in daily_data.iter() {
+        transaction
+            .execute(
+                "INSERT INTO daily_weather(weather_time,high,low,weather_code,moon_phase,sunrise_time,sunset_time,location,run_id,raw_weather_code) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)
+                 ON CONFLICT (run_id, weather_time) DO UPDATE SET high = EXCLUDED.high, low = EXCLUDED.low, weather_code = EXCLUDED.weather_code,
+                 moon_phase = EXCLUDED.moon_phase, sunrise_time = EXCLUDED.sunrise_time, sunset_time = EXCLUDED.sunset_time, raw_weather_code = EXCLUDED.raw_weather_code",
+                &[
+                    &zone.wall_time(interval.weather_time),
+                    &interval.high,
+                    &interval.low,
+                    &interval.weather_code,
+                    &interval.moon_phase,
+                    &interval.sunrise_time.map(|t| zone.wall_time(t)),
+                    &interval.sunset_time.map(|t| zone.wall_time(t)),
+                    &location,
+                    &run_id,
+                    &interval.raw_weather_code,
+                ]
+            )
+            .map_err(Error::db_query("Failed to dump daily data to DB"))?;
+    }
+
+    transaction.commit().map_err(Error::db_query("Failed to commit daily data to DB"))?;
+
+    println!("Successfully dumped {0} daily intervals to DB as run {run_id}", daily_data.len());
+    Ok(())
+}
+
+//A repeated weather_time within the run keeps the last interval
+pub fn dump_hourly_db(location: &str, provider: &str, hourly_data: &[HourlyWeather]) -> Result<(), Error> {
+    let zone = zone::of(location);
+    let mut client = connect()?;
+    let mut transaction = client.transaction().map_err(Error::db_query("Failed to start hourly data transaction"))?;
+    let run_id = begin_run(&mut transaction, location, "hourly", provider)?;
+
+    for interval in hourly_data.iter() {
+        transaction
+            .execute(
+                "INSERT INTO hourly_weather(weather_time,temp,feels_like,weather_code,precipitation_type,precipitation_chance,humidity,dew_point,location,run_id,raw_weather_code) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+                 ON CONFLICT (run_id, weather_time) DO UPDATE SET temp = EXCLUDED.temp, feels_like = EXCLUDED.feels_like, weather_code = EXCLUDED.weather_code,
+                 precipitation_type = EXCLUDED.precipitation_type, precipitation_chance = EXCLUDED.precipitation_chance, humidity = EXCLUDED.humidity, dew_point = EXCLUDED.dew_point,
+                 raw_weather_code = EXCLUDED.raw_weather_code",
+                &[
+                    &zone.wall_time(interval.weather_time),
+                    &interval.temp,
+                    &interval.feels_like,
+                    &interval.weather_code,
+                    &interval.precipitation_type,
+                    &interval.precipitation_chance,
+                    &interval.humidity,
+                    &interval.dew_point,
+                    &location,
+                    &run_id,
+                    &interval.raw_weather_code,
+                ]
+            )
+            .map_err(Error::db_query("Failed to dump hourly data to DB"))?;
+    }
+
+    transaction.commit().map_err(Error::db_query("Failed to commit hourly data to DB"))?;
+
+    println!("Successfully dumped {0} hourly intervals to DB as run {run_id}", hourly_data.len());
+    Ok(())
+}
+
+//One row per location and obs_time_utc, a station that hasn't updated since the last dump overwrites
+//its own reading instead of adding a duplicate
+pub fn dump_inst_db(location: &str, inst: &wunder::models::InstModel) -> Result<(), Error> {
+    let row = connect()?
+        .execute(
+            "INSERT INTO historical_weather(obs_time_utc,obs_time_local,temp,heat_index,wind_chill,dew_point,humidity,precip_rate,precip_total,wind_speed,wind_dir,wind_gust,pressure,solar_radiation,uv_index,location) VALUES($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16)
+             ON CONFLICT (location, obs_time_utc) DO UPDATE SET obs_time_local = EXCLUDED.obs_time_local, temp = EXCLUDED.temp, heat_index = EXCLUDED.heat_index,
+             wind_chill = EXCLUDED.wind_chill, dew_point = EXCLUDED.dew_point, humidity = EXCLUDED.humidity, precip_rate = EXCLUDED.precip_rate,
+             precip_total = EXCLUDED.precip_total, wind_speed = EXCLUDED.wind_speed, wind_dir = EXCLUDED.wind_dir, wind_gust = EXCLUDED.wind_gust,
+             pressure = EXCLUDED.pressure, solar_radiation = EXCLUDED.solar_radiation, uv_index = EXCLUDED.uv_index",
+            &[
+                &inst.obs_time_utc,
+                &inst.obs_time_local,
+                &inst.temp,
+                &inst.heat_index,
+                &inst.wind_chill,
+                &inst.dewpt,
+                &inst.humidity,
+                &inst.precip_rate,
+                &inst.precip_total,
+                &inst.wind_speed,
+                &inst.winddir,
+                &inst.wind_gust,
+                &inst.pressure,
+                &inst.solar_radiation,
+                &inst.uv,
+                &location,
+            ]
+        )
+        .map_err(Error::db_query("Failed to dump inst data to historical table"))?;
+
+    println!("Successfully dumped interval for {0} to DB, {row} rows affected", &inst.obs_time_utc);
+    Ok(())
+}
+
+pub fn dump_strike_db(location: &str, strike: &LightningStrike) -> Result<(), Error> {
+    let row = connect()?
+        .execute(
+            "INSERT INTO lightning_strikes(strike_time,distance_km,energy,location) VALUES ($1,$2,$3,$4)",
+            &[&strike.strike_time, &strike.distance_km, &strike.energy, &location]
+        )
+        .map_err(Error::db_query("Failed to dump lightning strike to DB"))?;
+
+    println!("Successfully dumped lightning strike at {0} to DB, {row} rows affected", strike.strike_time);
+    Ok(())
+}
+
+pub fn dump_precip_event_db(location: &str, event: &PrecipEvent) -> Result<(), Error> {
+    let row = connect()?
+        .execute("INSERT INTO precip_events(event_time,location) VALUES ($1,$2)", &[&event.event_time, &location])
+        .map_err(Error::db_query("Failed to dump rain start event to DB"))?;
+
+    println!("Successfully dumped rain start at {0} to DB, {row} rows affected", event.event_time);
+    Ok(())
+}
+
+//Events only keep their instant, so a day is turned into the instants of the local midnights around it.
+//Matching date(strike_time) would use whatever timezone the DB session has
+fn local_day_bounds(zone: Zone, day1: NaiveDate, day2: NaiveDate) -> Result<(DateTime<Utc>, DateTime<Utc>), Error> {
+    let midnight = |day: NaiveDate| zone.instant(day.and_time(NaiveTime::MIN)).map_err(|_| Error::Validation(format!("{day} has no local midnight")));
+
+    Ok((midnight(day1)?, midnight(day2 + chrono::Duration::days(1))?))
+}
+
+//Dates match on the local calendar day, datetimes on the exact instant
+pub fn get_events_db(location: &str, range: &HistoricalRange) -> Result<Events, Error> {
+    let mut client = connect()?;
+
+    let strike_rows = match *range {
+        HistoricalRange::Days(day1, day2) => {
+            let (start, end) = local_day_bounds(zone::of(location), day1, day2)?;
+            client.query(
+                "SELECT strike_time, distance_km, energy FROM lightning_strikes WHERE location = $1 AND strike_time >= $2 AND strike_time < $3 ORDER BY strike_time ASC",
+                &[&location, &start, &end]
+            )
+        }
+        HistoricalRange::Times(time1, time2) =>
+            client.query(
+                "SELECT strike_time, distance_km, energy FROM lightning_strikes WHERE location = $1 AND strike_time BETWEEN $2 AND $3 ORDER BY strike_time ASC",
+                &[&location, &time1, &time2]
+            ),
+    };
+
+    let strikes = strike_rows
+        .map_err(Error::db_query("Couldn't retrieve lightning strikes from DB"))?
+        .iter()
+        .map(|row| LightningStrike {
+            strike_time: row.get("strike_time"),
+            distance_km: row.get("distance_km"),
+            energy: row.get("energy"),
+        })
+        .collect();
+
+    let precip_rows = match *range {
+        HistoricalRange::Days(day1, day2) => {
+            let (start, end) = local_day_bounds(zone::of(location), day1, day2)?;
+            client.query(
+                "SELECT event_time FROM precip_events WHERE location = $1 AND event_time >= $2 AND event_time < $3 ORDER BY event_time ASC",
+                &[&location, &start, &end]
+            )
         }
-        Err(e) => Err(format!("Client couldn't connect to DB, Reason: {e}")),
+        HistoricalRange::Times(time1, time2) =>
+            client.query(
+                "SELECT event_time FROM precip_events WHERE location = $1 AND event_time BETWEEN $2 AND $3 ORDER BY event_time ASC",
+                &[&location, &time1, &time2]
+            ),
+    };
+
+    let precip_events = precip_rows
+        .map_err(Error::db_query("Couldn't retrieve rain start events from DB"))?
+        .iter()
+        .map(|row| PrecipEvent { event_time: row.get("event_time") })
+        .collect();
+
+    Ok(Events { lightning_strikes: strikes, precip_events })
+}
+
+//(provider, period, requests) for the given hour and day
+pub fn get_api_usage_db(hour_start: DateTime<Utc>, day_start: DateTime<Utc>) -> Result<Vec<(String, String, i32)>, Error> {
+    let rows = connect()?
+        .query(
+            "SELECT provider, period, requests FROM api_usage WHERE (period = 'hour' AND period_start = $1) OR (period = 'day' AND period_start = $2)",
+            &[&hour_start, &day_start]
+        )
+        .map_err(Error::db_query("Couldn't retrieve API usage from DB"))?;
+
+    Ok(rows.iter().map(|row| (row.get("provider"), row.get("period"), row.get("requests"))).collect())
+}
+
+pub fn bump_api_usage_db(provider: &str, hour_start: DateTime<Utc>, day_start: DateTime<Utc>) -> Result<(), Error> {
+    connect()?
+        .execute(
+            "INSERT INTO api_usage(provider, period, period_start, requests) VALUES ($1, 'hour', $2, 1), ($1, 'day', $3, 1)
+             ON CONFLICT (provider, period, period_start) DO UPDATE SET requests = api_usage.requests + 1",
+            &[&provider, &hour_start, &day_start]
+        )
+        .map_err(Error::db_query("Failed to count API request in DB"))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{ Local, TimeZone };
+
+    #[test]
+    fn event_days_are_local_days() {
+        let day1 = NaiveDate::from_ymd_opt(2024, 6, 12).unwrap();
+        let day2 = NaiveDate::from_ymd_opt(2024, 6, 13).unwrap();
+
+        let (start, end) = local_day_bounds(Zone::Host, day1, day2).unwrap();
+
+        assert_eq!(start.with_timezone(&Local).naive_local(), day1.and_time(NaiveTime::MIN));
+        assert_eq!(end.with_timezone(&Local).naive_local(), NaiveDate::from_ymd_opt(2024, 6, 14).unwrap().and_time(NaiveTime::MIN));
+        assert_eq!(end - start, chrono::Duration::days(2));
+
+        let (start, end) = local_day_bounds(Zone::parse("Asia/Kolkata").unwrap(), day1, day2).unwrap();
+        assert_eq!(start, Utc.with_ymd_and_hms(2024, 6, 11, 18, 30, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2024, 6, 13, 18, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn unknown_stored_enums_fail_to_read() {
+        use crate::climacell::MoonPhase;
+        use postgres::types::{ FromSql, Type };
+
+        assert!(WeatherCode::from_sql(&Type::VARCHAR, b"Bogus").is_err());
+        assert!(MoonPhase::from_sql(&Type::VARCHAR, b"Bogus").is_err());
+        assert!(PrecipitationType::from_sql(&Type::VARCHAR, b"Bogus").is_err());
+        assert_eq!(WeatherCode::from_sql(&Type::VARCHAR, b"Clear").unwrap(), WeatherCode::Clear);
+    }
+
+    //Needs a server, e.g. RUSTYFORECAST_TEST_DB="host=127.0.0.1 user=postgres password=x", skipped without one
+    #[test]
+    fn unknown_stored_enums_are_errors_not_panics() {
+        let Ok(conn_str) = std::env::var("RUSTYFORECAST_TEST_DB") else {
+            println!("RUSTYFORECAST_TEST_DB isn't set, skipping");
+            return;
+        };
+
+        let mut client = postgres::Client::connect(&conn_str, postgres::NoTls).unwrap();
+        let row = client
+            .query_one("SELECT 'Bogus'::varchar AS weather_code, 'Clear'::varchar AS known, NULL::varchar AS moon_phase", &[])
+            .unwrap();
+
+        assert!(matches!(column::<WeatherCode>(&row, "weather_code"), Err(Error::DbQuery { .. })));
+        assert!(column::<crate::climacell::MoonPhase>(&row, "moon_phase").is_err());
+        assert_eq!(column::<WeatherCode>(&row, "known").unwrap(), WeatherCode::Clear);
+    }
+
+    #[test]
+    fn forecast_times_are_stored_as_local_wall_time() {
+        let zone = Zone::parse("America/Chicago").unwrap();
+        let time = Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap();
+        let stored = zone.wall_time(time);
+
+        assert_eq!(stored, NaiveDate::from_ymd_opt(2024, 4, 12).unwrap().and_hms_opt(9, 0, 0).unwrap());
+        assert_eq!(zone.instant(stored).unwrap(), time);
+        assert_eq!(Zone::Host.instant(Zone::Host.wall_time(time)).unwrap(), time);
+    }
+
+    #[test]
+    fn forecasts_read_back_as_stored() {
+        if !test_db() {
+            return;
+        }
+
+        let location = test_location("round-trip");
+        let hour = Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap();
+        let hourly = HourlyWeather { weather_time: hour, temp: 68.0, weather_code: WeatherCode::Clear, ..HourlyWeather::default() };
+        let daily = DailyWeather {
+            weather_time: Utc.with_ymd_and_hms(2024, 4, 12, 10, 0, 0).unwrap(),
+            sunrise_time: Some(Utc.with_ymd_and_hms(2024, 4, 12, 10, 52, 0).unwrap()),
+            sunset_time: Some(Utc.with_ymd_and_hms(2024, 4, 12, 23, 41, 0).unwrap()),
+            ..DailyWeather::default()
+        };
+
+        dump_hourly_db(&location, "test", &[hourly]).unwrap();
+        dump_daily_db(&location, "test", std::slice::from_ref(&daily)).unwrap();
+
+        let stored: chrono::NaiveDateTime = connect().unwrap()
+            .query_one("SELECT weather_time FROM hourly_weather WHERE location = $1", &[&location])
+            .unwrap()
+            .get(0);
+        assert_eq!(stored, hour.with_timezone(&Local).naive_local());
+
+        assert_eq!(get_hourly_db(&location).unwrap()[0].weather_time, hour);
+
+        let read = &get_daily_db(&location).unwrap()[0];
+        assert_eq!(read.weather_time, daily.weather_time);
+        assert_eq!(read.sunrise_time, daily.sunrise_time);
+        assert_eq!(read.sunset_time, daily.sunset_time);
+    }
+
+    #[test]
+    fn evolution_finds_every_run_for_the_hour_and_local_day() {
+        if !test_db() {
+            return;
+        }
+
+        let location = test_location("evolution");
+        let hour = Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap();
+        let day = Utc.with_ymd_and_hms(2024, 4, 12, 10, 0, 0).unwrap();
+
+        for temp in [66.0, 68.0] {
+            let hourly = HourlyWeather { weather_time: hour, temp, ..HourlyWeather::default() };
+            let other = HourlyWeather { weather_time: hour + chrono::Duration::hours(1), temp: 0.0, ..HourlyWeather::default() };
+            dump_hourly_db(&location, "test", &[hourly, other]).unwrap();
+            dump_daily_db(&location, "test", &[DailyWeather { weather_time: day, high: temp, ..DailyWeather::default() }]).unwrap();
+        }
+
+        let hourly = get_hourly_evolution_db(&location, hour).unwrap();
+        assert_eq!(hourly.iter().map(|r| r.temp).collect::<Vec<f64>>(), vec![66.0, 68.0]);
+
+        let daily = get_daily_evolution_db(&location, day.with_timezone(&Local).date_naive()).unwrap();
+        assert_eq!(daily.iter().map(|r| r.high).collect::<Vec<f64>>(), vec![66.0, 68.0]);
+        assert!(get_daily_evolution_db(&location, day.with_timezone(&Local).date_naive().succ_opt().unwrap()).unwrap().is_empty());
+    }
+
+    const CERT_A: &str = "-----BEGIN CERTIFICATE-----\nQUFBQQ==\n-----END CERTIFICATE-----";
+    const CERT_B: &str = "-----BEGIN CERTIFICATE-----\nQkJCQg==\n-----END CERTIFICATE-----";
+
+    #[test]
+    fn bundles_split_into_their_certificates() {
+        let bundle = format!("{CERT_A}\n{CERT_B}\n");
+
+        assert_eq!(pem_certificates(bundle.as_bytes()), vec![CERT_A, CERT_B]);
+    }
+
+    #[test]
+    fn crlf_bundles_split_the_same() {
+        let bundle = format!("{CERT_A}\n{CERT_B}\n").replace('\n', "\r\n");
+        let certs = pem_certificates(bundle.as_bytes());
+
+        assert_eq!(certs.len(), 2);
+        assert_eq!(certs[0], CERT_A.replace('\n', "\r\n"));
+        assert_eq!(certs[1], CERT_B.replace('\n', "\r\n"));
+    }
+
+    #[test]
+    fn text_around_certificates_is_dropped() {
+        let bundle = format!("# Issuer: Example Root\n{CERT_A}\n\n# Issuer: Another Root\nsubject=/CN=b\n{CERT_B}\ntrailing notes\n");
+
+        assert_eq!(pem_certificates(bundle.as_bytes()), vec![CERT_A, CERT_B]);
+    }
+
+    #[test]
+    fn empty_and_truncated_files_have_no_certificates() {
+        assert!(pem_certificates(b"").is_empty());
+        assert!(pem_certificates(b"not a certificate\n").is_empty());
+        assert!(pem_certificates(b"-----BEGIN CERTIFICATE-----\nQUFBQQ==\n").is_empty());
+        assert_eq!(pem_certificates(format!("{CERT_A}\n-----BEGIN CERTIFICATE-----\nQkJC").as_bytes()), vec![CERT_A]);
     }
 }
@@ -0,0 +1,143 @@
+use crate::{ calc, error::Error, forecast_db, mqtt::MqttPublisher, wunder::models::InstModel, zone::Zone };
+use chrono::{ DateTime, Utc };
+use arc_swap::ArcSwap;
+use std::sync::{ Arc, Mutex };
+
+//Shared landing spot for observations, whether pulled from the cloud or pushed by a local station.
+//Local stations report every few seconds, so DB writes are throttled to one per persist interval
+pub struct InstSink {
+    location: String,
+    cache: ArcSwap<InstModel>,
+    persist_interval: chrono::Duration,
+    last_persisted: Mutex<DateTime<Utc>>,
+    publisher: Option<MqttPublisher>,
+}
+
+impl InstSink {
+    pub fn new(location: String, persist_minutes: i64, publisher: Option<MqttPublisher>) -> InstSink {
+        InstSink {
+            location,
+            cache: ArcSwap::from_pointee(InstModel::default()),
+            persist_interval: chrono::Duration::minutes(persist_minutes),
+            last_persisted: Mutex::new(DateTime::<Utc>::MIN_UTC),
+            publisher,
+        }
+    }
+
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+
+    pub fn cached(&self) -> InstModel {
+        InstModel::clone(&self.cache.load())
+    }
+
+    //Touch-ups between full observations that aren't worth a DB row.
+    //Reruns update on a fresh copy if another observation was swapped in meanwhile
+    pub fn update_cached(&self, update: impl Fn(&mut InstModel)) {
+        self.cache.rcu(|current| {
+            let mut inst = InstModel::clone(current);
+            update(&mut inst);
+            inst
+        });
+    }
+
+    //Err only when a due DB write failed, the observation is cached and published regardless unless it's older than the cached one
+    pub fn accept(&self, inst: InstModel) -> Result<(), Error> {
+        //Held through the write, so two observations arriving together can't both find the row due
+        let mut last_persisted = self.last_persisted.lock().unwrap();
+
+        let result = if inst.obs_time_utc - *last_persisted >= self.persist_interval {
+            let dumped = forecast_db::dump_inst_db(&self.location, &inst);
+
+            if dumped.is_ok() {
+                *last_persisted = inst.obs_time_utc;
+            }
+
+            dumped
+        } else {
+            //Not yet due for the DB
+            Ok(())
+        };
+        drop(last_persisted);
+
+        //Uploads can arrive out of order, a late one mustn't replace a newer observation
+        let inst = Arc::new(inst);
+        let previous = self.cache.rcu(|current| if inst.obs_time_utc >= current.obs_time_utc { Arc::clone(&inst) } else { Arc::clone(current) });
+
+        if inst.obs_time_utc >= previous.obs_time_utc {
+            if let Some(publisher) = &self.publisher {
+                publisher.publish_inst(&inst);
+            }
+        }
+
+        result
+    }
+}
+
+//Raw imperial readings from a local station, heat index / wind chill / dew point are derived
+pub struct Reading {
+    pub obs_time_utc: chrono::DateTime<Utc>,
+    pub temp: f64,
+    pub humidity: f64,
+    pub dewpt: Option<f64>,
+    pub wind_speed: f64,
+    pub wind_gust: f64,
+    pub winddir: f64,
+    pub pressure: f64,
+    pub precip_rate: f64,
+    pub precip_total: f64,
+    pub solar_radiation: f64,
+    pub uv: f64,
+}
+
+impl Reading {
+    //obs_time_local is on the location's clock, the one its stored forecasts are in
+    pub fn into_inst(self, zone: Zone) -> InstModel {
+        let dewpt = self.dewpt.unwrap_or_else(|| calc::dew_point(self.temp, self.humidity));
+
+        InstModel {
+            obs_time_utc: self.obs_time_utc,
+            obs_time_local: zone.wall_time(self.obs_time_utc),
+            temp: self.temp.round() as i32,
+            heat_index: calc::heat_index(self.temp, self.humidity).round() as i32,
+            wind_chill: calc::wind_chill(self.temp, self.wind_speed).round() as i32,
+            dewpt: dewpt.round() as i32,
+            humidity: self.humidity.round() as i32,
+            precip_rate: self.precip_rate,
+            precip_total: self.precip_total,
+            wind_speed: self.wind_speed.round() as i32,
+            winddir: self.winddir.round() as i32,
+            wind_gust: self.wind_gust.round() as i32,
+            pressure: self.pressure,
+            solar_radiation: self.solar_radiation,
+            uv: self.uv,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn observation(hour: u32, temp: i32) -> InstModel {
+        InstModel {
+            obs_time_utc: Utc.with_ymd_and_hms(2024, 4, 12, hour, 0, 0).unwrap(),
+            temp,
+            ..InstModel::default()
+        }
+    }
+
+    #[test]
+    fn late_observations_dont_replace_newer_ones() {
+        let sink = InstSink::new("default".to_string(), 5, None);
+        //No DB in the tests, only the cache is checked
+        let _ = sink.accept(observation(14, 72));
+        let _ = sink.accept(observation(13, 60));
+        assert_eq!(sink.cached().temp, 72);
+
+        let _ = sink.accept(observation(15, 75));
+        assert_eq!(sink.cached().temp, 75);
+    }
+}
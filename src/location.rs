@@ -0,0 +1,73 @@
+use crate::{ climacell::models::{ DailyWeather, HourlyWeather }, config::{ self, LocationConfig }, correction::Corrections, error::Error, ingest::InstSink, mqtt::MqttPublisher };
+use crate::{ refresh::RefreshRequests, validate::ValidationLog };
+use arc_swap::ArcSwap;
+use std::sync::Arc;
+
+//One configured location, with its own caches filled by its own cache loop.
+//The caches are snapshots: the loop builds a new Vec off to the side and swaps it in,
+//routes load whichever one is current and never wait on a pull
+pub struct Location {
+    pub name: String,
+    pub station_id: Option<String>,
+    pub hourly: ArcSwap<Vec<HourlyWeather>>,
+    pub daily: ArcSwap<Vec<DailyWeather>>,
+    //Relearned after each hourly pull, applied to the hourly snapshot on /forecast/hourly/corrected
+    pub corrections: ArcSwap<Corrections>,
+    pub sink: Arc<InstSink>,
+    //Whether the cache loop pulls instant data, otherwise it only comes from a local station
+    pub pulls_instant: bool,
+    pub refresh: RefreshRequests,
+    pub validation: ValidationLog,
+}
+
+impl Location {
+    pub fn new(config: &LocationConfig, persist_minutes: i64, publisher: Option<MqttPublisher>) -> Location {
+        Location {
+            name: config.name.clone(),
+            station_id: config.station_id.clone(),
+            hourly: ArcSwap::from_pointee(vec![]),
+            daily: ArcSwap::from_pointee(vec![]),
+            corrections: ArcSwap::from_pointee(Corrections::default()),
+            sink: Arc::new(InstSink::new(config.name.clone(), persist_minutes, publisher)),
+            pulls_instant: config::get().observation_provider_name(config) != "none",
+            refresh: RefreshRequests::new(),
+            validation: ValidationLog::new(),
+        }
+    }
+}
+
+//In config order, the first one is what the unscoped /forecast/hourly etc. routes serve
+pub struct Locations {
+    list: Vec<Arc<Location>>,
+}
+
+impl Locations {
+    pub fn new(list: Vec<Arc<Location>>) -> Locations {
+        Locations { list }
+    }
+
+    pub fn primary(&self) -> &Arc<Location> {
+        &self.list[0]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Location>> {
+        self.list.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<Location>> {
+        self.list.iter().find(|location| location.name == name)
+    }
+
+    //Wunderground-protocol uploads carry the station ID, which picks the location.
+    //Without any station IDs configured everything goes to the primary location
+    pub fn for_station(&self, station_id: Option<&str>) -> Result<&Arc<Location>, Error> {
+        if self.list.iter().all(|location| location.station_id.is_none()) {
+            return Ok(self.primary());
+        }
+
+        self.list
+            .iter()
+            .find(|location| location.station_id.is_some() && location.station_id.as_deref() == station_id)
+            .ok_or_else(|| Error::Unauthorized("ID doesn't match the configured station".to_string()))
+    }
+}
@@ -1,241 +1,431 @@
-use chrono::{ DateTime, Utc };
-use climacell::models::{ HourlyWeather, DailyWeather };
-use nickel::{ HttpRouter, Nickel };
-use std::sync::{ Arc, Mutex };
+//Nickel's MiddlewareResult carries a large NickelError, nothing to be done about it here
+#![allow(clippy::result_large_err)]
+
+use chrono::Utc;
+use config::RefreshConfig;
+use correction::Corrections;
+use location::{ Location, Locations };
+use mqtt::MqttPublisher;
+use nickel::{ HttpRouter, Nickel, Options };
+use provider::{ ForecastProvider, ObservationProvider };
+use refresh::RefreshOutcome;
+use scheduler::{ Dataset, Scheduler };
+use std::panic::{ self, AssertUnwindSafe };
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
 
 extern crate chrono;
 #[macro_use]
 extern crate nickel;
 
+mod calc;
 mod climacell;
+mod config;
+mod correction;
+mod error;
 mod forecast_db;
+mod ingest;
+mod location;
+mod metrics;
+mod migrate;
+mod mqtt;
+mod nws;
+mod open_meteo;
+mod provider;
+mod pws;
+mod quota;
+mod refresh;
+mod scheduler;
+mod summary;
+mod tempest;
+mod validate;
+mod verification;
 mod web;
 mod wunder;
+mod zone;
 
 fn main() {
-    //Wakeup
-    let hourlies: Arc<Mutex<Vec<HourlyWeather>>> = Arc::new(Mutex::new(vec![]));
-    let dailies: Arc<Mutex<Vec<DailyWeather>>> = Arc::new(Mutex::new(vec![]));
-    let inst: Arc<Mutex<wunder::models::Root>> = Arc::new(Mutex::new(wunder::models::Root::default()));
-
-    //Start Cache refresh Loop
-    let thandle_hourly = Arc::clone(&hourlies);
-    let thandle_daily = Arc::clone(&dailies);
-    let thandle_inst = Arc::clone(&inst);
-
-    //WeatherCacheLoop
-    let _handle = thread::spawn(move || {
-        //Timestamps
-        let mut _hourly_timestamp = DateTime::<Utc>::MIN_UTC;
-        let mut _daily_timestamp = DateTime::<Utc>::MIN_UTC;
-        let mut _last_instantpull = DateTime::<Utc>::MIN_UTC;
-
-        let conn_str = forecast_db::get_conn_str();
-
-        //Poke DB timestamps
-        match forecast_db::poke_db_timestamps() {
-            Ok(var) => {
-                (_hourly_timestamp, _daily_timestamp) = var;
-            }
+    //`rusty_forecast migrate` applies pending schema migrations and exits, no arguments runs the service
+    let migrate_only = match std::env::args().nth(1).as_deref() {
+        None => false,
+        Some("migrate") => true,
+        Some(other) => {
+            println!("Unknown command {other}, usage: rusty_forecast [migrate]");
+            std::process::exit(2);
+        }
+    };
+
+    //Settings, everything below reads from here
+    let config = match config::init() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Invalid configuration:\n{e}");
+            std::process::exit(1);
+        }
+    };
+
+    zone::init(config);
+
+    //Shared DB connections, everything below queries through them
+    if let Err(e) = forecast_db::init_pool(&config.database) {
+        println!("Couldn't set up the DB connection, Reason: {e}");
+        std::process::exit(1);
+    }
+
+    //Tables, before anything reads or writes them. A DB that's down at startup doesn't stop the service,
+    //the caches still fill from the providers and the migrations run on the next start
+    if migrate_only || config.database.migrate_on_startup {
+        match migrate::run() {
+            Ok(_) if migrate_only => std::process::exit(0),
+            Ok(_) => {}
             Err(e) => {
-                println!("Couldn't get database recent timestamps, Reason: {e}");
+                metrics::report("Couldn't migrate the DB schema", &e);
+
+                if migrate_only || !matches!(e, error::Error::DbConnect(_)) {
+                    std::process::exit(1);
+                }
             }
         }
+    }
 
-        loop {
-            if Utc::now() - _last_instantpull > chrono::Duration::minutes(30) {
-                //Pull Inst Web
-                println!("Pulling Instant Web");
-                let mut instant_t = thandle_inst.lock().unwrap();
-                match wunder::get_inst_web(&mut instant_t) {
-                    Ok(_) => {
-                        println!("Pulled Inst data from Web");
-                        _last_instantpull = Utc::now();
+    //Provider request counts, before any cache loop starts pulling
+    quota::init(config);
 
-                        match forecast_db::dump_inst_db(wunder::models::InstModel::convert(&mut instant_t), &conn_str) {
-                            Ok(_) => {
-                                println!("Successfully persisted Inst data to DB");
-                            }
-                            Err(_) => {
-                                println!("Failed to persist Inst data to DB");
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        println!("Failed to pull Inst data from wunderground");
-                    }
-                }
+    //MQTT bridge, if configured, with one publisher per location
+    let (mqtt_publishers, mqtt_subscriber) = match mqtt::connect(config) {
+        Some((publishers, subscriber)) => (publishers.into_iter().map(Some).collect(), Some(subscriber)),
+        None => (vec![None; config.locations.len()], None),
+    };
+
+    //Wakeup, a cache loop per location
+    let mut location_list = vec![];
 
-                drop(instant_t);
-            } else {
-                println!("Instant data still fresh");
+    for (location_config, publisher) in config.locations.iter().zip(mqtt_publishers) {
+        //Providers
+        let forecast_provider = match provider::forecast_provider(config, location_config) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("Couldn't set up forecast provider for {}, Reason: {e}", location_config.name);
+                std::process::exit(1);
             }
+        };
 
-            if Utc::now() - _hourly_timestamp > chrono::Duration::hours(8) {
-                //Pull Hourly Web
-                println!("Hourly data expired (8h), pulling from web");
+        let observation_provider = match provider::observation_provider(config, location_config) {
+            Ok(p) => p,
+            Err(e) => {
+                println!("Couldn't set up observation provider for {}, Reason: {e}", location_config.name);
+                std::process::exit(1);
+            }
+        };
 
-                let mut hourly_t = thandle_hourly.lock().unwrap();
+        //Observations from the pull loop and local station uploads both land in the location's sink
+        let location = Arc::new(
+            Location::new(location_config, config.refresh.inst_persist_minutes, publisher.clone())
+        );
 
-                //Hit Web API
-                match web::get_hourly_web(&mut hourly_t) {
-                    Ok(_) => {
-                        println!("Pulled hourly data from Web");
-                        //Refresh Timestamp
-                        _hourly_timestamp = Utc::now();
+        let thandle_location = Arc::clone(&location);
+        let _handle = thread::spawn(move || {
+            cache_loop(&thandle_location, forecast_provider, observation_provider, publisher, &config.refresh)
+        });
 
-                        //Persist Hourly Data in DB
-                        println!("Persisting new hourly data to DB");
-                        match forecast_db::dump_hourly_db(&mut hourly_t) {
-                            Ok(_) => {
-                                println!("Successfully saved hourly data to DB");
-                            }
-                            Err(_) => {
-                                println!("Failed to save hourly data to DB");
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        println!("Failed to pull hourly data from Web");
-                    }
-                }
+        location_list.push(location);
+    }
 
-                //Unlock reference to hourly data
-                drop(hourly_t);
-            } else {
-                let mut hourly_t = thandle_hourly.lock().unwrap();
+    let locations = Arc::new(Locations::new(location_list));
 
-                if hourly_t.len() == 0 {
-                    println!("Hourly cache empty, pulling from DB");
+    //MQTT connection and station readings
+    if let Some(subscriber) = mqtt_subscriber {
+        let mqtt_sink = Arc::clone(&station_location(config.mqtt.location.as_deref(), &locations).sink);
+        thread::spawn(move || mqtt::run(subscriber, mqtt_sink));
+    }
 
-                    match forecast_db::get_hourly_db() {
-                        Ok(hourly) => {
-                            *hourly_t = hourly;
-                        }
-                        Err(e) => {
-                            println!("failed to get hourly data from database, Reason: {e}");
-                        }
-                    }
-                } else {
-                    println!("Hourly data still fresh");
-                }
-            }
+    //Daily climate summaries from the stored observations
+    let shandle_locations = Arc::clone(&locations);
+    thread::spawn(move || summary::run(shandle_locations));
 
-            if Utc::now() - _daily_timestamp > chrono::Duration::hours(24) {
-                //Pull Daily Web
-                println!("Daily data expired(24h), pulling from web");
-                let mut daily_t = thandle_daily.lock().unwrap();
+    //Tempest hub broadcasts, only when a bind address is configured (usually 0.0.0.0:50222)
+    if let Some(bind) = &config.tempest.bind {
+        let tempest_sink = Arc::clone(&station_location(config.tempest.location.as_deref(), &locations).sink);
+        thread::spawn(move || tempest::listen(bind, &config.tempest, tempest_sink));
+    }
 
-                match web::get_daily_web(&mut daily_t) {
-                    Ok(_) => {
-                        println!("Pulled daily data from Web");
-                        _daily_timestamp = Utc::now();
+    let mut server = Nickel::new();
+    server.options = Options::default().thread_count(Some(config.server.threads));
 
-                        //Persist Daily Data in DB
-                        println!("Persisting daily data to DB");
-                        match forecast_db::dump_daily_db(&mut daily_t) {
-                            Ok(_) => {
-                                println!("Successfully saved daily data to DB");
-                            }
-                            Err(_) => {
-                                println!("Failed to save daily data to DB");
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        println!("Failed to pull daily data from Web");
-                    }
-                }
+    let mut router = Nickel::router();
+
+    //Weather routes, the unscoped ones serve the first configured location
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/hourly",
+        middleware!(|request| web::get_cached_hourly(request, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/instant",
+        middleware!(|request| web::get_cached_inst(request, mhandle_locations.primary()))
+    );
 
-                drop(daily_t);
-            } else {
-                let mut daily_t = thandle_daily.lock().unwrap();
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/v2/forecast/instant",
+        middleware!(|request| web::get_cached_inst_v2(request, mhandle_locations.primary()))
+    );
 
-                if daily_t.len() == 0 {
-                    println!("Daily cache empty, pulling from DB");
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/daily",
+        middleware!(|request| web::get_cached_daily(request, mhandle_locations.primary()))
+    );
 
-                    match forecast_db::get_daily_db() {
-                        Ok(dailies) => {
-                            *daily_t = dailies;
-                        }
-                        Err(e) => {
-                            println!("Failed to get hourly data from DB, Reason: {e}");
-                        }
-                    }
-                } else {
-                    println!("Daily data still fresh");
-                }
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/historical",
+        middleware!(|request, mut response| web::get_hist(request, &mut response, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/hourly/corrected",
+        middleware!(|request| web::get_corrected_hourly(request, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/hourly/evolution",
+        middleware!(|request, mut response| web::get_hourly_evolution(request, &mut response, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/daily/evolution",
+        middleware!(|request, mut response| web::get_daily_evolution(request, &mut response, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/summary",
+        middleware!(|request, mut response| web::get_summary(request, &mut response, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/verification",
+        middleware!(|request, mut response| web::get_verification(request, &mut response, mhandle_locations.primary()))
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/events",
+        middleware!(|request, mut response| web::get_events(request, &mut response, mhandle_locations.primary()))
+    );
+
+    //Per-location weather routes
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/hourly",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_cached_hourly(request, location),
+                Err(e) => e,
+            }
+        }
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/instant",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_cached_inst(request, location),
+                Err(e) => e,
             }
+        }
+    );
 
-            //Pause the data collection loop for 15 minutes
-            thread::sleep(Duration::from_secs(15 * 60));
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/v2/forecast/:location/instant",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_cached_inst_v2(request, location),
+                Err(e) => e,
+            }
         }
-    });
+    );
 
-    let mut server = Nickel::new();
-    let mhandle_inst = Arc::clone(&inst);
-    let mhandle_hour = Arc::clone(&hourlies);
-    let mhandle_daily = Arc::clone(&dailies);
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/daily",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_cached_daily(request, location),
+                Err(e) => e,
+            }
+        }
+    );
 
-    let mut router = Nickel::router();
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/historical",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_hist(request, &mut response, location),
+                Err(e) => e,
+            }
+        }
+    );
 
-    //Weather routes
+    let mhandle_locations = Arc::clone(&locations);
     router.get(
-        "/forecast/hourly",
-        middleware! {|request|
-            let mut hourly = mhandle_hour.lock().unwrap();
-            let resp = web::get_cached_hourly(request,&mut hourly);
-            drop(hourly);
-            resp
+        "/forecast/:location/hourly/corrected",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_corrected_hourly(request, location),
+                Err(e) => e,
+            }
         }
     );
 
+    let mhandle_locations = Arc::clone(&locations);
     router.get(
-        "/forecast/instant",
-        middleware! {|request|
-            let mut inst = mhandle_inst.lock().unwrap();  //TODO PoisonError Here, running with new error checking to see if still necessary to chase
-            let resp = web::get_cached_inst(request, &mut inst);
-            drop(inst);
-            resp
+        "/forecast/:location/hourly/evolution",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_hourly_evolution(request, &mut response, location),
+                Err(e) => e,
+            }
         }
     );
 
+    let mhandle_locations = Arc::clone(&locations);
     router.get(
-        "/forecast/daily",
-        middleware! {|request|
-            let mut daily = mhandle_daily.lock().unwrap();
-            let resp = web::get_cached_daily(request,&mut daily);
-            drop(daily);
-            resp
+        "/forecast/:location/daily/evolution",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_daily_evolution(request, &mut response, location),
+                Err(e) => e,
+            }
         }
     );
 
+    let mhandle_locations = Arc::clone(&locations);
     router.get(
-        "/forecast/historical",
-        middleware!(|request, mut response| web::get_hist(request, &mut response))
+        "/forecast/:location/summary",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_summary(request, &mut response, location),
+                Err(e) => e,
+            }
+        }
     );
 
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/verification",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_verification(request, &mut response, location),
+                Err(e) => e,
+            }
+        }
+    );
+
+    let mhandle_locations = Arc::clone(&locations);
+    router.get(
+        "/forecast/:location/events",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &mhandle_locations) {
+                Ok(location) => web::get_events(request, &mut response, location),
+                Err(e) => e,
+            }
+        }
+    );
+
+    //Local station uploads, only taken with a credential to check them against
+    if config.pws.password.is_some() {
+        let whandle_locations = Arc::clone(&locations);
+        router.get(
+            "/weatherstation/updateweatherstation.php",
+            middleware!(|request, mut response| web::ingest_wunderground(request, &mut response, &whandle_locations))
+        );
+    } else {
+        println!("Wunderground station uploads are off, set pws.password to accept them");
+    }
+
+    if config.pws.ecowitt_passkey.is_some() {
+        let ehandle_locations = Arc::clone(&locations);
+        router.post(
+            "/data/report/",
+            middleware!(|request, mut response| web::ingest_ecowitt(request, &mut response, ehandle_locations.primary()))
+        );
+
+        //Ecowitt gateways have a configurable path, which is how they pick a location
+        let ehandle_locations = Arc::clone(&locations);
+        router.post(
+            "/data/report/:location",
+            middleware! {|request, mut response|
+                match web::find_location(request, &mut response, &ehandle_locations) {
+                    Ok(location) => web::ingest_ecowitt(request, &mut response, location),
+                    Err(e) => e,
+                }
+            }
+        );
+    } else {
+        println!("Ecowitt station uploads are off, set pws.ecowitt_passkey to accept them");
+    }
+
     router.get(
         "/forecast/echo",
         middleware! {
             |request| web::echo(request)
         }
     );
+
+    let mhandle_locations = Arc::clone(&locations);
     router.get(
         "/forecast/poke",
+        middleware!(|_request, mut response| web::poke(&mut response, mhandle_locations.primary()))
+    );
+
+    //On-demand pulls
+    let rhandle_locations = Arc::clone(&locations);
+    router.post(
+        "/forecast/refresh",
+        middleware!(|request, mut response| web::refresh(request, &mut response, rhandle_locations.primary()))
+    );
+
+    let rhandle_locations = Arc::clone(&locations);
+    router.post(
+        "/forecast/:location/refresh",
+        middleware! {|request, mut response|
+            match web::find_location(request, &mut response, &rhandle_locations) {
+                Ok(location) => web::refresh(request, &mut response, location),
+                Err(e) => e,
+            }
+        }
+    );
+
+    router.get(
+        "/forecast/quota",
+        middleware! {
+            web::get_quota()
+        }
+    );
+
+    router.get(
+        "/forecast/metrics",
         middleware! {
-            web::poke()
+            web::get_metrics()
         }
     );
 
+    let hhandle_locations = Arc::clone(&locations);
     router.get(
         "/forecast/healthcheck",
-        //check poisons on locks?
         middleware! {
-            println!("Healthcheck(Ok)");
-            "ok"
+            web::healthcheck(&hhandle_locations)
         }
     );
 
@@ -243,13 +433,290 @@ fn main() {
 
     server.utilize(router);
 
-    server
-        .listen(
-            format!(
-                "{hostname}:{port}",
-                hostname = std::env::var("RUSTYFORECAST_HostURL").unwrap(),
-                port = std::env::var("RUSTYFORECAST_HostPort").unwrap()
-            )
-        )
-        .unwrap();
+    server.listen(config.listen_addr()).unwrap();
+}
+
+//Locations were checked against the config at startup
+fn station_location<'a>(name: Option<&str>, locations: &'a Locations) -> &'a Arc<Location> {
+    match name {
+        Some(name) => locations.get(name).unwrap_or(locations.primary()),
+        None => locations.primary(),
+    }
+}
+
+//Most a cache loop sleeps, the Home Assistant condition follows the current hour so it's republished at least this often
+const MAX_SLEEP_MINUTES: i64 = 15;
+
+//WeatherCacheLoop, each dataset is pulled when its schedule says it's due
+fn cache_loop(
+    location: &Location,
+    forecast_provider: Box<dyn ForecastProvider>,
+    observation_provider: Option<Box<dyn ObservationProvider>>,
+    publisher: Option<MqttPublisher>,
+    refresh: &RefreshConfig
+) {
+    let name = &location.name;
+    let mut scheduler = Scheduler::new(refresh, observation_provider.is_some());
+
+    //Resume from the newest stored pulls, so a restart doesn't refetch data that's still fresh
+    match forecast_db::poke_db_timestamps(name) {
+        Ok((hourly_timestamp, daily_timestamp)) => {
+            if let Some(schedule) = scheduler.get_mut(Dataset::Hourly) {
+                schedule.resume_from(hourly_timestamp);
+            }
+            if let Some(schedule) = scheduler.get_mut(Dataset::Daily) {
+                schedule.resume_from(daily_timestamp);
+            }
+        }
+        Err(e) => {
+            metrics::report(&format!("Couldn't get database recent timestamps for {name}"), &e);
+        }
+    }
+
+    guarded(&format!("Learning corrections for {name}"), || relearn_corrections(location, forecast_provider.name()));
+
+    loop {
+        let due = scheduler.due(Utc::now());
+
+        //Due datasets plus any an on-demand refresh is waiting on
+        let mut datasets = due.clone();
+        for dataset in location.refresh.pending() {
+            if !datasets.contains(&dataset) {
+                datasets.push(dataset);
+            }
+        }
+
+        for dataset in &datasets {
+            let ticket = location.refresh.begin(*dataset);
+
+            let provider = match dataset {
+                Dataset::Instant => observation_provider.as_ref().map_or("none", |p| p.name()),
+                Dataset::Hourly | Dataset::Daily => forecast_provider.name(),
+            };
+
+            //Out of requests, wait for the quota instead of burning retries against it
+            let outcome = match quota::get().check(provider, Utc::now()) {
+                Err(until) => {
+                    if due.contains(dataset) {
+                        if let Some(schedule) = scheduler.get_mut(*dataset) {
+                            schedule.defer_until(until);
+                        }
+                    }
+
+                    println!("{provider} quota used up, deferring {} pull for {name} until {until}", dataset.name());
+                    RefreshOutcome::from(error::Error::QuotaExceeded { provider, until })
+                }
+                Ok(_) => {
+                    //A panicking pull is just a failed one, the loop and the current snapshots carry on
+                    let outcome = guarded(&format!("{} pull for {name}", dataset.name()), || match dataset {
+                        Dataset::Instant =>
+                            match &observation_provider {
+                                Some(observation_provider) => pull_inst(location, observation_provider.as_ref()),
+                                None => RefreshOutcome::ProviderError(format!("No observation provider for {name}")),
+                            }
+                        Dataset::Hourly => pull_hourly(location, forecast_provider.as_ref(), publisher.as_ref()),
+                        Dataset::Daily => pull_daily(location, forecast_provider.as_ref(), publisher.as_ref()),
+                    }).unwrap_or_else(|| RefreshOutcome::ProviderError(format!("{} pull panicked", dataset.name())));
+
+                    //A DB failure still leaves fresh data in the cache, only provider failures are retried.
+                    //A pull that ran out of quota partway, or lost the last request to another location's loop,
+                    //waits for the quota like one that never started
+                    if let Some(schedule) = scheduler.get_mut(*dataset) {
+                        match &outcome {
+                            RefreshOutcome::QuotaExceeded(e) => {
+                                let until = quota::get().check(provider, Utc::now()).err().unwrap_or_else(Utc::now);
+                                schedule.defer_until(until);
+                                println!("Failed to pull {} data for {name}, Reason: {e}, waiting until {until}", dataset.name());
+                            }
+                            RefreshOutcome::ProviderError(e) | RefreshOutcome::Rejected(e) => {
+                                let delay = schedule.failed(Utc::now());
+                                println!("Failed to pull {} data for {name}, Reason: {e}, retrying in {}s", dataset.name(), delay.num_seconds());
+                            }
+                            _ => schedule.succeeded(Utc::now()),
+                        }
+                    }
+
+                    outcome
+                }
+            };
+
+            //New forecast rows may have new observations to pair with
+            if *dataset == Dataset::Hourly && matches!(outcome, RefreshOutcome::Ok) {
+                guarded(&format!("Learning corrections for {name}"), || relearn_corrections(location, forecast_provider.name()));
+            }
+
+            location.refresh.finish(*dataset, ticket, outcome);
+        }
+
+        guarded(&format!("Filling the caches for {name} from the DB"), || fill_from_db(location, publisher.as_ref()));
+
+        //Home Assistant condition follows the current hour, so republish every pass
+        if let Some(publisher) = &publisher {
+            publisher.publish_weather(&location.hourly.load(), &location.daily.load());
+        }
+
+        //Sleep until the next dataset is due or a refresh is requested
+        location.refresh.wait(scheduler.time_until_next(Utc::now(), chrono::Duration::minutes(MAX_SLEEP_MINUTES)));
+    }
+}
+
+//A panic is logged by the panic hook and treated as nothing done, so the cache loop carries on
+//with the snapshots it has
+fn guarded<T>(context: &str, f: impl FnOnce() -> T) -> Option<T> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|_| println!("{context} panicked"))
+        .ok()
+}
+
+//Nothing pulled yet (not due, deferred or failed), serve what the DB has
+fn fill_from_db(location: &Location, publisher: Option<&MqttPublisher>) {
+    let name = &location.name;
+
+    if location.hourly.load().is_empty() {
+        println!("Hourly cache for {name} empty, pulling from DB");
+
+        match forecast_db::get_hourly_db(name) {
+            Ok(hourly) => {
+                if let Some(publisher) = publisher {
+                    publisher.publish_hourly(&hourly);
+                }
+
+                location.hourly.store(Arc::new(hourly));
+            }
+            Err(e) => {
+                metrics::report("Failed to get hourly data from DB", &e);
+            }
+        }
+    }
+
+    if location.daily.load().is_empty() {
+        println!("Daily cache for {name} empty, pulling from DB");
+
+        match forecast_db::get_daily_db(name) {
+            Ok(dailies) => {
+                if let Some(publisher) = publisher {
+                    publisher.publish_daily(&dailies);
+                }
+
+                location.daily.store(Arc::new(dailies));
+            }
+            Err(e) => {
+                metrics::report("Failed to get daily data from DB", &e);
+            }
+        }
+    }
+}
+
+//The previous corrections stay in place when the DB can't be reached
+fn relearn_corrections(location: &Location, provider: &str) {
+    match Corrections::learn(&location.name, provider, &config::get().correction) {
+        Ok(corrections) => location.corrections.store(Arc::new(corrections)),
+        Err(e) => metrics::report(&format!("Couldn't learn forecast corrections for {}", location.name), &e),
+    }
+}
+
+fn pull_inst(location: &Location, observation_provider: &dyn ObservationProvider) -> RefreshOutcome {
+    println!("Pulling Instant Web for {} from {}", location.name, observation_provider.name());
+
+    let inst = match observation_provider.get_inst() {
+        Ok(inst) => inst,
+        Err(e) => {
+            metrics::count_error(&e);
+            return e.into();
+        }
+    };
+    println!("Pulled Inst data from Web");
+
+    match location.sink.accept(inst) {
+        Ok(_) => {
+            println!("Successfully persisted Inst data to DB");
+            RefreshOutcome::Ok
+        }
+        Err(e) => {
+            metrics::report("Failed to persist Inst data to DB", &e);
+            e.into()
+        }
+    }
+}
+
+fn pull_hourly(location: &Location, forecast_provider: &dyn ForecastProvider, publisher: Option<&MqttPublisher>) -> RefreshOutcome {
+    println!("Hourly data for {} due, pulling from {}", location.name, forecast_provider.name());
+
+    let hourly = match forecast_provider.get_hourly() {
+        Ok(hourly) => hourly,
+        Err(e) => {
+            metrics::count_error(&e);
+            return e.into();
+        }
+    };
+    println!("Pulled hourly data from {}", forecast_provider.name());
+
+    //The current snapshot stays put when the response doesn't hold up
+    if let Err(e) = validate::hourly(&hourly, Utc::now(), config::get().horizons.hourly_hours) {
+        metrics::count_error(&e);
+        location.validation.rejected(Dataset::Hourly, &e);
+        return RefreshOutcome::Rejected(format!("{}: {e}", forecast_provider.name()));
+    }
+    location.validation.accepted(Dataset::Hourly);
+
+    //Readers switch to the new snapshot at once, the DB write happens after
+    let hourly = Arc::new(hourly);
+    location.hourly.store(Arc::clone(&hourly));
+
+    if let Some(publisher) = publisher {
+        publisher.publish_hourly(&hourly);
+    }
+
+    //Persist Hourly Data in DB
+    println!("Persisting new hourly data to DB");
+    match forecast_db::dump_hourly_db(&location.name, forecast_provider.name(), &hourly) {
+        Ok(_) => {
+            println!("Successfully saved hourly data to DB");
+            RefreshOutcome::Ok
+        }
+        Err(e) => {
+            metrics::report("Failed to save hourly data to DB", &e);
+            e.into()
+        }
+    }
+}
+
+fn pull_daily(location: &Location, forecast_provider: &dyn ForecastProvider, publisher: Option<&MqttPublisher>) -> RefreshOutcome {
+    println!("Daily data for {} due, pulling from {}", location.name, forecast_provider.name());
+
+    let daily = match forecast_provider.get_daily() {
+        Ok(daily) => daily,
+        Err(e) => {
+            metrics::count_error(&e);
+            return e.into();
+        }
+    };
+    println!("Pulled daily data from {}", forecast_provider.name());
+
+    if let Err(e) = validate::daily(&daily, Utc::now(), config::get().horizons.daily_days) {
+        metrics::count_error(&e);
+        location.validation.rejected(Dataset::Daily, &e);
+        return RefreshOutcome::Rejected(format!("{}: {e}", forecast_provider.name()));
+    }
+    location.validation.accepted(Dataset::Daily);
+
+    let daily = Arc::new(daily);
+    location.daily.store(Arc::clone(&daily));
+
+    if let Some(publisher) = publisher {
+        publisher.publish_daily(&daily);
+    }
+
+    //Persist Daily Data in DB
+    println!("Persisting daily data to DB");
+    match forecast_db::dump_daily_db(&location.name, forecast_provider.name(), &daily) {
+        Ok(_) => {
+            println!("Successfully saved daily data to DB");
+            RefreshOutcome::Ok
+        }
+        Err(e) => {
+            metrics::report("Failed to save daily data to DB", &e);
+            e.into()
+        }
+    }
 }
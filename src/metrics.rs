@@ -0,0 +1,30 @@
+use crate::error::{ self, Error };
+use serde::Serialize;
+use std::{ collections::BTreeMap, sync::Mutex };
+
+//Counters since startup, served on /forecast/metrics
+static ERRORS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    //Handled errors by kind, see error::KINDS
+    pub errors: BTreeMap<&'static str, u64>,
+}
+
+pub fn count_error(e: &Error) {
+    *ERRORS.lock().unwrap().entry(e.kind()).or_insert(0) += 1;
+}
+
+//Logs and counts an error that stops here instead of being returned
+pub fn report(context: &str, e: &Error) {
+    count_error(e);
+    println!("{context}, Reason: {e}");
+}
+
+pub fn get() -> Metrics {
+    let counted = ERRORS.lock().unwrap();
+
+    Metrics {
+        errors: error::KINDS.iter().map(|kind| (*kind, counted.get(kind).copied().unwrap_or(0))).collect(),
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{ error::Error, forecast_db };
+
+//One versioned change to the schema, embedded from migrations/. Never edit or renumber one that has
+//shipped, add the next file instead
+struct Migration {
+    version: i32,
+    name: &'static str,
+    sql: &'static str,
+}
+
+const MIGRATIONS: [Migration; 10] = [
+    Migration { version: 1, name: "forecast_tables", sql: include_str!("../migrations/0001_forecast_tables.sql") },
+    Migration { version: 2, name: "location_columns", sql: include_str!("../migrations/0002_location_columns.sql") },
+    Migration { version: 3, name: "event_tables", sql: include_str!("../migrations/0003_event_tables.sql") },
+    Migration { version: 4, name: "api_usage", sql: include_str!("../migrations/0004_api_usage.sql") },
+    Migration { version: 5, name: "forecast_runs", sql: include_str!("../migrations/0005_forecast_runs.sql") },
+    Migration { version: 6, name: "forecast_run_issued_at", sql: include_str!("../migrations/0006_forecast_run_issued_at.sql") },
+    Migration { version: 7, name: "daily_summary", sql: include_str!("../migrations/0007_daily_summary.sql") },
+    Migration { version: 8, name: "event_locations", sql: include_str!("../migrations/0008_event_locations.sql") },
+    Migration { version: 9, name: "raw_weather_code", sql: include_str!("../migrations/0009_raw_weather_code.sql") },
+    Migration { version: 10, name: "local_weather_times", sql: include_str!("../migrations/0010_local_weather_times.sql") },
+];
+
+//pg_advisory_xact_lock key, so two instances starting together apply each migration once
+const LOCK_KEY: i64 = 0x7275_7374_795f_6663;
+
+//Applies whatever schema_migrations doesn't list yet, each migration in its own transaction with its
+//row, and returns how many ran. Databases set up by hand are adopted, the early migrations only create
+//what's missing
+pub fn run() -> Result<usize, Error> {
+    let mut client = forecast_db::connect()?;
+    let mut applied = 0;
+    let zone = local_zone();
+
+    if zone.is_none() {
+        println!("Couldn't tell this host's timezone, migrations that need it use the DB server's");
+    }
+
+    for migration in MIGRATIONS.iter() {
+        let mut transaction = client.transaction().map_err(Error::db_query("Couldn't start migration transaction"))?;
+
+        transaction
+            .execute("SELECT pg_advisory_xact_lock($1)", &[&LOCK_KEY])
+            .map_err(Error::db_query("Couldn't take the migration lock"))?;
+
+        //For the ones converting stored wall times, chrono's Local and the DB then agree on what local is
+        if let Some(zone) = &zone {
+            transaction
+                .execute("SELECT set_config('TimeZone', $1, true)", &[zone])
+                .map_err(Error::db_query("Couldn't set the migration timezone"))?;
+        }
+
+        transaction
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations(version INTEGER PRIMARY KEY, name VARCHAR NOT NULL, applied_at TIMESTAMPTZ NOT NULL DEFAULT now());"
+            )
+            .map_err(Error::db_query("Failed to create schema_migrations table"))?;
+
+        let done = transaction
+            .query_opt("SELECT version FROM schema_migrations WHERE version = $1", &[&migration.version])
+            .map_err(Error::db_query("Couldn't read applied migrations"))?
+            .is_some();
+
+        if done {
+            continue;
+        }
+
+        println!("Applying migration {:04}_{}", migration.version, migration.name);
+
+        transaction.batch_execute(migration.sql).map_err(Error::db_query("Migration failed, nothing from it was applied"))?;
+
+        transaction
+            .execute("INSERT INTO schema_migrations(version, name) VALUES ($1, $2)", &[&migration.version, &migration.name])
+            .map_err(Error::db_query("Failed to record migration"))?;
+
+        transaction.commit().map_err(Error::db_query("Failed to commit migration"))?;
+        applied += 1;
+    }
+
+    let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or(0);
+    let current: Option<i32> = client
+        .query_one("SELECT max(version) FROM schema_migrations", &[])
+        .map_err(Error::db_query("Couldn't read applied migrations"))?
+        .get(0);
+
+    match current {
+        Some(current) if current > latest => {
+            println!("DB schema is at version {current}, newer than this build knows ({latest}), queries may not match it");
+        }
+        _ => println!("DB schema is at version {latest}, {applied} migrations applied"),
+    }
+
+    Ok(applied)
+}
+
+//The zone chrono's Local goes by, TZ first as it does, then the system's
+fn local_zone() -> Option<String> {
+    match std::env::var("TZ") {
+        Ok(tz) if !tz.is_empty() => Some(tz.trim_start_matches(':').to_string()),
+        _ => iana_time_zone::get_timezone().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_count_up_from_one() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1, "{} is out of order", migration.name);
+            assert!(!migration.sql.trim().is_empty(), "{} is empty", migration.name);
+        }
+    }
+
+    //A file added to migrations/ without its entry above would never run
+    #[test]
+    fn every_migration_file_is_listed() {
+        let mut files: Vec<String> = std::fs::read_dir(format!("{}/migrations", env!("CARGO_MANIFEST_DIR")))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.ends_with(".sql"))
+            .collect();
+        files.sort();
+
+        let listed: Vec<String> = MIGRATIONS.iter().map(|m| format!("{:04}_{}.sql", m.version, m.name)).collect();
+
+        assert_eq!(files, listed);
+    }
+}
@@ -0,0 +1,179 @@
+pub mod discovery;
+pub mod readings;
+
+use crate::{ climacell::models::{ DailyWeather, HourlyWeather }, config::{ Config, Units }, ingest::InstSink, metrics, wunder::models::InstModel, zone::{ self, Zone } };
+use rumqttc::{ Client, Connection, Event, MqttOptions, Packet, QoS };
+use serde::Serialize;
+use std::{ sync::Arc, thread, time::Duration };
+
+//Optional MQTT bridge, enabled by mqtt.host.
+//Publishes every refreshed cache to retained {prefix}/instant|hourly|daily topics and
+//takes station readings (rtl_433 or weewx JSON) from the mqtt.subscribe topics.
+//With [[locations]] every location gets its own {prefix}/{location}/... topics and HA device
+#[derive(Clone)]
+pub struct MqttPublisher {
+    client: Client,
+    prefix: String,
+    //HA node/unique id base and device name
+    node_id: String,
+    device_name: String,
+    //Home Assistant discovery prefix, None when mqtt.discovery_prefix is empty
+    discovery_prefix: Option<String>,
+    units: Units,
+    //The location's, for which local day an hour's sunrise and sunset are on
+    zone: Zone,
+}
+
+pub struct MqttSubscriber {
+    publishers: Vec<MqttPublisher>,
+    connection: Connection,
+    topics: Vec<String>,
+    filter: readings::DeviceFilter,
+}
+
+//One publisher per configured location, in config order
+pub fn connect(config: &Config) -> Option<(Vec<MqttPublisher>, MqttSubscriber)> {
+    let mqtt = &config.mqtt;
+    let host = mqtt.host.clone()?;
+
+    let mut options = MqttOptions::new(mqtt.client_id.clone(), host, mqtt.port);
+    options.set_keep_alive(Duration::from_secs(30));
+
+    if let (Some(user), Some(pass)) = (&mqtt.user, &mqtt.pass) {
+        options.set_credentials(user.clone(), pass.clone());
+    }
+
+    //Room for the discovery burst on connect, which is queued from the event loop thread itself
+    let (client, connection) = Client::new(options, 32 * config.locations.len());
+
+    let discovery_prefix = Some(mqtt.discovery_prefix.clone()).filter(|prefix| !prefix.is_empty());
+
+    let publishers: Vec<MqttPublisher> = config.locations
+        .iter()
+        .map(|location| {
+            let (prefix, node_id, device_name) = if config.single_location {
+                (mqtt.topic_prefix.clone(), mqtt.topic_prefix.clone(), "Rusty Forecast".to_string())
+            } else {
+                (
+                    format!("{}/{}", mqtt.topic_prefix, location.name),
+                    format!("{}_{}", mqtt.topic_prefix, location.name),
+                    format!("Rusty Forecast {}", location.name),
+                )
+            };
+
+            MqttPublisher {
+                client: client.clone(),
+                prefix,
+                node_id,
+                device_name,
+                discovery_prefix: discovery_prefix.clone(),
+                units: config.units,
+                zone: zone::of(&location.name),
+            }
+        })
+        .collect();
+
+    let filter = readings::DeviceFilter { model: mqtt.rtl_433_model.clone(), id: mqtt.rtl_433_id.clone() };
+    if !mqtt.subscribe.is_empty() && filter.model.is_none() && filter.id.is_none() {
+        println!("Taking rtl_433 readings from every device on the bus, set mqtt.rtl_433_model or mqtt.rtl_433_id to pick the station");
+    }
+
+    Some((publishers.clone(), MqttSubscriber { publishers, connection, topics: mqtt.subscribe.clone(), filter }))
+}
+
+impl MqttPublisher {
+    pub fn publish_inst(&self, inst: &InstModel) {
+        self.publish_retained("instant", &inst.clone().in_units(self.units));
+    }
+
+    pub fn publish_hourly(&self, hourly: &[HourlyWeather]) {
+        self.publish_retained("hourly", &in_units_hourly(hourly, self.units));
+    }
+
+    pub fn publish_daily(&self, daily: &[DailyWeather]) {
+        self.publish_retained("daily", &in_units_daily(daily, self.units));
+    }
+
+    pub fn publish_weather(&self, hourly: &[HourlyWeather], daily: &[DailyWeather]) {
+        if self.discovery_prefix.is_some() {
+            let state = discovery::weather_state(&in_units_hourly(hourly, self.units), &in_units_daily(daily, self.units), self.zone);
+            self.publish_retained("weather", &state);
+        }
+    }
+
+    //Retained, so Home Assistant picks the sensors up whenever it restarts
+    fn publish_discovery(&self) {
+        if let Some(discovery_prefix) = &self.discovery_prefix {
+            for (topic, config) in discovery::configs(discovery_prefix, &self.prefix, &self.node_id, &self.device_name, self.units) {
+                self.publish_to(topic, &config);
+            }
+        }
+    }
+
+    fn publish_retained<T: Serialize>(&self, dataset: &str, value: &T) {
+        self.publish_to(format!("{}/{dataset}", self.prefix), value);
+    }
+
+    fn publish_to<T: Serialize>(&self, topic: String, value: &T) {
+        match serde_json::to_vec(value) {
+            Ok(payload) => {
+                //try_ so a dead broker never blocks the cache loop or an HTTP handler
+                if let Err(e) = self.client.try_publish(&topic, QoS::AtLeastOnce, true, payload) {
+                    println!("Couldn't publish {topic} to MQTT, Reason: {e}");
+                }
+            }
+            Err(e) => println!("Couldn't serialize {topic} for MQTT, Reason: {e}"),
+        }
+    }
+}
+
+fn in_units_hourly(hourly: &[HourlyWeather], units: Units) -> Vec<HourlyWeather> {
+    hourly.iter().map(|h| h.clone().in_units(units)).collect()
+}
+
+fn in_units_daily(daily: &[DailyWeather], units: Units) -> Vec<DailyWeather> {
+    daily.iter().map(|d| d.clone().in_units(units)).collect()
+}
+
+//Drives the connection (publishes only go out while this runs) and feeds readings into the sink
+pub fn run(subscriber: MqttSubscriber, sink: Arc<InstSink>) {
+    let MqttSubscriber { publishers, mut connection, topics, filter } = subscriber;
+    let zone = zone::of(sink.location());
+    let mut rain_counter = readings::RainCounter::new(zone);
+
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                println!("Connected to MQTT broker");
+                for publisher in publishers.iter() {
+                    publisher.publish_discovery();
+                }
+
+                //Clean session, so subscribe again on every reconnect
+                for topic in topics.iter() {
+                    if let Err(e) = publishers[0].client.try_subscribe(topic, QoS::AtMostOnce) {
+                        println!("Couldn't subscribe to {topic}, Reason: {e}");
+                    }
+                }
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                match readings::parse(&publish.payload, &mut rain_counter, &filter) {
+                    Ok(Some(reading)) => {
+                        let inst = reading.merge_into(&sink.cached(), zone);
+
+                        if let Err(e) = sink.accept(inst) {
+                            metrics::report(&format!("Failed to persist MQTT reading from {}", publish.topic), &e);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => println!("Skipping MQTT message on {}, Reason: {e}", publish.topic),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                println!("MQTT connection error, retrying, Reason: {e}");
+                thread::sleep(Duration::from_secs(5));
+            }
+        }
+    }
+}
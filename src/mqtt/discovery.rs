@@ -0,0 +1,187 @@
+use crate::{ climacell::{ models::{ DailyWeather, HourlyWeather }, WeatherCode }, config::Units, zone::Zone };
+use chrono::{ DateTime, Utc };
+use serde::Serialize;
+use serde_json::{ json, Value };
+
+//Home Assistant MQTT discovery. One sensor per InstModel field reading {prefix}/instant,
+//value_templates use the same flat camelCase keys /v2/forecast/instant serves (/forecast/instant
+//keeps Wunderground's {observations:[...]} shape).
+//MQTT discovery has no weather platform, so the forecast goes to {prefix}/weather in HA's
+//forecast shape with a condition sensor on top, ready for a template weather entity
+
+struct InstSensor {
+    key: &'static str,
+    name: &'static str,
+    device_class: Option<&'static str>,
+    unit: &'static str,
+    metric_unit: &'static str,
+    //None where no state class fits, a plain measurement would average directions across north
+    state_class: Option<&'static str>,
+}
+
+const INST_SENSORS: [InstSensor; 11] = [
+    InstSensor { key: "temp", name: "Temperature", device_class: Some("temperature"), unit: "°F", metric_unit: "°C", state_class: Some("measurement") },
+    InstSensor { key: "heatIndex", name: "Heat Index", device_class: Some("temperature"), unit: "°F", metric_unit: "°C", state_class: Some("measurement") },
+    InstSensor { key: "dewpt", name: "Dew Point", device_class: Some("temperature"), unit: "°F", metric_unit: "°C", state_class: Some("measurement") },
+    InstSensor { key: "humidity", name: "Humidity", device_class: Some("humidity"), unit: "%", metric_unit: "%", state_class: Some("measurement") },
+    InstSensor { key: "pressure", name: "Pressure", device_class: Some("atmospheric_pressure"), unit: "inHg", metric_unit: "hPa", state_class: Some("measurement") },
+    InstSensor { key: "windSpeed", name: "Wind Speed", device_class: Some("wind_speed"), unit: "mph", metric_unit: "km/h", state_class: Some("measurement") },
+    InstSensor { key: "winddir", name: "Wind Direction", device_class: None, unit: "°", metric_unit: "°", state_class: None },
+    InstSensor { key: "uv", name: "UV Index", device_class: None, unit: "UV index", metric_unit: "UV index", state_class: Some("measurement") },
+    InstSensor { key: "solarRadiation", name: "Solar Radiation", device_class: Some("irradiance"), unit: "W/m²", metric_unit: "W/m²", state_class: Some("measurement") },
+    InstSensor { key: "precipRate", name: "Precipitation Rate", device_class: Some("precipitation_intensity"), unit: "in/h", metric_unit: "mm/h", state_class: Some("measurement") },
+    //Resets at midnight, which total_increasing treats as a new cycle
+    InstSensor { key: "precipTotal", name: "Precipitation Today", device_class: Some("precipitation"), unit: "in", metric_unit: "mm", state_class: Some("total_increasing") },
+];
+
+//(topic, payload) pairs, all published retained
+pub fn configs(discovery_prefix: &str, prefix: &str, node_id: &str, device_name: &str, units: Units) -> Vec<(String, Value)> {
+    let device = json!({
+        "identifiers": [node_id],
+        "name": device_name,
+        "manufacturer": "rusty_forecast",
+        "model": "Weather cache",
+    });
+
+    let mut configs = vec![];
+
+    for sensor in INST_SENSORS.iter() {
+        let object_id = sensor.key.to_lowercase();
+
+        let mut config = json!({
+            "name": sensor.name,
+            "unique_id": format!("{node_id}_{object_id}"),
+            "state_topic": format!("{prefix}/instant"),
+            "value_template": format!("{{{{ value_json.{} }}}}", sensor.key),
+            "unit_of_measurement": match units {
+                Units::Imperial => sensor.unit,
+                Units::Metric => sensor.metric_unit,
+            },
+            "device": device,
+        });
+
+        if let Some(device_class) = sensor.device_class {
+            config["device_class"] = json!(device_class);
+        }
+
+        if let Some(state_class) = sensor.state_class {
+            config["state_class"] = json!(state_class);
+        }
+
+        configs.push((format!("{discovery_prefix}/sensor/{node_id}/{object_id}/config"), config));
+    }
+
+    configs.push((
+        format!("{discovery_prefix}/sensor/{node_id}/condition/config"),
+        json!({
+            "name": "Condition",
+            "unique_id": format!("{node_id}_condition"),
+            "state_topic": format!("{prefix}/weather"),
+            "value_template": "{{ value_json.condition }}",
+            "json_attributes_topic": format!("{prefix}/weather"),
+            "icon": "mdi:weather-partly-cloudy",
+            "device": device,
+        }),
+    ));
+
+    configs
+}
+
+#[derive(Debug, Serialize)]
+pub struct WeatherState {
+    pub condition: &'static str,
+    pub forecast_hourly: Vec<Value>,
+    pub forecast_daily: Vec<Value>,
+}
+
+pub fn weather_state(hourly: &[HourlyWeather], daily: &[DailyWeather], zone: Zone) -> WeatherState {
+    let now = Utc::now();
+
+    //Current condition is whichever hour we're in, falling back to today's daily code
+    let condition = match hourly.iter().rev().find(|h| h.weather_time <= now).or(hourly.first()) {
+        Some(hour) => ha_condition(hour.weather_code, is_night(hour.weather_time, daily, zone)),
+        None =>
+            match daily.first() {
+                Some(day) => ha_condition(day.weather_code, false),
+                None => "exceptional",
+            }
+    };
+
+    WeatherState {
+        condition,
+        forecast_hourly: hourly
+            .iter()
+            .map(|h| {
+                json!({
+                    "datetime": h.weather_time.to_rfc3339(),
+                    "condition": ha_condition(h.weather_code, is_night(h.weather_time, daily, zone)),
+                    "temperature": h.temp,
+                    "apparent_temperature": h.feels_like,
+                    "precipitation_probability": h.precipitation_chance,
+                    "humidity": h.humidity,
+                    "dew_point": h.dew_point,
+                })
+            })
+            .collect(),
+        forecast_daily: daily
+            .iter()
+            .map(|d| {
+                json!({
+                    "datetime": d.weather_time.to_rfc3339(),
+                    "condition": ha_condition(d.weather_code, false),
+                    "temperature": d.high,
+                    "templow": d.low,
+                })
+            })
+            .collect(),
+    }
+}
+
+//Night if the hour falls outside that local day's sunrise/sunset, when the provider gave us them
+fn is_night(time: DateTime<Utc>, daily: &[DailyWeather], zone: Zone) -> bool {
+    let date = zone.at(time).date_naive();
+
+    daily
+        .iter()
+        .find(|d| zone.at(d.weather_time).date_naive() == date)
+        .and_then(|d| Some((d.sunrise_time?, d.sunset_time?)))
+        .map(|(sunrise, sunset)| time < sunrise || time >= sunset)
+        .unwrap_or(false)
+}
+
+//https://developers.home-assistant.io/docs/core/entity/weather/#recommended-values-for-state-and-condition
+pub fn ha_condition(code: WeatherCode, night: bool) -> &'static str {
+    match code {
+        WeatherCode::Clear | WeatherCode::Mostly_Clear if night => "clear-night",
+        WeatherCode::Clear | WeatherCode::Mostly_Clear => "sunny",
+        WeatherCode::Partly_Cloudy => "partlycloudy",
+        WeatherCode::Mostly_Cloudy | WeatherCode::Cloudy => "cloudy",
+        WeatherCode::Fog | WeatherCode::Light_Fog => "fog",
+        WeatherCode::Light_Wind | WeatherCode::Wind | WeatherCode::Strong_Wind => "windy",
+        WeatherCode::Drizzle | WeatherCode::Light_Rain | WeatherCode::Rain => "rainy",
+        WeatherCode::Heavy_Rain => "pouring",
+        WeatherCode::Snow | WeatherCode::Flurries | WeatherCode::Light_Snow | WeatherCode::Heavy_Snow => "snowy",
+        | WeatherCode::Freezing_Drizzle
+        | WeatherCode::Freezing_Rain
+        | WeatherCode::Light_Freezing_Rain
+        | WeatherCode::Heavy_Freezing_Rain => "snowy-rainy",
+        WeatherCode::Ice_Pellets | WeatherCode::Heavy_Ice_Pellets | WeatherCode::Light_Ice_Pellets => "hail",
+        WeatherCode::Thunderstorm => "lightning-rainy",
+        WeatherCode::Unknown => "exceptional",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wind_direction_has_no_state_class() {
+        let configs = configs("homeassistant", "rusty_forecast", "rusty_forecast", "Weather", Units::Imperial);
+        let config = |object_id: &str| &configs.iter().find(|(topic, _)| topic.ends_with(&format!("/{object_id}/config"))).unwrap().1;
+
+        assert!(config("winddir").get("state_class").is_none());
+        assert_eq!(config("windspeed")["state_class"], "measurement");
+        assert_eq!(config("preciptotal")["state_class"], "total_increasing");
+    }
+}
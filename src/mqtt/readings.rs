@@ -0,0 +1,272 @@
+use crate::{ calc, wunder::models::InstModel, zone::Zone };
+use chrono::{ DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc };
+use serde_json::Value;
+
+//Station readings off the bus. Sensors often only report a few fields per message,
+//so whatever is present gets merged over the last cached observation
+#[derive(Default, Debug)]
+pub struct PartialReading {
+    pub obs_time_utc: Option<DateTime<Utc>>,
+    pub temp: Option<f64>,
+    pub humidity: Option<f64>,
+    pub dewpt: Option<f64>,
+    pub wind_speed: Option<f64>,
+    pub wind_gust: Option<f64>,
+    pub winddir: Option<f64>,
+    pub pressure: Option<f64>,
+    pub precip_rate: Option<f64>,
+    pub precip_total: Option<f64>,
+    pub solar_radiation: Option<f64>,
+    pub uv: Option<f64>,
+}
+
+//rtl_433 only has a running rain counter, the day's total is measured from the first reading of the day
+#[derive(Default)]
+pub struct RainCounter {
+    //The location's, whose midnight starts the day
+    zone: Zone,
+    day: Option<NaiveDate>,
+    baseline_in: f64,
+}
+
+impl RainCounter {
+    pub fn new(zone: Zone) -> RainCounter {
+        RainCounter { zone, ..RainCounter::default() }
+    }
+
+    fn daily_total(&mut self, counter_in: f64, at: DateTime<Utc>) -> f64 {
+        let today = self.zone.at(at).date_naive();
+
+        //New day, or the sensor's counter reset (battery swap)
+        if self.day != Some(today) || counter_in < self.baseline_in {
+            self.day = Some(today);
+            self.baseline_in = counter_in;
+        }
+
+        counter_in - self.baseline_in
+    }
+}
+
+//rtl_433 publishes every device in radio range, only the configured one's readings are merged. Unset matches any
+#[derive(Default, Debug)]
+pub struct DeviceFilter {
+    pub model: Option<String>,
+    pub id: Option<String>,
+}
+
+impl DeviceFilter {
+    fn wanted(&self, json: &Value) -> bool {
+        //rtl_433 sends ids as numbers, but some devices as strings
+        let matches = |expected: &Option<String>, key: &str| {
+            match (expected, json.get(key)) {
+                (None, _) => true,
+                (Some(expected), Some(Value::String(value))) => value == expected,
+                (Some(expected), Some(Value::Number(value))) => value.to_string() == *expected,
+                _ => false,
+            }
+        };
+
+        matches(&self.model, "model") && matches(&self.id, "id")
+    }
+}
+
+//None for another rtl_433 device's message
+pub fn parse(payload: &[u8], rain_counter: &mut RainCounter, filter: &DeviceFilter) -> Result<Option<PartialReading>, String> {
+    let json = match serde_json::from_slice::<Value>(payload) {
+        Ok(json) => json,
+        Err(e) => {
+            return Err(format!("Couldn't deserialize MQTT payload, Reason: {e}"));
+        }
+    };
+
+    let reading = if json.get("model").is_some() {
+        if !filter.wanted(&json) {
+            return Ok(None);
+        }
+
+        from_rtl_433(&json, rain_counter)
+    } else if json.get("dateTime").is_some() || json.get("outTemp_F").is_some() {
+        from_weewx(&json)
+    } else {
+        return Err("MQTT payload isn't rtl_433 or weewx JSON".to_string());
+    };
+
+    //Otherwise it'd be merged as a fresh copy of the last observation
+    if reading.is_empty() {
+        return Err("MQTT payload has no readings".to_string());
+    }
+
+    Ok(Some(reading))
+}
+
+//rtl_433 -F json, units vary by device so take whichever one is present
+fn from_rtl_433(json: &Value, rain_counter: &mut RainCounter) -> PartialReading {
+    let obs_time_utc = json
+        .get("time")
+        .and_then(|t| t.as_str())
+        .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok())
+        .and_then(|t| Local.from_local_datetime(&t).single())
+        .map(|t| t.with_timezone(&Utc));
+
+    let speed = |prefix: &str| {
+        num(json, &format!("{prefix}_mi_h"))
+            .or_else(|| num(json, &format!("{prefix}_km_h")).map(|kmh| kmh * 0.621371))
+            .or_else(|| num(json, &format!("{prefix}_m_s")).map(|ms| ms * 2.23694))
+    };
+
+    PartialReading {
+        obs_time_utc,
+        temp: num(json, "temperature_F").or_else(|| num(json, "temperature_C").map(calc::c_to_f)),
+        humidity: num(json, "humidity"),
+        dewpt: None,
+        wind_speed: speed("wind_avg"),
+        wind_gust: speed("wind_max"),
+        winddir: num(json, "wind_dir_deg"),
+        pressure: num(json, "pressure_inHg").or_else(|| num(json, "pressure_hPa").map(|hpa| hpa * 0.02953)),
+        precip_rate: num(json, "rain_rate_in_h").or_else(|| num(json, "rain_rate_mm_h").map(|mm| mm / 25.4)),
+        precip_total: num(json, "rain_in")
+            .or_else(|| num(json, "rain_mm").map(|mm| mm / 25.4))
+            .map(|counter| rain_counter.daily_total(counter, obs_time_utc.unwrap_or_else(Utc::now))),
+        solar_radiation: num(json, "light_lux").map(|lux| lux / 126.7),
+        uv: num(json, "uvi").or_else(|| num(json, "uv")),
+    }
+}
+
+//weewx MQTT extension in US units, values come through as strings
+fn from_weewx(json: &Value) -> PartialReading {
+    PartialReading {
+        obs_time_utc: num(json, "dateTime").and_then(|t| Utc.timestamp_opt(t as i64, 0).single()),
+        temp: num(json, "outTemp_F"),
+        humidity: num(json, "outHumidity"),
+        dewpt: num(json, "dewpoint_F"),
+        wind_speed: num(json, "windSpeed_mph"),
+        wind_gust: num(json, "windGust_mph"),
+        winddir: num(json, "windDir"),
+        pressure: num(json, "barometer_inHg"),
+        precip_rate: num(json, "rainRate_inch_per_hour"),
+        precip_total: num(json, "dayRain_in"),
+        solar_radiation: num(json, "radiation_Wpm2"),
+        uv: num(json, "UV"),
+    }
+}
+
+fn num(json: &Value, key: &str) -> Option<f64> {
+    match json.get(key)? {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+impl PartialReading {
+    fn is_empty(&self) -> bool {
+        [
+            self.temp,
+            self.humidity,
+            self.dewpt,
+            self.wind_speed,
+            self.wind_gust,
+            self.winddir,
+            self.pressure,
+            self.precip_rate,
+            self.precip_total,
+            self.solar_radiation,
+            self.uv,
+        ]
+            .iter()
+            .all(Option::is_none)
+    }
+
+    pub fn merge_into(self, previous: &InstModel, zone: Zone) -> InstModel {
+        let obs_time_utc = self.obs_time_utc.unwrap_or_else(Utc::now);
+
+        let temp = self.temp.unwrap_or(previous.temp as f64);
+        let humidity = self.humidity.unwrap_or(previous.humidity as f64);
+        let wind_speed = self.wind_speed.unwrap_or(previous.wind_speed as f64);
+
+        //Re-derive if either input moved and the station didn't send its own
+        let dewpt = match self.dewpt {
+            Some(dewpt) => dewpt,
+            None if self.temp.is_some() || self.humidity.is_some() => calc::dew_point(temp, humidity),
+            None => previous.dewpt as f64,
+        };
+
+        InstModel {
+            obs_time_utc,
+            obs_time_local: zone.wall_time(obs_time_utc),
+            temp: temp.round() as i32,
+            heat_index: calc::heat_index(temp, humidity).round() as i32,
+            wind_chill: calc::wind_chill(temp, wind_speed).round() as i32,
+            dewpt: dewpt.round() as i32,
+            humidity: humidity.round() as i32,
+            precip_rate: self.precip_rate.unwrap_or(previous.precip_rate),
+            precip_total: self.precip_total.unwrap_or(previous.precip_total),
+            wind_speed: wind_speed.round() as i32,
+            winddir: self.winddir.map(|d| d.round() as i32).unwrap_or(previous.winddir),
+            wind_gust: self.wind_gust.map(|g| g.round() as i32).unwrap_or(previous.wind_gust),
+            pressure: self.pressure.unwrap_or(previous.pressure),
+            solar_radiation: self.solar_radiation.unwrap_or(previous.solar_radiation),
+            uv: self.uv.unwrap_or(previous.uv),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WS90: &[u8] = br#"{"time": "2024-04-12 10:00:00", "model": "Fineoffset-WS90", "id": 41263, "temperature_C": 20.0, "humidity": 50, "wind_avg_m_s": 2.0}"#;
+    const NEIGHBOUR: &[u8] = br#"{"time": "2024-04-12 10:00:00", "model": "Acurite-Tower", "id": "A", "temperature_C": 30.0, "humidity": 90}"#;
+
+    fn filter(model: Option<&str>, id: Option<&str>) -> DeviceFilter {
+        DeviceFilter { model: model.map(str::to_string), id: id.map(str::to_string) }
+    }
+
+    fn parse_with(payload: &[u8], filter: &DeviceFilter) -> Result<Option<PartialReading>, String> {
+        parse(payload, &mut RainCounter::default(), filter)
+    }
+
+    #[test]
+    fn configured_rtl_433_device_is_taken() {
+        let reading = parse_with(WS90, &filter(Some("Fineoffset-WS90"), Some("41263"))).unwrap().unwrap();
+
+        assert_eq!(reading.temp, Some(68.0));
+        assert_eq!(reading.humidity, Some(50.0));
+        assert!((reading.wind_speed.unwrap() - 4.47388).abs() < 1e-9);
+    }
+
+    #[test]
+    fn other_rtl_433_devices_are_skipped() {
+        assert!(parse_with(NEIGHBOUR, &filter(Some("Fineoffset-WS90"), None)).unwrap().is_none());
+        assert!(parse_with(WS90, &filter(None, Some("1"))).unwrap().is_none());
+        assert!(parse_with(WS90, &filter(Some("Fineoffset-WS90"), Some("1"))).unwrap().is_none());
+        assert!(parse_with(NEIGHBOUR, &filter(None, Some("A"))).unwrap().is_some());
+    }
+
+    #[test]
+    fn any_rtl_433_device_is_taken_without_a_filter() {
+        assert!(parse_with(WS90, &DeviceFilter::default()).unwrap().is_some());
+        assert!(parse_with(NEIGHBOUR, &DeviceFilter::default()).unwrap().is_some());
+    }
+
+    #[test]
+    fn messages_without_readings_are_skipped() {
+        let rtl_433 = br#"{"time": "2024-04-12 10:00:00", "model": "Fineoffset-WS90", "id": 41263, "battery_ok": 1}"#;
+        let weewx = br#"{"dateTime": "1712930400.0"}"#;
+
+        assert!(parse_with(rtl_433, &DeviceFilter::default()).is_err());
+        assert!(parse_with(weewx, &DeviceFilter::default()).is_err());
+        assert!(parse_with(br#"{"foo": 1}"#, &DeviceFilter::default()).is_err());
+    }
+
+    #[test]
+    fn filtered_devices_dont_touch_the_rain_counter() {
+        let mut rain_counter = RainCounter::default();
+        let rain = |mm: f64| format!(r#"{{"time": "2024-04-12 10:00:00", "model": "Acurite-5n1", "id": 7, "rain_mm": {mm}}}"#);
+
+        parse(rain(25.4).as_bytes(), &mut rain_counter, &filter(Some("Fineoffset-WS90"), None)).unwrap();
+        let reading = parse(rain(50.8).as_bytes(), &mut rain_counter, &DeviceFilter::default()).unwrap().unwrap();
+
+        assert_eq!(reading.precip_total, Some(0.0));
+    }
+}
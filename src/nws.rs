@@ -0,0 +1,327 @@
+pub mod weather_code;
+pub mod webmodels;
+
+use crate::climacell::{ models::{ DailyWeather, HourlyWeather }, MoonPhase, PrecipitationType };
+use crate::{ calc, config::{ Config, LocationConfig }, error::Error, provider::{ self, ForecastProvider }, quota };
+use chrono::{ DateTime, Utc };
+use serde::de::DeserializeOwned;
+use std::sync::Mutex;
+use webmodels::{ ForecastRoot, Period, PointProperties, PointRoot };
+
+//National Weather Service (api.weather.gov), US only and keyless.
+//The tests replay the recorded payloads under docs/fixtures/nws from a local server
+pub struct NwsProvider {
+    base_url: String,
+    user_agent: String,
+    lat: f64,
+    lon: f64,
+    hourly_hours: i64,
+    daily_days: i64,
+    //The gridpoint a location's lat/long falls in doesn't move, so /points is only asked once
+    point: Mutex<Option<PointProperties>>,
+}
+
+impl NwsProvider {
+    pub fn from_config(config: &Config, location: &LocationConfig) -> Result<NwsProvider, Error> {
+        let (lat, lon) = provider::parse_lat_long(&location.lat_long).map_err(Error::Config)?;
+
+        Ok(NwsProvider {
+            base_url: config.providers.nws_base_url.clone(),
+            user_agent: config.providers.nws_user_agent.clone(),
+            lat,
+            lon,
+            hourly_hours: config.horizons.hourly_hours,
+            daily_days: config.horizons.daily_days,
+            point: Mutex::new(None),
+        })
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let client = reqwest::blocking::Client::new();
+
+        let resp = quota::send(self.name(), client.get(url).header("User-Agent", &self.user_agent).header("Accept", "application/geo+json"))?;
+
+        provider::parse_json::<T>(self.name(), resp)
+    }
+
+    //The gridpoint forecast urls come from the points lookup, a failed lookup is tried again next pull
+    fn get_point(&self) -> Result<PointProperties, Error> {
+        let mut point = self.point.lock().unwrap();
+
+        if let Some(point) = point.as_ref() {
+            return Ok(point.clone());
+        }
+
+        let root = self.get_json::<PointRoot>(&format!("{}/points/{:.4},{:.4}", self.base_url, self.lat, self.lon))?;
+        *point = Some(root.properties.clone());
+
+        Ok(root.properties)
+    }
+}
+
+impl ForecastProvider for NwsProvider {
+    fn name(&self) -> &'static str {
+        "nws"
+    }
+
+    fn get_hourly(&self) -> Result<Vec<HourlyWeather>, Error> {
+        println!("hitting NWS hourly API");
+        let point = self.get_point()?;
+        let root = self.get_json::<ForecastRoot>(&point.forecast_hourly)?;
+
+        let horizon = Utc::now() + chrono::Duration::hours(self.hourly_hours);
+        let mut hourlyvec = vec![];
+
+        for period in root.properties.periods.iter() {
+            let weather_time = parse_time(&period.start_time)?;
+
+            if weather_time > horizon {
+                break;
+            }
+
+            hourlyvec.push(convert_hourly(period, weather_time));
+        }
+
+        Ok(hourlyvec)
+    }
+
+    fn get_daily(&self) -> Result<Vec<DailyWeather>, Error> {
+        println!("hitting NWS daily API");
+        let point = self.get_point()?;
+        let root = self.get_json::<ForecastRoot>(&point.forecast)?;
+
+        convert_daily(&root.properties.periods, Utc::now() + chrono::Duration::days(self.daily_days))
+    }
+}
+
+//Periods alternate day/night, a day's low is the night that follows it.
+//After dark the first period is "Tonight" with no high, so today is skipped, and a last day
+//whose night is past the end of the forecast has no low, so it's left out
+fn convert_daily(periods: &[Period], horizon: DateTime<Utc>) -> Result<Vec<DailyWeather>, Error> {
+    let mut dailyvec = vec![];
+
+    for (i, period) in periods.iter().enumerate() {
+        if !period.is_daytime {
+            continue;
+        }
+
+        let night = match periods.get(i + 1) {
+            Some(night) if !night.is_daytime => night,
+            _ => continue,
+        };
+
+        let weather_time = parse_time(&period.start_time)?;
+
+        if weather_time > horizon {
+            break;
+        }
+
+        let code = weather_code::to_weather_code(&period.icon, &period.short_forecast);
+
+        dailyvec.push(DailyWeather {
+            id: 0,
+            created_at: Utc::now(),
+            weather_time,
+            high: to_fahrenheit(period.temperature, &period.temperature_unit),
+            low: to_fahrenheit(night.temperature, &night.temperature_unit),
+            weather_code: code,
+            moon_phase: MoonPhase::from_date(weather_time),
+            sunrise_time: None,
+            sunset_time: None,
+            raw_weather_code: None,
+        });
+    }
+
+    Ok(dailyvec)
+}
+
+fn convert_hourly(period: &Period, weather_time: DateTime<Utc>) -> HourlyWeather {
+    let temp = to_fahrenheit(period.temperature, &period.temperature_unit);
+    let humidity = period.relative_humidity.as_ref().and_then(|q| q.value);
+    let wind_mph = period.wind_speed.as_deref().map(parse_wind_speed).unwrap_or(0.0);
+    let code = weather_code::to_weather_code(&period.icon, &period.short_forecast);
+
+    HourlyWeather {
+        id: 0,
+        created_at: Utc::now(),
+        weather_time,
+        temp,
+        //Like the other providers, the temperature itself when there's nothing to adjust it by
+        feels_like: calc::apparent_temp(temp, humidity, wind_mph).unwrap_or(temp),
+        weather_code: code,
+        precipitation_type: PrecipitationType::from_weather_code(code),
+        precipitation_chance: period.probability_of_precipitation.as_ref().and_then(|q| q.value).unwrap_or(0.0),
+        humidity,
+        //NWS always reports dewpoint in wmoUnit:degC
+        dew_point: period.dewpoint.as_ref().and_then(|q| q.value).map(calc::c_to_f),
+        raw_weather_code: None,
+    }
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|t| t.into())
+        .map_err(|e| Error::upstream_decode("nws", format!("Couldn't parse NWS period time '{time}', Reason: {e}")))
+}
+
+fn to_fahrenheit(temp: f64, unit: &str) -> f64 {
+    match unit {
+        "C" => calc::c_to_f(temp),
+        _ => temp,
+    }
+}
+
+//"10 mph" or "5 to 10 mph", take the top of the range
+fn parse_wind_speed(wind_speed: &str) -> f64 {
+    wind_speed
+        .split_whitespace()
+        .filter_map(|part| part.parse::<f64>().ok())
+        .next_back()
+        .unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::climacell::WeatherCode;
+    use std::io::{ BufRead, BufReader, Write };
+    use std::net::TcpListener;
+    use std::sync::{ atomic::{ AtomicUsize, Ordering }, Arc };
+    use std::thread;
+
+    //Serves docs/fixtures/nws the way api.weather.gov would, with the recorded urls pointed back at
+    //itself. Returns the base url and how many /points lookups it answered
+    fn fixture_server() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let points = Arc::new(AtomicUsize::new(0));
+
+        let (base, counter) = (base_url.clone(), points.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream).read_line(&mut request_line).unwrap();
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                if path.starts_with("/points/") {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+
+                //A file can't also be the forecast/ directory, so the hourly forecast is saved as forecast_hourly
+                let file = format!("{}/docs/fixtures/nws{}", env!("CARGO_MANIFEST_DIR"), path.replace("/forecast/hourly", "/forecast_hourly"));
+                let response = match std::fs::read_to_string(file) {
+                    Ok(body) => {
+                        let body = body.replace("https://api.weather.gov", &base);
+                        format!("HTTP/1.1 200 OK\r\nContent-Type: application/geo+json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+                    }
+                    Err(_) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                };
+
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (base_url, points)
+    }
+
+    fn fixture_provider() -> (NwsProvider, Arc<AtomicUsize>) {
+        let (base_url, points) = fixture_server();
+        let mut config = Config::default();
+        config.providers.nws_base_url = base_url;
+
+        //Every request goes through the quota tracker, which counts from zero without a DB
+        quota::init(Box::leak(Box::new(Config::default())));
+
+        let location = LocationConfig { lat_long: "35.9,-79.05".to_string(), ..LocationConfig::default() };
+
+        (NwsProvider::from_config(&config, &location).unwrap(), points)
+    }
+
+    fn period(temperature: f64, humidity: Option<f64>, wind_speed: &str) -> Period {
+        Period {
+            start_time: "2024-07-12T15:00:00-04:00".to_string(),
+            temperature,
+            temperature_unit: "F".to_string(),
+            relative_humidity: Some(webmodels::Quantity { unit_code: "wmoUnit:percent".to_string(), value: humidity }),
+            wind_speed: Some(wind_speed.to_string()),
+            ..Period::default()
+        }
+    }
+
+    #[test]
+    fn hourly_from_fixtures() {
+        let (provider, _) = fixture_provider();
+        let hourly = provider.get_hourly().unwrap();
+
+        assert_eq!(hourly.len(), 6);
+
+        let first = &hourly[0];
+        assert_eq!(first.weather_time, DateTime::parse_from_rfc3339("2024-04-12T06:00:00Z").unwrap());
+        assert_eq!(first.temp, 58.0);
+        //Too warm for wind chill and too cool for the heat index
+        assert_eq!(first.feels_like, 58.0);
+        assert_eq!(first.humidity, Some(74.0));
+        assert!((first.dew_point.unwrap() - 46.94).abs() < 0.01);
+        assert_eq!(first.precipitation_chance, 2.0);
+        assert_eq!(first.weather_code, WeatherCode::Mostly_Clear);
+        assert_eq!(first.precipitation_type, PrecipitationType::NA);
+
+        let last = &hourly[5];
+        assert_eq!(last.temp, 63.0);
+        assert_eq!(last.precipitation_chance, 40.0);
+        assert_eq!(last.weather_code, WeatherCode::Thunderstorm);
+    }
+
+    #[test]
+    fn daily_from_fixtures() {
+        let (provider, _) = fixture_provider();
+        let daily = provider.get_daily().unwrap();
+
+        assert_eq!(daily.len(), 5);
+
+        let highs_lows: Vec<(f64, f64)> = daily.iter().map(|d| (d.high, d.low)).collect();
+        assert_eq!(highs_lows, vec![(74.0, 52.0), (71.0, 47.0), (78.0, 53.0), (82.0, 58.0), (68.0, 50.0)]);
+
+        assert_eq!(daily[0].weather_time, DateTime::parse_from_rfc3339("2024-04-12T10:00:00Z").unwrap());
+        assert_eq!(daily[0].weather_code, WeatherCode::Thunderstorm);
+        assert_eq!(daily[1].weather_code, WeatherCode::Clear);
+        assert_eq!(daily[4].weather_code, WeatherCode::Rain);
+    }
+
+    #[test]
+    fn points_lookup_is_cached() {
+        let (provider, points) = fixture_provider();
+
+        provider.get_hourly().unwrap();
+        provider.get_daily().unwrap();
+        provider.get_hourly().unwrap();
+
+        assert_eq!(points.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unpaired_last_day_is_dropped() {
+        let forecast = std::fs::read_to_string(format!("{}/docs/fixtures/nws/gridpoints/RAH/62,58/forecast", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let root: ForecastRoot = serde_json::from_str(&forecast).unwrap();
+
+        //Cut off before the last night, so the last day has no low
+        let periods = &root.properties.periods[..9];
+        let daily = convert_daily(periods, Utc::now()).unwrap();
+
+        assert_eq!(daily.len(), 4);
+        assert_eq!(daily.last().map(|d| (d.high, d.low)), Some((82.0, 58.0)));
+    }
+
+    #[test]
+    fn feels_like_without_humidity() {
+        let time = Utc::now();
+
+        //No humidity for the heat index, so no adjustment
+        assert_eq!(convert_hourly(&period(92.0, None, "5 mph"), time).feels_like, 92.0);
+        assert!(convert_hourly(&period(92.0, Some(60.0), "5 mph"), time).feels_like > 92.0);
+
+        //Wind chill doesn't need it
+        assert!(convert_hourly(&period(30.0, None, "5 to 15 mph"), time).feels_like < 30.0);
+    }
+}
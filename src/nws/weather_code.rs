@@ -0,0 +1,83 @@
+use crate::climacell::WeatherCode;
+
+//Icon urls look like .../icons/land/day/tsra_sct,40/rain,20?size=small, the first condition wins
+fn icon_condition(icon: &str) -> &str {
+    let path = icon.split('?').next().unwrap_or_default();
+
+    match path.split("/land/").nth(1) {
+        Some(rest) => {
+            let condition = rest.split('/').nth(1).unwrap_or_default();
+            condition.split(',').next().unwrap_or_default()
+        }
+        None => "",
+    }
+}
+
+pub fn to_weather_code(icon: &str, short_forecast: &str) -> WeatherCode {
+    match icon_condition(icon) {
+        "skc" | "hot" | "cold" => WeatherCode::Clear,
+        "few" => WeatherCode::Mostly_Clear,
+        "sct" => WeatherCode::Partly_Cloudy,
+        "bkn" => WeatherCode::Mostly_Cloudy,
+        "ovc" => WeatherCode::Cloudy,
+        "wind_skc" | "wind_few" | "wind_sct" | "wind_bkn" | "wind_ovc" => WeatherCode::Wind,
+        "snow" | "rain_snow" => WeatherCode::Snow,
+        "blizzard" => WeatherCode::Heavy_Snow,
+        "sleet" | "rain_sleet" | "snow_sleet" => WeatherCode::Ice_Pellets,
+        "fzra" | "rain_fzra" | "snow_fzra" => WeatherCode::Freezing_Rain,
+        "rain" => WeatherCode::Rain,
+        "rain_showers" | "rain_showers_hi" => WeatherCode::Light_Rain,
+        "tsra" | "tsra_sct" | "tsra_hi" | "tornado" => WeatherCode::Thunderstorm,
+        "hurricane" | "tropical_storm" => WeatherCode::Strong_Wind,
+        "fog" => WeatherCode::Fog,
+        "haze" | "smoke" | "dust" => WeatherCode::Light_Fog,
+        _ => from_short_forecast(short_forecast),
+    }
+}
+
+//Fallback for icons we don't recognize
+fn from_short_forecast(short_forecast: &str) -> WeatherCode {
+    let text = short_forecast.to_lowercase();
+
+    if text.contains("thunder") {
+        WeatherCode::Thunderstorm
+    } else if text.contains("freezing drizzle") {
+        WeatherCode::Freezing_Drizzle
+    } else if text.contains("freezing rain") {
+        WeatherCode::Freezing_Rain
+    } else if text.contains("sleet") {
+        WeatherCode::Ice_Pellets
+    } else if text.contains("heavy snow") || text.contains("blizzard") {
+        WeatherCode::Heavy_Snow
+    } else if text.contains("flurries") {
+        WeatherCode::Flurries
+    } else if text.contains("snow") {
+        WeatherCode::Snow
+    } else if text.contains("drizzle") {
+        WeatherCode::Drizzle
+    } else if text.contains("heavy rain") {
+        WeatherCode::Heavy_Rain
+    } else if text.contains("showers") || text.contains("light rain") {
+        WeatherCode::Light_Rain
+    } else if text.contains("rain") {
+        WeatherCode::Rain
+    } else if text.contains("fog") {
+        WeatherCode::Fog
+    } else if text.contains("haze") || text.contains("smoke") {
+        WeatherCode::Light_Fog
+    } else if text.contains("windy") || text.contains("breezy") {
+        WeatherCode::Wind
+    } else if text.contains("mostly cloudy") {
+        WeatherCode::Mostly_Cloudy
+    } else if text.contains("partly") {
+        WeatherCode::Partly_Cloudy
+    } else if text.contains("mostly sunny") || text.contains("mostly clear") {
+        WeatherCode::Mostly_Clear
+    } else if text.contains("cloudy") || text.contains("overcast") {
+        WeatherCode::Cloudy
+    } else if text.contains("sunny") || text.contains("clear") {
+        WeatherCode::Clear
+    } else {
+        WeatherCode::Unknown
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointRoot {
+    pub properties: PointProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointProperties {
+    pub forecast: String,
+    pub forecast_hourly: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForecastRoot {
+    pub properties: ForecastProperties,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForecastProperties {
+    pub periods: Vec<Period>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Period {
+    pub number: i32,
+    pub name: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub is_daytime: bool,
+    pub temperature: f64,
+    pub temperature_unit: String,
+    pub probability_of_precipitation: Option<Quantity>,
+    pub dewpoint: Option<Quantity>,
+    pub relative_humidity: Option<Quantity>,
+    pub wind_speed: Option<String>,
+    pub icon: String,
+    pub short_forecast: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quantity {
+    pub unit_code: String,
+    pub value: Option<f64>,
+}
@@ -0,0 +1,256 @@
+pub mod weather_code;
+pub mod webmodels;
+
+use crate::climacell::{ models::{ DailyWeather, HourlyWeather }, MoonPhase, PrecipitationType };
+use crate::{ config::{ Config, LocationConfig }, error::Error, provider::{ self, ForecastProvider }, quota };
+use chrono::{ DateTime, TimeZone, Utc };
+use serde::de::DeserializeOwned;
+use webmodels::{ Daily, DailyRoot, Hourly, HourlyRoot };
+
+//Open-Meteo (api.open-meteo.com), keyless
+pub struct OpenMeteoProvider {
+    base_url: String,
+    lat: f64,
+    lon: f64,
+    hourly_hours: i64,
+    daily_days: i64,
+}
+
+impl OpenMeteoProvider {
+    pub fn from_config(config: &Config, location: &LocationConfig) -> Result<OpenMeteoProvider, Error> {
+        let (lat, lon) = provider::parse_lat_long(&location.lat_long).map_err(Error::Config)?;
+
+        Ok(OpenMeteoProvider {
+            base_url: config.providers.open_meteo_base_url.clone(),
+            lat,
+            lon,
+            hourly_hours: config.horizons.hourly_hours,
+            daily_days: config.horizons.daily_days,
+        })
+    }
+
+    fn get_forecast<T: DeserializeOwned>(&self, params: &[(&str, &str)]) -> Result<T, Error> {
+        let client = reqwest::blocking::Client::new();
+        let lat = self.lat.to_string();
+        let lon = self.lon.to_string();
+
+        let common = [
+            ("latitude", lat.as_str()),
+            ("longitude", lon.as_str()),
+            ("temperature_unit", "fahrenheit"),
+            ("timezone", "auto"),
+            ("timeformat", "unixtime"),
+        ];
+
+        let resp = quota::send(self.name(), client.get(format!("{}/v1/forecast", self.base_url)).query(&common).query(params))?;
+
+        provider::parse_json::<T>(self.name(), resp)
+    }
+}
+
+impl ForecastProvider for OpenMeteoProvider {
+    fn name(&self) -> &'static str {
+        "open_meteo"
+    }
+
+    fn get_hourly(&self) -> Result<Vec<HourlyWeather>, Error> {
+        println!("hitting Open-Meteo hourly API");
+        //Days start at local midnight, so the horizon can reach one day further than it looks
+        let forecast_days = forecast_days((self.hourly_hours + 23) / 24 + 1);
+        let root = self.get_forecast::<HourlyRoot>(
+            &[
+                ("hourly", "temperature_2m,apparent_temperature,relative_humidity_2m,dew_point_2m,precipitation_probability,weather_code"),
+                ("forecast_days", &forecast_days),
+            ]
+        )?;
+
+        to_hourly(&root.hourly, Utc::now(), self.hourly_hours)
+    }
+
+    fn get_daily(&self) -> Result<Vec<DailyWeather>, Error> {
+        println!("hitting Open-Meteo daily API");
+        let root = self.get_forecast::<DailyRoot>(
+            &[
+                ("daily", "weather_code,temperature_2m_max,temperature_2m_min,sunrise,sunset"),
+                ("forecast_days", &forecast_days(self.daily_days + 1)),
+            ]
+        )?;
+
+        to_daily(&root.daily, Utc::now(), self.daily_days)
+    }
+}
+
+fn to_hourly(hourly: &Hourly, now: DateTime<Utc>, hourly_hours: i64) -> Result<Vec<HourlyWeather>, Error> {
+    let horizon = now + chrono::Duration::hours(hourly_hours);
+    let mut hourlyvec = vec![];
+
+    for (i, time) in hourly.time.iter().enumerate() {
+        let weather_time = from_unix(*time)?;
+
+        //Start from the current hour like tomorrow.io does
+        if weather_time + chrono::Duration::hours(1) <= now {
+            continue;
+        }
+
+        if weather_time > horizon {
+            break;
+        }
+
+        let temp = match value_at(&hourly.temperature_2m, i) {
+            Some(t) => t,
+            None => {
+                continue;
+            }
+        };
+
+        let (code, raw_weather_code) = weather_code::convert(value_at(&hourly.weather_code, i));
+
+        hourlyvec.push(HourlyWeather {
+            id: 0,
+            created_at: now,
+            weather_time,
+            temp,
+            feels_like: value_at(&hourly.apparent_temperature, i).unwrap_or(temp),
+            weather_code: code,
+            precipitation_type: PrecipitationType::from_weather_code(code),
+            precipitation_chance: value_at(&hourly.precipitation_probability, i).unwrap_or(0.0),
+            humidity: value_at(&hourly.relative_humidity_2m, i),
+            dew_point: value_at(&hourly.dew_point_2m, i),
+            raw_weather_code,
+        });
+    }
+
+    Ok(hourlyvec)
+}
+
+fn to_daily(daily: &Daily, now: DateTime<Utc>, daily_days: i64) -> Result<Vec<DailyWeather>, Error> {
+    let horizon = now + chrono::Duration::days(daily_days);
+    let mut dailyvec = vec![];
+
+    for (i, time) in daily.time.iter().enumerate() {
+        //Local midnight of each day
+        let weather_time = from_unix(*time)?;
+
+        if weather_time > horizon {
+            break;
+        }
+
+        let (high, low) = match (value_at(&daily.temperature_2m_max, i), value_at(&daily.temperature_2m_min, i)) {
+            (Some(high), Some(low)) => (high, low),
+            _ => {
+                continue;
+            }
+        };
+
+        let (weather_code, raw_weather_code) = weather_code::convert(value_at(&daily.weather_code, i));
+
+        dailyvec.push(DailyWeather {
+            id: 0,
+            created_at: now,
+            weather_time,
+            high,
+            low,
+            weather_code,
+            //Open-Meteo doesn't report one
+            moon_phase: MoonPhase::from_date(weather_time),
+            sunrise_time: value_at(&daily.sunrise, i).and_then(|t| from_unix(t).ok()),
+            sunset_time: value_at(&daily.sunset, i).and_then(|t| from_unix(t).ok()),
+            raw_weather_code,
+        });
+    }
+
+    Ok(dailyvec)
+}
+
+//Open-Meteo returns parallel arrays with nulls for missing values
+fn value_at<T: Copy>(values: &[Option<T>], i: usize) -> Option<T> {
+    values.get(i).copied().flatten()
+}
+
+fn from_unix(time: i64) -> Result<DateTime<Utc>, Error> {
+    match Utc.timestamp_opt(time, 0).single() {
+        Some(t) => Ok(t),
+        None => Err(Error::upstream_decode("open_meteo", format!("Couldn't parse Open-Meteo time '{time}'"))),
+    }
+}
+
+//Open-Meteo serves at most 16 days
+fn forecast_days(days: i64) -> String {
+    days.clamp(1, 16).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::climacell::WeatherCode;
+
+    fn fixture<T: DeserializeOwned>(name: &str) -> T {
+        let body = std::fs::read(format!("{}/docs/fixtures/open_meteo/{name}.json", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, 12, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn hourly_from_fixture() {
+        let root: HourlyRoot = fixture("hourly");
+        //Halfway through the first hour, so it's still the current one
+        let hourly = to_hourly(&root.hourly, at(4, 30), 4).unwrap();
+
+        //The hour without a temperature is skipped, the one past the horizon dropped
+        let times: Vec<_> = hourly.iter().map(|h| h.weather_time).collect();
+        assert_eq!(times, [at(4, 0), at(5, 0), at(7, 0), at(8, 0)]);
+
+        assert_eq!(hourly[0].temp, 55.1);
+        assert_eq!(hourly[0].feels_like, 53.0);
+        assert_eq!(hourly[0].humidity, Some(80.0));
+        assert_eq!(hourly[0].dew_point, Some(49.0));
+        assert_eq!(hourly[0].weather_code, WeatherCode::Clear);
+        assert_eq!(hourly[0].raw_weather_code, None);
+        assert_eq!(hourly[0].created_at, at(4, 30));
+
+        //Missing values fall back
+        assert_eq!(hourly[2].feels_like, 52.9);
+        assert_eq!(hourly[2].precipitation_chance, 0.0);
+        assert_eq!(hourly[2].weather_code, WeatherCode::Unknown);
+        assert_eq!(hourly[2].raw_weather_code.as_deref(), Some("42"));
+
+        assert_eq!(hourly[3].humidity, None);
+        assert_eq!(hourly[3].weather_code, WeatherCode::Unknown);
+        assert_eq!(hourly[3].raw_weather_code, None);
+
+        assert!(to_hourly(&root.hourly, at(5, 0), 4).unwrap().iter().all(|h| h.weather_time >= at(5, 0)));
+    }
+
+    #[test]
+    fn daily_from_fixture() {
+        let root: DailyRoot = fixture("daily");
+        let daily = to_daily(&root.daily, at(12, 0), 3).unwrap();
+
+        //The day without a high is skipped
+        assert_eq!(daily.len(), 3);
+        assert_eq!(daily[0].weather_time, at(4, 0));
+        assert_eq!(daily[0].high, 78.4);
+        assert_eq!(daily[0].low, 55.0);
+        assert_eq!(daily[0].weather_code, WeatherCode::Thunderstorm);
+        assert_eq!(daily[0].sunrise_time, Some(at(10, 52)));
+        assert_eq!(daily[0].sunset_time, Some(at(23, 41)));
+        assert_eq!(daily[0].moon_phase, MoonPhase::from_date(at(4, 0)));
+
+        assert_eq!(daily[1].weather_code, WeatherCode::Unknown);
+        assert_eq!(daily[1].raw_weather_code.as_deref(), Some("200"));
+
+        assert_eq!(daily[2].weather_time, at(4, 0) + chrono::Duration::days(3));
+        assert_eq!(daily[2].weather_code, WeatherCode::Mostly_Clear);
+        assert_eq!(daily[2].sunrise_time, None);
+    }
+
+    #[test]
+    fn forecast_days_stay_within_what_open_meteo_serves() {
+        assert_eq!(forecast_days(0), "1");
+        assert_eq!(forecast_days(8), "8");
+        assert_eq!(forecast_days(30), "16");
+    }
+}
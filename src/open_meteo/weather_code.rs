@@ -0,0 +1,74 @@
+use crate::climacell::WeatherCode;
+
+//WMO 4677 weather interpretation codes, the subset Open-Meteo reports
+pub fn to_weather_code(wmo: u8) -> WeatherCode {
+    match wmo {
+        0 => WeatherCode::Clear,
+        1 => WeatherCode::Mostly_Clear,
+        2 => WeatherCode::Partly_Cloudy,
+        3 => WeatherCode::Cloudy,
+        45 | 48 => WeatherCode::Fog,
+        51 | 53 | 55 => WeatherCode::Drizzle,
+        56 | 57 => WeatherCode::Freezing_Drizzle,
+        61 | 80 => WeatherCode::Light_Rain,
+        63 | 81 => WeatherCode::Rain,
+        65 | 82 => WeatherCode::Heavy_Rain,
+        66 => WeatherCode::Light_Freezing_Rain,
+        67 => WeatherCode::Heavy_Freezing_Rain,
+        71 | 85 => WeatherCode::Light_Snow,
+        73 => WeatherCode::Snow,
+        75 | 86 => WeatherCode::Heavy_Snow,
+        77 => WeatherCode::Flurries,
+        95 | 96 | 99 => WeatherCode::Thunderstorm,
+        _ => WeatherCode::Unknown,
+    }
+}
+
+//A code we don't map is still stored and served as raw_weather_code, like tomorrow.io's
+pub fn convert(wmo: Option<u8>) -> (WeatherCode, Option<String>) {
+    match wmo.map(|wmo| (wmo, to_weather_code(wmo))) {
+        Some((wmo, WeatherCode::Unknown)) => (WeatherCode::Unknown, Some(wmo.to_string())),
+        Some((_, code)) => (code, None),
+        None => (WeatherCode::Unknown, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wmo_codes_map_to_weather_codes() {
+        let cases = [
+            (0, WeatherCode::Clear),
+            (1, WeatherCode::Mostly_Clear),
+            (2, WeatherCode::Partly_Cloudy),
+            (3, WeatherCode::Cloudy),
+            (48, WeatherCode::Fog),
+            (53, WeatherCode::Drizzle),
+            (57, WeatherCode::Freezing_Drizzle),
+            (80, WeatherCode::Light_Rain),
+            (63, WeatherCode::Rain),
+            (82, WeatherCode::Heavy_Rain),
+            (66, WeatherCode::Light_Freezing_Rain),
+            (67, WeatherCode::Heavy_Freezing_Rain),
+            (85, WeatherCode::Light_Snow),
+            (73, WeatherCode::Snow),
+            (86, WeatherCode::Heavy_Snow),
+            (77, WeatherCode::Flurries),
+            (99, WeatherCode::Thunderstorm),
+        ];
+
+        for (wmo, code) in cases {
+            assert_eq!(to_weather_code(wmo), code, "WMO {wmo}");
+            assert_eq!(convert(Some(wmo)), (code, None), "WMO {wmo}");
+        }
+    }
+
+    #[test]
+    fn unmapped_codes_keep_the_raw_code() {
+        assert_eq!(convert(Some(42)), (WeatherCode::Unknown, Some("42".to_string())));
+        assert_eq!(convert(Some(4)), (WeatherCode::Unknown, Some("4".to_string())));
+        assert_eq!(convert(None), (WeatherCode::Unknown, None));
+    }
+}
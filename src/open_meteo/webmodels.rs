@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+
+//Requested with timeformat=unixtime, so every time is epoch seconds
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HourlyRoot {
+    pub hourly: Hourly,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hourly {
+    pub time: Vec<i64>,
+    pub temperature_2m: Vec<Option<f64>>,
+    pub apparent_temperature: Vec<Option<f64>>,
+    pub relative_humidity_2m: Vec<Option<f64>>,
+    pub dew_point_2m: Vec<Option<f64>>,
+    pub precipitation_probability: Vec<Option<f64>>,
+    pub weather_code: Vec<Option<u8>>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyRoot {
+    pub daily: Daily,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Daily {
+    pub time: Vec<i64>,
+    pub weather_code: Vec<Option<u8>>,
+    pub temperature_2m_max: Vec<Option<f64>>,
+    pub temperature_2m_min: Vec<Option<f64>>,
+    pub sunrise: Vec<Option<i64>>,
+    pub sunset: Vec<Option<i64>>,
+}
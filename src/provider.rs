@@ -0,0 +1,60 @@
+use crate::climacell::{ self, models::{ DailyWeather, HourlyWeather } };
+use crate::{ config::{ Config, LocationConfig }, error::Error, nws, open_meteo };
+use crate::wunder::{ self, models::InstModel };
+use reqwest::blocking::Response;
+use serde::de::DeserializeOwned;
+
+//Source of hourly and daily forecasts
+pub trait ForecastProvider: Send {
+    fn name(&self) -> &'static str;
+    fn get_hourly(&self) -> Result<Vec<HourlyWeather>, Error>;
+    fn get_daily(&self) -> Result<Vec<DailyWeather>, Error>;
+}
+
+//Source of current station observations
+pub trait ObservationProvider: Send {
+    fn name(&self) -> &'static str;
+    fn get_inst(&self) -> Result<InstModel, Error>;
+}
+
+pub fn forecast_provider(config: &Config, location: &LocationConfig) -> Result<Box<dyn ForecastProvider>, Error> {
+    let name = config.forecast_provider_name(location);
+
+    match name {
+        "climacell" => Ok(Box::new(climacell::ClimacellProvider::from_config(config, location)?)),
+        "nws" => Ok(Box::new(nws::NwsProvider::from_config(config, location)?)),
+        "open_meteo" => Ok(Box::new(open_meteo::OpenMeteoProvider::from_config(config, location)?)),
+        _ => Err(Error::Config(format!("Unknown forecast provider '{name}'"))),
+    }
+}
+
+//"none" when a local station pushes its own observations
+pub fn observation_provider(config: &Config, location: &LocationConfig) -> Result<Option<Box<dyn ObservationProvider>>, Error> {
+    let name = config.observation_provider_name(location);
+
+    match name {
+        "wunder" => Ok(Some(Box::new(wunder::WunderProvider::from_config(config, location)?))),
+        "none" => Ok(None),
+        _ => Err(Error::Config(format!("Unknown observation provider '{name}'"))),
+    }
+}
+
+//Like resp.json(), but the error names the field that didn't fit, e.g. data.timelines[0].intervals[3].values.temperature
+pub fn parse_json<T: DeserializeOwned>(provider: &'static str, resp: Response) -> Result<T, Error> {
+    let body = resp.text().map_err(|e| Error::upstream_http(provider, e))?;
+
+    serde_path_to_error::deserialize(&mut serde_json::Deserializer::from_str(&body))
+        .map_err(|e| Error::upstream_decode(provider, format!("{}: {}", e.path(), e.inner())))
+}
+
+//lat_long is "lat,lon", as tomorrow.io takes it
+pub fn parse_lat_long(lat_long: &str) -> Result<(f64, f64), String> {
+    match lat_long.split_once(',') {
+        Some((lat, lon)) =>
+            match (lat.trim().parse::<f64>(), lon.trim().parse::<f64>()) {
+                (Ok(lat), Ok(lon)) => Ok((lat, lon)),
+                _ => Err(format!("Couldn't parse Lat Long '{lat_long}'")),
+            }
+        None => Err(format!("Couldn't parse Lat Long '{lat_long}'")),
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{ error::Error, ingest::Reading, wunder::models::InstModel, zone::Zone };
+use chrono::{ NaiveDateTime, TimeZone, Utc };
+use nickel::Params;
+
+//Personal weather station uploads, read straight off the LAN instead of back from weather.com.
+//Wunderground protocol: GET /weatherstation/updateweatherstation.php?ID=..&PASSWORD=..&tempf=..
+//Ecowitt protocol: POST form to the "customized" server path, default /data/report/
+
+//Where an upload's fields are read from, the query string or form body nickel parsed
+pub trait Fields {
+    fn field(&self, key: &str) -> Option<&str>;
+}
+
+impl Fields for Params {
+    fn field(&self, key: &str) -> Option<&str> {
+        self.get(key)
+    }
+}
+
+pub fn from_wunderground(params: &impl Fields, password: Option<&str>, zone: Zone) -> Result<InstModel, Error> {
+    //ID is matched to a location by the caller
+    check_credential(params, "PASSWORD", password)?;
+
+    Ok(
+        (Reading {
+            obs_time_utc: parse_dateutc(params)?,
+            temp: required(params, "tempf")?,
+            humidity: required(params, "humidity")?,
+            dewpt: optional(params, "dewptf"),
+            wind_speed: optional(params, "windspeedmph").unwrap_or(0.0),
+            wind_gust: optional(params, "windgustmph").unwrap_or(0.0),
+            winddir: optional(params, "winddir").unwrap_or(0.0),
+            pressure: optional(params, "baromin").unwrap_or(0.0),
+            //Rain over the last hour, the closest thing to a rate this protocol has
+            precip_rate: optional(params, "rainin").unwrap_or(0.0),
+            precip_total: optional(params, "dailyrainin").unwrap_or(0.0),
+            solar_radiation: optional(params, "solarradiation").unwrap_or(0.0),
+            uv: optional(params, "UV").unwrap_or(0.0),
+        }).into_inst(zone)
+    )
+}
+
+pub fn from_ecowitt(params: &impl Fields, passkey: Option<&str>, zone: Zone) -> Result<InstModel, Error> {
+    check_credential(params, "PASSKEY", passkey)?;
+
+    Ok(
+        (Reading {
+            obs_time_utc: parse_dateutc(params)?,
+            temp: required(params, "tempf")?,
+            humidity: required(params, "humidity")?,
+            dewpt: None,
+            wind_speed: optional(params, "windspeedmph").unwrap_or(0.0),
+            wind_gust: optional(params, "windgustmph").unwrap_or(0.0),
+            winddir: optional(params, "winddir").unwrap_or(0.0),
+            pressure: optional(params, "baromrelin").unwrap_or(0.0),
+            precip_rate: optional(params, "rainratein").unwrap_or(0.0),
+            precip_total: optional(params, "dailyrainin").unwrap_or(0.0),
+            solar_radiation: optional(params, "solarradiation").unwrap_or(0.0),
+            uv: optional(params, "uv").unwrap_or(0.0),
+        }).into_inst(zone)
+    )
+}
+
+//The upload routes are only mounted with a credential configured, an upload without a matching one is refused
+fn check_credential(params: &impl Fields, key: &str, expected: Option<&str>) -> Result<(), Error> {
+    match (expected, params.field(key)) {
+        (Some(expected), Some(provided)) if provided == expected => Ok(()),
+        (None, _) => Err(Error::Unauthorized(format!("station uploads need a {key} configured"))),
+        _ => Err(Error::Unauthorized(format!("{key} doesn't match the configured station"))),
+    }
+}
+
+//Stations send "now" when they don't keep a clock
+fn parse_dateutc(params: &impl Fields) -> Result<chrono::DateTime<Utc>, Error> {
+    match params.field("dateutc") {
+        Some("now") | None => Ok(Utc::now()),
+        Some(date) =>
+            match NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S") {
+                Ok(naive) => Ok(Utc.from_utc_datetime(&naive)),
+                Err(e) => Err(Error::Validation(format!("Couldn't parse dateutc '{date}', Reason: {e}"))),
+            }
+    }
+}
+
+fn required(params: &impl Fields, key: &str) -> Result<f64, Error> {
+    optional(params, key).ok_or_else(|| Error::Validation(format!("missing or invalid {key}")))
+}
+
+//Wunderground uploads use -9999 for sensors that aren't reporting
+fn optional(params: &impl Fields, key: &str) -> Option<f64> {
+    params
+        .field(key)
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|value| *value > -9999.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl Fields for Vec<(&str, &str)> {
+        fn field(&self, key: &str) -> Option<&str> {
+            self.iter().find(|(name, _)| *name == key).map(|(_, value)| *value)
+        }
+    }
+
+    fn upload() -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("ID", "KXX1"),
+            ("PASSWORD", "secret"),
+            ("dateutc", "2024-04-12 14:00:00"),
+            ("tempf", "72.4"),
+            ("humidity", "50"),
+            ("dewptf", "-9999"),
+            ("windspeedmph", "4.6"),
+            ("winddir", "144"),
+            ("baromin", "30.05"),
+            ("dailyrainin", "0.12"),
+            ("UV", "3"),
+        ]
+    }
+
+    fn with(key: &'static str, value: &'static str) -> Vec<(&'static str, &'static str)> {
+        let mut params = without(key);
+        params.push((key, value));
+        params
+    }
+
+    fn without(key: &str) -> Vec<(&'static str, &'static str)> {
+        upload().into_iter().filter(|(name, _)| *name != key).collect()
+    }
+
+    #[test]
+    fn matching_credential_is_accepted() {
+        assert!(from_wunderground(&upload(), Some("secret"), Zone::Host).is_ok());
+        assert!(from_ecowitt(&with("PASSKEY", "abc123"), Some("abc123"), Zone::Host).is_ok());
+    }
+
+    #[test]
+    fn wrong_or_missing_credential_is_refused() {
+        assert!(matches!(from_wunderground(&upload(), Some("other"), Zone::Host), Err(Error::Unauthorized(_))));
+        assert!(matches!(from_wunderground(&without("PASSWORD"), Some("secret"), Zone::Host), Err(Error::Unauthorized(_))));
+        assert!(matches!(from_ecowitt(&upload(), Some("abc123"), Zone::Host), Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn uploads_are_refused_without_a_configured_credential() {
+        assert!(matches!(from_wunderground(&upload(), None, Zone::Host), Err(Error::Unauthorized(_))));
+        assert!(matches!(from_wunderground(&with("PASSWORD", ""), None, Zone::Host), Err(Error::Unauthorized(_))));
+        assert!(matches!(from_ecowitt(&with("PASSKEY", "abc123"), None, Zone::Host), Err(Error::Unauthorized(_))));
+    }
+
+    #[test]
+    fn wunderground_fields_are_parsed() {
+        let inst = from_wunderground(&upload(), Some("secret"), Zone::parse("America/New_York").unwrap()).unwrap();
+
+        assert_eq!(inst.obs_time_utc, Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap());
+        //On the location's clock, not the server's
+        assert_eq!(inst.obs_time_local.to_string(), "2024-04-12 10:00:00");
+        assert_eq!(inst.temp, 72);
+        assert_eq!(inst.humidity, 50);
+        //-9999 is a sensor that isn't reporting, the dew point is worked out instead
+        assert_eq!(inst.dewpt, 53);
+        assert_eq!(inst.wind_speed, 5);
+        assert_eq!(inst.wind_gust, 0);
+        assert_eq!(inst.winddir, 144);
+        assert!((inst.pressure - 30.05).abs() < 1e-9);
+        assert!((inst.precip_total - 0.12).abs() < 1e-9);
+        assert_eq!(inst.uv, 3.0);
+    }
+
+    #[test]
+    fn ecowitt_fields_are_parsed() {
+        let mut params = with("PASSKEY", "abc123");
+        params.extend([("baromrelin", "29.91"), ("rainratein", "0.4"), ("uv", "5"), ("UV", "9")]);
+        let inst = from_ecowitt(&params, Some("abc123"), Zone::Host).unwrap();
+
+        assert!((inst.pressure - 29.91).abs() < 1e-9);
+        assert!((inst.precip_rate - 0.4).abs() < 1e-9);
+        assert_eq!(inst.uv, 5.0);
+    }
+
+    #[test]
+    fn temperature_and_humidity_are_required() {
+        assert!(matches!(from_wunderground(&without("tempf"), Some("secret"), Zone::Host), Err(Error::Validation(_))));
+        assert!(matches!(from_wunderground(&with("humidity", "-9999"), Some("secret"), Zone::Host), Err(Error::Validation(_))));
+        assert!(matches!(from_wunderground(&with("tempf", "warm"), Some("secret"), Zone::Host), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn dateutc_is_now_or_a_utc_time() {
+        let before = Utc::now();
+        let inst = from_wunderground(&with("dateutc", "now"), Some("secret"), Zone::Host).unwrap();
+        assert!(inst.obs_time_utc >= before);
+
+        let inst = from_wunderground(&without("dateutc"), Some("secret"), Zone::Host).unwrap();
+        assert!(inst.obs_time_utc >= before);
+
+        assert!(matches!(from_wunderground(&with("dateutc", "2024-04-12T14:00"), Some("secret"), Zone::Host), Err(Error::Validation(_))));
+    }
+}
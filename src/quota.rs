@@ -0,0 +1,384 @@
+use crate::{ config::{ Config, QuotaConfig, QuotaLimits }, error::Error, forecast_db, metrics };
+use chrono::{ DateTime, Duration, DurationRound, Utc };
+use reqwest::{ blocking::{ RequestBuilder, Response }, header::HeaderMap, StatusCode };
+use serde::Serialize;
+use std::{ collections::BTreeMap, sync::{ Mutex, OnceLock } };
+
+//Request accounting per provider, shared by every location's cache loop. Counts are kept per UTC hour
+//and day in api_usage, so a restart picks up where it left off instead of starting a fresh quota.
+//Providers that send rate-limit headers (tomorrow.io) can cut us off before our own limits do,
+//and a 429 blocks the provider until its Retry-After
+static TRACKER: OnceLock<QuotaTracker> = OnceLock::new();
+
+pub struct QuotaTracker {
+    limits: &'static QuotaConfig,
+    usage: Mutex<BTreeMap<String, Usage>>,
+}
+
+#[derive(Default)]
+struct Usage {
+    hour_start: DateTime<Utc>,
+    hour_requests: u32,
+    day_start: DateTime<Utc>,
+    day_requests: u32,
+    //From the provider's rate-limit headers, when it sends them
+    remaining_hour: Option<u32>,
+    remaining_day: Option<u32>,
+    blocked_until: Option<DateTime<Utc>>,
+}
+
+//Where a request was counted, so one that never got a response can be handed back
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    hour_start: DateTime<Utc>,
+    day_start: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuotaStatus {
+    pub provider: String,
+    pub hour_start: DateTime<Utc>,
+    pub hour_requests: u32,
+    pub hour_limit: Option<u32>,
+    pub remaining_hour: Option<u32>,
+    pub day_start: DateTime<Utc>,
+    pub day_requests: u32,
+    pub day_limit: Option<u32>,
+    pub remaining_day: Option<u32>,
+    pub blocked_until: Option<DateTime<Utc>>,
+}
+
+//Loads this hour's and today's counts. Without the DB counting starts at zero
+pub fn init(config: &'static Config) -> &'static QuotaTracker {
+    let tracker = QuotaTracker { limits: &config.quota, usage: Mutex::new(BTreeMap::new()) };
+    let now = Utc::now();
+
+    {
+        let mut usage = tracker.usage.lock().unwrap();
+
+        //Every provider in use shows up on /forecast/quota, even before its first request
+        for location in config.locations.iter() {
+            for provider in [config.forecast_provider_name(location), config.observation_provider_name(location)] {
+                if provider != "none" {
+                    usage.entry(provider.to_string()).or_default().roll(now);
+                }
+            }
+        }
+
+        match forecast_db::get_api_usage_db(hour_start(now), day_start(now)) {
+            Ok(rows) => {
+                for (provider, period, requests) in rows {
+                    let entry = usage.entry(provider).or_default();
+                    entry.roll(now);
+
+                    match period.as_str() {
+                        "hour" => entry.hour_requests = requests.max(0) as u32,
+                        "day" => entry.day_requests = requests.max(0) as u32,
+                        _ => {}
+                    }
+                }
+            }
+            Err(e) => {
+                metrics::report("Couldn't load API usage, counting from zero", &e);
+            }
+        }
+    }
+
+    TRACKER.get_or_init(|| tracker)
+}
+
+pub fn get() -> &'static QuotaTracker {
+    TRACKER.get().expect("Quota read before quota::init")
+}
+
+//Every provider request goes through here, so it's refused once over quota and counted once it got a response.
+//The request is counted before it's sent, so cache loops sharing a provider can't both take its last one
+pub fn send(provider: &'static str, request: RequestBuilder) -> Result<Response, Error> {
+    let tracker = get();
+
+    let slot = match tracker.take(provider, Utc::now()) {
+        Ok(slot) => slot,
+        Err(until) => {
+            return Err(Error::QuotaExceeded { provider, until });
+        }
+    };
+
+    let resp = match request.send() {
+        Ok(resp) => resp,
+        Err(e) => {
+            tracker.give_back(provider, slot);
+            return Err(Error::upstream_http(provider, e));
+        }
+    };
+    tracker.record(provider, slot, resp.status(), resp.headers(), Utc::now());
+
+    //Counted either way, but an error status is the provider's failure, not something to deserialize
+    resp.error_for_status().map_err(|e| Error::upstream_http(provider, e))
+}
+
+impl QuotaTracker {
+    //Whether a pull is worth starting, send does its own check as it counts the request.
+    //Err holds when the provider can next be asked
+    pub fn check(&self, provider: &str, now: DateTime<Utc>) -> Result<(), DateTime<Utc>> {
+        let limits = self.limits.limits(provider);
+        let mut usage = self.usage.lock().unwrap();
+
+        let usage = match usage.get_mut(provider) {
+            Some(usage) => usage,
+            None => {
+                return Ok(());
+            }
+        };
+
+        usage.roll(now);
+        usage.available(limits, now)
+    }
+
+    //check and count the request under the same lock
+    fn take(&self, provider: &str, now: DateTime<Utc>) -> Result<Slot, DateTime<Utc>> {
+        let limits = self.limits.limits(provider);
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(provider.to_string()).or_default();
+
+        usage.roll(now);
+        usage.available(limits, now)?;
+
+        usage.hour_requests += 1;
+        usage.day_requests += 1;
+
+        Ok(Slot { hour_start: usage.hour_start, day_start: usage.day_start })
+    }
+
+    //The request never reached the provider, unless the hour or day it was counted in has already rolled over
+    fn give_back(&self, provider: &str, slot: Slot) {
+        let mut usage = self.usage.lock().unwrap();
+
+        if let Some(usage) = usage.get_mut(provider) {
+            if usage.hour_start == slot.hour_start {
+                usage.hour_requests = usage.hour_requests.saturating_sub(1);
+            }
+            if usage.day_start == slot.day_start {
+                usage.day_requests = usage.day_requests.saturating_sub(1);
+            }
+        }
+    }
+
+    fn record(&self, provider: &str, slot: Slot, status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) {
+        {
+            let mut usage = self.usage.lock().unwrap();
+            let usage = usage.entry(provider.to_string()).or_default();
+
+            usage.roll(now);
+
+            //tomorrow.io's names, weather.com only answers 429
+            if let Some(remaining) = header_u32(headers, "x-ratelimit-remaining-hour") {
+                usage.remaining_hour = Some(remaining);
+            }
+            if let Some(remaining) = header_u32(headers, "x-ratelimit-remaining-day") {
+                usage.remaining_day = Some(remaining);
+            }
+
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let until = match header_u32(headers, "retry-after") {
+                    Some(seconds) => now + Duration::seconds(seconds as i64),
+                    None => usage.hour_start + Duration::hours(1),
+                };
+
+                println!("{provider} rate limited us, holding off until {until}");
+                usage.blocked_until = Some(until);
+            }
+        }
+
+        if let Err(e) = forecast_db::bump_api_usage_db(provider, slot.hour_start, slot.day_start) {
+            metrics::report(&format!("Couldn't count {provider} request"), &e);
+        }
+    }
+
+    pub fn status(&self) -> Vec<QuotaStatus> {
+        let now = Utc::now();
+        let mut usage = self.usage.lock().unwrap();
+
+        usage
+            .iter_mut()
+            .map(|(provider, usage)| {
+                usage.roll(now);
+                let limits = self.limits.limits(provider);
+
+                QuotaStatus {
+                    provider: provider.clone(),
+                    hour_start: usage.hour_start,
+                    hour_requests: usage.hour_requests,
+                    hour_limit: Some(limits.per_hour).filter(|limit| *limit > 0),
+                    remaining_hour: usage.remaining_hour,
+                    day_start: usage.day_start,
+                    day_requests: usage.day_requests,
+                    day_limit: Some(limits.per_day).filter(|limit| *limit > 0),
+                    remaining_day: usage.remaining_day,
+                    blocked_until: usage.blocked_until.filter(|until| *until > now),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Usage {
+    //Our limits and whatever the provider told us, against this hour's and day's counts
+    fn available(&self, limits: QuotaLimits, now: DateTime<Utc>) -> Result<(), DateTime<Utc>> {
+        if let Some(until) = self.blocked_until.filter(|until| *until > now) {
+            return Err(until);
+        }
+
+        if self.remaining_day == Some(0) || (limits.per_day > 0 && self.day_requests >= limits.per_day) {
+            return Err(self.day_start + Duration::days(1));
+        }
+
+        if self.remaining_hour == Some(0) || (limits.per_hour > 0 && self.hour_requests >= limits.per_hour) {
+            return Err(self.hour_start + Duration::hours(1));
+        }
+
+        Ok(())
+    }
+
+    //Counts start over with each UTC hour and day
+    fn roll(&mut self, now: DateTime<Utc>) {
+        let hour = hour_start(now);
+        if self.hour_start != hour {
+            self.hour_start = hour;
+            self.hour_requests = 0;
+            self.remaining_hour = None;
+        }
+
+        let day = day_start(now);
+        if self.day_start != day {
+            self.day_start = day;
+            self.day_requests = 0;
+            self.remaining_day = None;
+        }
+    }
+}
+
+fn hour_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.duration_trunc(Duration::hours(1)).unwrap_or(now)
+}
+
+fn day_start(now: DateTime<Utc>) -> DateTime<Utc> {
+    now.duration_trunc(Duration::days(1)).unwrap_or(now)
+}
+
+fn header_u32(headers: &HeaderMap, name: &str) -> Option<u32> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use reqwest::header::HeaderValue;
+
+    fn tracker(per_hour: u32, per_day: u32) -> QuotaTracker {
+        let limits = QuotaConfig { climacell: QuotaLimits { per_hour, per_day }, ..QuotaConfig::default() };
+
+        QuotaTracker { limits: Box::leak(Box::new(limits)), usage: Mutex::new(BTreeMap::new()) }
+    }
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 4, 12, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn hour_limit_rolls_over() {
+        let tracker = tracker(2, 0);
+
+        assert!(tracker.take("climacell", at(10, 5)).is_ok());
+        assert!(tracker.take("climacell", at(10, 30)).is_ok());
+        assert_eq!(tracker.take("climacell", at(10, 59)).unwrap_err(), at(11, 0));
+        assert_eq!(tracker.check("climacell", at(10, 59)), Err(at(11, 0)));
+
+        assert!(tracker.check("climacell", at(11, 0)).is_ok());
+        assert!(tracker.take("climacell", at(11, 0)).is_ok());
+    }
+
+    #[test]
+    fn day_limit_spans_hours_and_rolls_over_at_midnight() {
+        let tracker = tracker(0, 3);
+
+        for hour in [1, 7, 13] {
+            assert!(tracker.take("climacell", at(hour, 0)).is_ok());
+        }
+
+        let midnight = Utc.with_ymd_and_hms(2024, 4, 13, 0, 0, 0).unwrap();
+        assert_eq!(tracker.take("climacell", at(23, 59)).unwrap_err(), midnight);
+        assert!(tracker.take("climacell", midnight).is_ok());
+    }
+
+    #[test]
+    fn unsent_requests_are_given_back() {
+        let tracker = tracker(1, 0);
+
+        let slot = tracker.take("climacell", at(9, 59)).unwrap();
+        assert!(tracker.check("climacell", at(9, 59)).is_err());
+
+        tracker.give_back("climacell", slot);
+        let slot = tracker.take("climacell", at(9, 59)).unwrap();
+
+        //Counted in an hour that's already over, nothing to hand back in this one
+        assert!(tracker.take("climacell", at(10, 0)).is_ok());
+        tracker.give_back("climacell", slot);
+        assert!(tracker.check("climacell", at(10, 1)).is_err());
+    }
+
+    #[test]
+    fn too_many_requests_blocks_until_retry_after() {
+        let tracker = tracker(0, 0);
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("120"));
+
+        let slot = tracker.take("wunder", at(10, 0)).unwrap();
+        tracker.record("wunder", slot, StatusCode::TOO_MANY_REQUESTS, &headers, at(10, 0));
+
+        assert_eq!(tracker.check("wunder", at(10, 1)), Err(at(10, 2)));
+        assert!(tracker.take("wunder", at(10, 1)).is_err());
+        assert!(tracker.check("wunder", at(10, 2)).is_ok());
+    }
+
+    #[test]
+    fn too_many_requests_without_retry_after_blocks_for_the_hour() {
+        let tracker = tracker(0, 0);
+
+        let slot = tracker.take("wunder", at(10, 20)).unwrap();
+        tracker.record("wunder", slot, StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), at(10, 20));
+
+        assert_eq!(tracker.check("wunder", at(10, 45)), Err(at(11, 0)));
+        assert!(tracker.check("wunder", at(11, 0)).is_ok());
+    }
+
+    #[test]
+    fn rate_limit_headers_stop_us_early() {
+        let tracker = tracker(25, 500);
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining-hour", HeaderValue::from_static("0"));
+
+        let slot = tracker.take("climacell", at(10, 0)).unwrap();
+        tracker.record("climacell", slot, StatusCode::OK, &headers, at(10, 0));
+
+        assert_eq!(tracker.check("climacell", at(10, 30)), Err(at(11, 0)));
+        //A new hour forgets what the header said
+        assert!(tracker.check("climacell", at(11, 0)).is_ok());
+    }
+
+    #[test]
+    fn used_up_quota_is_its_own_error() {
+        let until = Utc::now() + Duration::hours(1);
+        TRACKER.get_or_init(|| tracker(0, 0)).usage.lock().unwrap().entry("climacell".to_string()).or_default().blocked_until = Some(until);
+
+        //Refused before anything is sent
+        match send("climacell", reqwest::blocking::Client::new().get("http://127.0.0.1:9/")) {
+            Err(Error::QuotaExceeded { provider, until: refused_until }) => {
+                assert_eq!(provider, "climacell");
+                assert_eq!(refused_until, until);
+            }
+            other => panic!("expected QuotaExceeded, got {other:?}"),
+        }
+    }
+}
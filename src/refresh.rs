@@ -0,0 +1,265 @@
+use crate::{ error::Error, scheduler::Dataset };
+use serde::Serialize;
+use std::sync::{ Condvar, Mutex };
+use std::time::{ Duration, Instant };
+
+//On-demand refreshes for one location. A request wakes the location's cache loop, which pulls the
+//dataset on its next pass. Each request takes a ticket: a request made while another for the same
+//dataset is still waiting shares that ticket, so they all get one upstream call and its outcome.
+//One made while the dataset is being pulled may have missed what it's after, so it waits for the next pull
+pub struct RefreshRequests {
+    tickets: Mutex<Vec<Ticket>>,
+    changed: Condvar,
+}
+
+struct Ticket {
+    dataset: Dataset,
+    requested: u64,
+    //Newest ticket a pull has begun on
+    started: u64,
+    finished: u64,
+    outcome: RefreshOutcome,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum RefreshOutcome {
+    Ok,
+    ProviderError(String),
+    //Failed validation, the previous snapshot is still served
+    Rejected(String),
+    //Pulled and cached, but not saved
+    DbError(String),
+    QuotaExceeded(String),
+    //Not pulled yet when the request stopped waiting, the pull still happens
+    Pending,
+}
+
+impl From<Error> for RefreshOutcome {
+    fn from(e: Error) -> RefreshOutcome {
+        match e {
+            Error::Validation(_) => RefreshOutcome::Rejected(e.to_string()),
+            Error::DbConnect(_) | Error::DbQuery { .. } => RefreshOutcome::DbError(e.to_string()),
+            Error::QuotaExceeded { .. } => RefreshOutcome::QuotaExceeded(e.to_string()),
+            _ => RefreshOutcome::ProviderError(e.to_string()),
+        }
+    }
+}
+
+impl RefreshRequests {
+    pub fn new() -> RefreshRequests {
+        let tickets = [Dataset::Instant, Dataset::Hourly, Dataset::Daily]
+            .into_iter()
+            .map(|dataset| Ticket { dataset, requested: 0, started: 0, finished: 0, outcome: RefreshOutcome::Ok })
+            .collect();
+
+        RefreshRequests { tickets: Mutex::new(tickets), changed: Condvar::new() }
+    }
+
+    //Blocks until the cache loop has pulled the dataset, None if that takes longer than timeout
+    pub fn request(&self, dataset: Dataset, timeout: Duration) -> Option<RefreshOutcome> {
+        let deadline = Instant::now() + timeout;
+        let mut tickets = self.tickets.lock().unwrap();
+
+        let ticket = {
+            let entry = find(&mut tickets, dataset);
+
+            if entry.requested == entry.started {
+                entry.requested += 1;
+            }
+
+            entry.requested
+        };
+
+        self.changed.notify_all();
+
+        loop {
+            let entry = find(&mut tickets, dataset);
+
+            if entry.finished >= ticket {
+                return Some(entry.outcome.clone());
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+
+            tickets = self.changed.wait_timeout(tickets, deadline - now).unwrap().0;
+        }
+    }
+
+    //Datasets someone is waiting on
+    pub fn pending(&self) -> Vec<Dataset> {
+        self.tickets
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.requested > entry.started)
+            .map(|entry| entry.dataset)
+            .collect()
+    }
+
+    //The cache loop's sleep, cut short by a refresh request
+    pub fn wait(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        let mut tickets = self.tickets.lock().unwrap();
+
+        loop {
+            if tickets.iter().any(|entry| entry.requested > entry.started) {
+                return;
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+
+            tickets = self.changed.wait_timeout(tickets, deadline - now).unwrap().0;
+        }
+    }
+
+    //Called before every pull, scheduled or requested, the pull answers every ticket handed out so far
+    pub fn begin(&self, dataset: Dataset) -> u64 {
+        let mut tickets = self.tickets.lock().unwrap();
+        let entry = find(&mut tickets, dataset);
+
+        entry.started = entry.requested;
+        entry.started
+    }
+
+    pub fn finish(&self, dataset: Dataset, ticket: u64, outcome: RefreshOutcome) {
+        {
+            let mut tickets = self.tickets.lock().unwrap();
+            let entry = find(&mut tickets, dataset);
+
+            if ticket >= entry.finished {
+                entry.finished = ticket;
+                entry.outcome = outcome;
+            }
+        }
+
+        self.changed.notify_all();
+    }
+}
+
+fn find(tickets: &mut [Ticket], dataset: Dataset) -> &mut Ticket {
+    tickets.iter_mut().find(|entry| entry.dataset == dataset).expect("Every dataset has a ticket")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread::{ self, JoinHandle };
+
+    const WAIT: Duration = Duration::from_secs(10);
+
+    fn request(requests: &Arc<RefreshRequests>) -> JoinHandle<Option<RefreshOutcome>> {
+        let requests = requests.clone();
+        thread::spawn(move || requests.request(Dataset::Hourly, WAIT))
+    }
+
+    //What the cache loop sees once the request threads have taken their tickets
+    fn until_pending(requests: &RefreshRequests) {
+        let deadline = Instant::now() + WAIT;
+
+        while !requests.pending().contains(&Dataset::Hourly) {
+            assert!(Instant::now() < deadline, "request never showed up");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn waiting_requests_share_a_pull() {
+        let requests = Arc::new(RefreshRequests::new());
+
+        let first = request(&requests);
+        until_pending(&requests);
+        let second = request(&requests);
+        thread::sleep(Duration::from_millis(50));
+
+        let ticket = requests.begin(Dataset::Hourly);
+        assert!(requests.pending().is_empty());
+        requests.finish(Dataset::Hourly, ticket, RefreshOutcome::Ok);
+
+        assert_eq!(ticket, 1);
+        assert_eq!(first.join().unwrap(), Some(RefreshOutcome::Ok));
+        assert_eq!(second.join().unwrap(), Some(RefreshOutcome::Ok));
+    }
+
+    #[test]
+    fn request_during_a_pull_waits_for_the_next_one() {
+        let requests = Arc::new(RefreshRequests::new());
+
+        let first = request(&requests);
+        until_pending(&requests);
+        let ticket = requests.begin(Dataset::Hourly);
+
+        //Arrives while the first pull is on its way upstream
+        let second = request(&requests);
+        until_pending(&requests);
+
+        requests.finish(Dataset::Hourly, ticket, RefreshOutcome::Ok);
+        assert_eq!(first.join().unwrap(), Some(RefreshOutcome::Ok));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!second.is_finished());
+
+        let next = requests.begin(Dataset::Hourly);
+        requests.finish(Dataset::Hourly, next, RefreshOutcome::ProviderError("down".to_string()));
+
+        assert_eq!(next, ticket + 1);
+        assert_eq!(second.join().unwrap(), Some(RefreshOutcome::ProviderError("down".to_string())));
+    }
+
+    #[test]
+    fn scheduled_pull_answers_waiting_requests() {
+        let requests = Arc::new(RefreshRequests::new());
+
+        //Nobody asked, the pull hands out no new ticket
+        let ticket = requests.begin(Dataset::Hourly);
+        requests.finish(Dataset::Hourly, ticket, RefreshOutcome::Ok);
+        assert_eq!(ticket, 0);
+
+        let waiting = request(&requests);
+        until_pending(&requests);
+
+        let ticket = requests.begin(Dataset::Hourly);
+        requests.finish(Dataset::Hourly, ticket, RefreshOutcome::QuotaExceeded("used up".to_string()));
+
+        assert_eq!(waiting.join().unwrap(), Some(RefreshOutcome::QuotaExceeded("used up".to_string())));
+    }
+
+    #[test]
+    fn used_up_quota_is_not_a_provider_error() {
+        let until = chrono::Utc::now();
+        let outcome = RefreshOutcome::from(Error::QuotaExceeded { provider: "climacell", until });
+
+        assert_eq!(outcome, RefreshOutcome::QuotaExceeded(format!("climacell quota used up until {until}")));
+    }
+
+    #[test]
+    fn request_gives_up_but_stays_pending() {
+        let requests = RefreshRequests::new();
+
+        assert_eq!(requests.request(Dataset::Daily, Duration::from_millis(20)), None);
+        assert_eq!(requests.pending(), vec![Dataset::Daily]);
+
+        //A later request joins the ticket still waiting for its pull
+        assert_eq!(requests.request(Dataset::Daily, Duration::from_millis(20)), None);
+        assert_eq!(requests.begin(Dataset::Daily), 1);
+    }
+
+    #[test]
+    fn request_cuts_the_wait_short() {
+        let requests = Arc::new(RefreshRequests::new());
+        let started = Instant::now();
+
+        let _waiting = request(&requests);
+        requests.wait(WAIT);
+
+        assert!(started.elapsed() < WAIT);
+        assert_eq!(requests.pending(), vec![Dataset::Hourly]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::config::{ RefreshConfig, ScheduleConfig };
+use chrono::{ DateTime, Duration, Utc };
+use rand::Rng;
+use std::str::FromStr;
+
+//Each dataset a cache loop pulls keeps its own next-due time. A successful pull waits the full
+//interval, a failed one is retried after retry_minutes, doubling per consecutive failure up to
+//retry_max_minutes, so a provider hiccup doesn't cost a whole interval.
+//Every next-due time gets up to jitter_seconds added at random
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dataset {
+    Instant,
+    Hourly,
+    Daily,
+}
+
+impl Dataset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dataset::Instant => "instant",
+            Dataset::Hourly => "hourly",
+            Dataset::Daily => "daily",
+        }
+    }
+}
+
+impl FromStr for Dataset {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Dataset, Self::Err> {
+        match input {
+            "instant" => Ok(Dataset::Instant),
+            "hourly" => Ok(Dataset::Hourly),
+            "daily" => Ok(Dataset::Daily),
+            _ => Err(format!("Unknown dataset '{input}', expected instant, hourly or daily")),
+        }
+    }
+}
+
+pub struct Schedule {
+    pub dataset: Dataset,
+    interval: Duration,
+    jitter_seconds: i64,
+    retry: Duration,
+    retry_max: Duration,
+    next_due: DateTime<Utc>,
+    failures: u32,
+}
+
+impl Schedule {
+    pub fn new(dataset: Dataset, config: &ScheduleConfig) -> Schedule {
+        Schedule {
+            dataset,
+            interval: Duration::minutes(config.interval_minutes),
+            jitter_seconds: config.jitter_seconds,
+            retry: Duration::minutes(config.retry_minutes),
+            retry_max: Duration::minutes(config.retry_max_minutes),
+            //Due straight away until resume_from says otherwise
+            next_due: DateTime::<Utc>::MIN_UTC,
+            failures: 0,
+        }
+    }
+
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        now >= self.next_due
+    }
+
+    pub fn next_due(&self) -> DateTime<Utc> {
+        self.next_due
+    }
+
+    //Picks up where the last run left off, from the newest stored pull
+    pub fn resume_from(&mut self, last_pull: DateTime<Utc>) {
+        if last_pull > DateTime::<Utc>::MIN_UTC {
+            self.next_due = last_pull + self.interval;
+        }
+    }
+
+    pub fn succeeded(&mut self, now: DateTime<Utc>) {
+        self.failures = 0;
+        self.next_due = now + self.interval + self.jitter();
+    }
+
+    //Over quota, wait for it to free up without counting a failure
+    pub fn defer_until(&mut self, until: DateTime<Utc>) {
+        self.next_due = until + self.jitter();
+    }
+
+    //Returns the delay until the retry
+    pub fn failed(&mut self, now: DateTime<Utc>) -> Duration {
+        self.failures += 1;
+
+        //2^(failures - 1) retries, the shift is capped well before it could overflow
+        let backoff = self.retry * (1i32 << (self.failures - 1).min(16));
+        let delay = backoff.min(self.retry_max) + self.jitter();
+
+        self.next_due = now + delay;
+        delay
+    }
+
+    fn jitter(&self) -> Duration {
+        if self.jitter_seconds <= 0 {
+            return Duration::zero();
+        }
+
+        Duration::seconds(rand::thread_rng().gen_range(0..=self.jitter_seconds))
+    }
+}
+
+pub struct Scheduler {
+    schedules: Vec<Schedule>,
+}
+
+impl Scheduler {
+    //Instant is only scheduled when there's an observation provider to pull it from
+    pub fn new(config: &RefreshConfig, with_instant: bool) -> Scheduler {
+        let mut schedules = vec![];
+
+        if with_instant {
+            schedules.push(Schedule::new(Dataset::Instant, &config.instant));
+        }
+        schedules.push(Schedule::new(Dataset::Hourly, &config.hourly));
+        schedules.push(Schedule::new(Dataset::Daily, &config.daily));
+
+        Scheduler { schedules }
+    }
+
+    pub fn get_mut(&mut self, dataset: Dataset) -> Option<&mut Schedule> {
+        self.schedules.iter_mut().find(|schedule| schedule.dataset == dataset)
+    }
+
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<Dataset> {
+        self.schedules.iter().filter(|schedule| schedule.is_due(now)).map(|schedule| schedule.dataset).collect()
+    }
+
+    //How long until the soonest dataset is due, never longer than max
+    pub fn time_until_next(&self, now: DateTime<Utc>, max: Duration) -> std::time::Duration {
+        let next = self.schedules.iter().map(|schedule| schedule.next_due()).min().unwrap_or(now + max);
+
+        (next - now).clamp(Duration::zero(), max).to_std().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(jitter_seconds: i64) -> Schedule {
+        Schedule::new(Dataset::Hourly, &ScheduleConfig { interval_minutes: 480, jitter_seconds, retry_minutes: 5, retry_max_minutes: 120 })
+    }
+
+    #[test]
+    fn retry_doubles_per_failure_up_to_max() {
+        let mut schedule = schedule(0);
+        let now = Utc::now();
+
+        let delays: Vec<i64> = (0..8).map(|_| schedule.failed(now).num_minutes()).collect();
+
+        assert_eq!(delays, vec![5, 10, 20, 40, 80, 120, 120, 120]);
+        assert_eq!(schedule.next_due(), now + Duration::minutes(120));
+    }
+
+    #[test]
+    fn success_resets_the_backoff() {
+        let mut schedule = schedule(0);
+        let now = Utc::now();
+
+        schedule.failed(now);
+        schedule.failed(now);
+        schedule.failed(now);
+        schedule.succeeded(now);
+
+        assert_eq!(schedule.next_due(), now + Duration::minutes(480));
+        assert_eq!(schedule.failed(now), Duration::minutes(5));
+    }
+
+    #[test]
+    fn backoff_survives_many_failures() {
+        let mut schedule = schedule(0);
+        let now = Utc::now();
+
+        for _ in 0..100 {
+            schedule.failed(now);
+        }
+
+        assert_eq!(schedule.failed(now), Duration::minutes(120));
+    }
+
+    #[test]
+    fn jitter_stays_within_jitter_seconds() {
+        let now = Utc::now();
+
+        for _ in 0..200 {
+            let mut schedule = schedule(30);
+
+            let delay = schedule.failed(now);
+            assert!(delay >= Duration::minutes(5) && delay <= Duration::minutes(5) + Duration::seconds(30), "{delay}");
+
+            schedule.succeeded(now);
+            let wait = schedule.next_due() - now;
+            assert!(wait >= Duration::minutes(480) && wait <= Duration::minutes(480) + Duration::seconds(30), "{wait}");
+
+            let until = now + Duration::hours(1);
+            schedule.defer_until(until);
+            assert!(schedule.next_due() >= until && schedule.next_due() <= until + Duration::seconds(30));
+        }
+    }
+}
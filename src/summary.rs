@@ -0,0 +1,131 @@
+use crate::{ calc, config::Units, forecast_db, location::Locations, metrics };
+use chrono::{ DateTime, NaiveDate, Utc };
+use serde::Serialize;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+//How often the newest days are rolled up again, as often as historical_weather gets rows by default
+const ROLLUP_MINUTES: u64 = 30;
+
+//One local day of station observations, from the daily_summary table
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailySummary {
+    pub day: NaiveDate,
+    pub high_temp: i32,
+    pub high_time: DateTime<Utc>,
+    pub low_temp: i32,
+    pub low_time: DateTime<Utc>,
+    pub mean_temp: f64,
+    pub max_gust: i32,
+    pub max_gust_dir: i32,
+    pub max_gust_time: DateTime<Utc>,
+    //Rain that fell during the day, across the station's daily reset of its running total
+    pub precip_total: f64,
+    pub max_uv: f64,
+    //None when the station reported no pressure that day
+    pub mean_pressure: Option<f64>,
+    pub min_humidity: i32,
+    pub observations: i32,
+    pub observed_hours: i32,
+    //Share of the day's hours with at least one observation, today's grows until midnight
+    pub completeness: f64,
+}
+
+impl DailySummary {
+    pub fn in_units(mut self, units: Units) -> DailySummary {
+        if units == Units::Metric {
+            let temp = |f: i32| calc::f_to_c(f as f64).round() as i32;
+
+            self.high_temp = temp(self.high_temp);
+            self.low_temp = temp(self.low_temp);
+            self.mean_temp = calc::f_to_c(self.mean_temp);
+            self.max_gust = calc::mph_to_kmh(self.max_gust as f64).round() as i32;
+            self.precip_total = calc::in_to_mm(self.precip_total);
+            self.mean_pressure = self.mean_pressure.map(calc::inhg_to_hpa);
+        }
+
+        self
+    }
+}
+
+//Rolls every location's newest days up into daily_summary, the first pass catches up on everything
+//stored before the table existed
+pub fn run(locations: Arc<Locations>) {
+    loop {
+        for location in locations.iter() {
+            match forecast_db::rollup_daily_summary_db(&location.name) {
+                //Nothing to say for a location without observations
+                Ok(days) if days > 0 => println!("Rolled up {days} daily summaries for {}", location.name),
+                Ok(_) => {}
+                Err(e) => metrics::report(&format!("Couldn't roll up daily summaries for {}", location.name), &e),
+            }
+        }
+
+        thread::sleep(Duration::from_secs(ROLLUP_MINUTES * 60));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn summary() -> DailySummary {
+        let noon = Utc.with_ymd_and_hms(2024, 4, 12, 16, 0, 0).unwrap();
+
+        DailySummary {
+            day: NaiveDate::from_ymd_opt(2024, 4, 12).unwrap(),
+            high_temp: 72,
+            high_time: noon,
+            low_temp: 32,
+            low_time: noon,
+            mean_temp: 50.0,
+            max_gust: 20,
+            max_gust_dir: 270,
+            max_gust_time: noon,
+            precip_total: 0.5,
+            max_uv: 6.0,
+            mean_pressure: Some(30.0),
+            min_humidity: 40,
+            observations: 288,
+            observed_hours: 24,
+            completeness: 1.0,
+        }
+    }
+
+    #[test]
+    fn metric_summaries_are_converted() {
+        let metric = summary().in_units(Units::Metric);
+
+        assert_eq!(metric.high_temp, 22);
+        assert_eq!(metric.low_temp, 0);
+        assert!((metric.mean_temp - 10.0).abs() < 1e-9);
+        assert_eq!(metric.max_gust, 32);
+        assert!((metric.precip_total - 12.7).abs() < 1e-9);
+        assert!((metric.mean_pressure.unwrap() - 1015.9).abs() < 0.1);
+        //Not temperatures, speeds or amounts
+        assert_eq!(metric.max_gust_dir, 270);
+        assert_eq!(metric.max_uv, 6.0);
+        assert_eq!(metric.min_humidity, 40);
+        assert_eq!(metric.observed_hours, 24);
+    }
+
+    #[test]
+    fn imperial_summaries_are_left_alone() {
+        let imperial = summary().in_units(Units::Imperial);
+
+        assert_eq!(imperial.high_temp, 72);
+        assert_eq!(imperial.max_gust, 20);
+        assert_eq!(imperial.precip_total, 0.5);
+        assert_eq!(imperial.mean_pressure, Some(30.0));
+    }
+
+    #[test]
+    fn missing_pressure_stays_missing() {
+        let metric = DailySummary { mean_pressure: None, ..summary() }.in_units(Units::Metric);
+
+        assert_eq!(metric.mean_pressure, None);
+    }
+}
@@ -0,0 +1,247 @@
+pub mod models;
+
+use crate::{ calc, config::TempestConfig, forecast_db, ingest::{ InstSink, Reading }, metrics, zone::{ self, Zone } };
+use chrono::{ NaiveDate, TimeZone, Utc };
+use models::{ LightningStrike, Message, PrecipEvent };
+use std::{ net::UdpSocket, sync::Arc };
+
+//WeatherFlow Tempest hub UDP broadcasts on the LAN, no cloud or API key involved.
+//Replay the datagrams under docs/fixtures/tempest with: nc -u -w1 127.0.0.1 50222 < obs_st.json
+
+const MS_TO_MPH: f64 = 2.23694;
+const MM_TO_IN: f64 = 1.0 / 25.4;
+const MB_TO_INHG: f64 = 0.02953;
+
+struct Listener {
+    sink: Arc<InstSink>,
+    //Only take packets from this station if several share the LAN
+    serial: Option<String>,
+    elevation_m: f64,
+    //The hub only reports rain per interval, the daily total is kept here and resets at local midnight
+    zone: Zone,
+    rain_day: NaiveDate,
+    daily_rain_in: f64,
+}
+
+pub fn listen(bind: &str, config: &TempestConfig, sink: Arc<InstSink>) {
+    let socket = match UdpSocket::bind(bind) {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("Couldn't bind Tempest listener to {bind}, Reason: {e}");
+            return;
+        }
+    };
+
+    let zone = zone::of(sink.location());
+    let mut listener = Listener {
+        sink,
+        serial: config.serial.clone(),
+        elevation_m: config.elevation_ft * 0.3048,
+        zone,
+        rain_day: zone.now().date_naive(),
+        daily_rain_in: 0.0,
+    };
+
+    println!("Listening for Tempest broadcasts on {bind}");
+    let mut buf = [0u8; 4096];
+
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) =>
+                match serde_json::from_slice::<Message>(&buf[..len]) {
+                    Ok(message) => listener.handle(message),
+                    Err(e) => println!("Couldn't deserialize Tempest datagram, Reason: {e}"),
+                }
+            Err(e) => println!("Couldn't read Tempest datagram, Reason: {e}"),
+        }
+    }
+}
+
+impl Listener {
+    fn wanted(&self, serial_number: &str) -> bool {
+        match &self.serial {
+            Some(serial) => serial == serial_number,
+            None => true,
+        }
+    }
+
+    fn handle(&mut self, message: Message) {
+        match message {
+            Message::ObsSt { serial_number, obs } if self.wanted(&serial_number) => {
+                for ob in obs.iter() {
+                    match self.convert_obs(ob) {
+                        Some(reading) => {
+                            if let Err(e) = self.sink.accept(reading.into_inst(self.zone)) {
+                                metrics::report("Failed to persist Tempest observation", &e);
+                            }
+                        }
+                        None => println!("Skipping incomplete Tempest observation"),
+                    }
+                }
+            }
+            Message::RapidWind { serial_number, ob } if self.wanted(&serial_number) => {
+                if let (Some(speed), Some(dir)) = (ob.get(1), ob.get(2)) {
+                    self.sink.update_cached(|inst| {
+                        inst.wind_speed = (speed * MS_TO_MPH).round() as i32;
+                        inst.winddir = dir.round() as i32;
+                    });
+                }
+            }
+            Message::EvtStrike { serial_number, evt } if self.wanted(&serial_number) => {
+                if let Some(strike) = convert_strike(&evt) {
+                    if let Err(e) = forecast_db::dump_strike_db(self.sink.location(), &strike) {
+                        metrics::report("Failed to persist lightning strike", &e);
+                    }
+                }
+            }
+            Message::EvtPrecip { serial_number, evt } if self.wanted(&serial_number) => {
+                if let Some(event) = convert_precip(&evt) {
+                    if let Err(e) = forecast_db::dump_precip_event_db(self.sink.location(), &event) {
+                        metrics::report("Failed to persist rain start event", &e);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn convert_obs(&mut self, ob: &[Option<f64>]) -> Option<Reading> {
+        let value = |i: usize| ob.get(i).copied().flatten();
+
+        let obs_time_utc = from_epoch(value(models::OBS_EPOCH)?)?;
+        let temp_c = value(models::OBS_AIR_TEMP)?;
+        let humidity = value(models::OBS_HUMIDITY)?;
+
+        let today = self.zone.at(obs_time_utc).date_naive();
+        if today != self.rain_day {
+            self.rain_day = today;
+            self.daily_rain_in = 0.0;
+        }
+
+        let rain_in = value(models::OBS_RAIN_ACCUMULATED).unwrap_or(0.0) * MM_TO_IN;
+        self.daily_rain_in += rain_in;
+        let report_minutes = value(models::OBS_REPORT_INTERVAL).unwrap_or(1.0).max(1.0);
+
+        Some(Reading {
+            obs_time_utc,
+            temp: calc::c_to_f(temp_c),
+            humidity,
+            dewpt: None,
+            wind_speed: value(models::OBS_WIND_AVG).unwrap_or(0.0) * MS_TO_MPH,
+            wind_gust: value(models::OBS_WIND_GUST).unwrap_or(0.0) * MS_TO_MPH,
+            winddir: value(models::OBS_WIND_DIR).unwrap_or(0.0),
+            pressure: value(models::OBS_STATION_PRESSURE)
+                .map(|mb| sea_level_pressure(mb, temp_c, self.elevation_m) * MB_TO_INHG)
+                .unwrap_or(0.0),
+            precip_rate: rain_in * (60.0 / report_minutes),
+            precip_total: self.daily_rain_in,
+            solar_radiation: value(models::OBS_SOLAR_RADIATION).unwrap_or(0.0),
+            uv: value(models::OBS_UV).unwrap_or(0.0),
+        })
+    }
+}
+
+//evt_strike is [epoch, distance km, energy]
+fn convert_strike(evt: &[f64]) -> Option<LightningStrike> {
+    match (evt.first().and_then(|t| from_epoch(*t)), evt.get(1), evt.get(2)) {
+        (Some(strike_time), Some(distance_km), Some(energy)) => Some(LightningStrike { strike_time, distance_km: *distance_km, energy: *energy }),
+        _ => None,
+    }
+}
+
+//evt_precip is just [epoch]
+fn convert_precip(evt: &[f64]) -> Option<PrecipEvent> {
+    evt.first().and_then(|t| from_epoch(*t)).map(|event_time| PrecipEvent { event_time })
+}
+
+//The hub reports station pressure, Wunderground and friends report sea level
+fn sea_level_pressure(station_mb: f64, temp_c: f64, elevation_m: f64) -> f64 {
+    station_mb * (1.0 - (0.0065 * elevation_m) / (temp_c + 0.0065 * elevation_m + 273.15)).powf(-5.257)
+}
+
+fn from_epoch(epoch: f64) -> Option<chrono::DateTime<Utc>> {
+    Utc.timestamp_opt(epoch as i64, 0).single()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wunder::models::InstModel;
+
+    fn fixture(name: &str) -> Message {
+        let datagram = std::fs::read(format!("{}/docs/fixtures/tempest/{name}.json", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        serde_json::from_slice(&datagram).unwrap()
+    }
+
+    fn listener(serial: Option<&str>) -> Listener {
+        Listener {
+            sink: Arc::new(InstSink::new("default".to_string(), 5, None)),
+            serial: serial.map(str::to_string),
+            elevation_m: 0.0,
+            zone: Zone::Host,
+            rain_day: Zone::Host.now().date_naive(),
+            daily_rain_in: 0.0,
+        }
+    }
+
+    #[test]
+    fn obs_st_is_converted_and_cached() {
+        let mut listener = listener(Some("ST-00000512"));
+        //No DB in the tests, the observation is still cached
+        listener.handle(fixture("obs_st"));
+
+        let inst = listener.sink.cached();
+        assert_eq!(inst.obs_time_utc, from_epoch(1712930400.0).unwrap());
+        assert_eq!(inst.temp, 72);
+        assert_eq!(inst.humidity, 50);
+        assert_eq!(inst.dewpt, 53);
+        assert_eq!(inst.heat_index, 72);
+        assert_eq!(inst.wind_speed, 0);
+        assert_eq!(inst.wind_gust, 1);
+        assert_eq!(inst.winddir, 144);
+        assert!((inst.pressure - 30.049).abs() < 0.001);
+        assert!((inst.precip_total - 0.01).abs() < 1e-9);
+        assert!((inst.precip_rate - 0.6).abs() < 1e-9);
+        assert_eq!(inst.solar_radiation, 3.0);
+        assert_eq!(inst.uv, 0.03);
+    }
+
+    #[test]
+    fn rapid_wind_updates_the_cached_observation() {
+        let mut listener = listener(None);
+        listener.handle(fixture("obs_st"));
+        listener.handle(fixture("rapid_wind"));
+
+        let inst = listener.sink.cached();
+        assert_eq!(inst.wind_speed, 5);
+        assert_eq!(inst.winddir, 128);
+        assert_eq!(inst.temp, 72);
+    }
+
+    #[test]
+    fn other_stations_are_ignored() {
+        let mut listener = listener(Some("ST-00000001"));
+        listener.handle(fixture("obs_st"));
+
+        assert_eq!(listener.sink.cached().obs_time_utc, InstModel::default().obs_time_utc);
+    }
+
+    #[test]
+    fn events_are_converted() {
+        let strike = match fixture("evt_strike") {
+            Message::EvtStrike { evt, .. } => convert_strike(&evt).unwrap(),
+            other => panic!("evt_strike parsed as {other:?}"),
+        };
+        assert_eq!(strike.strike_time, from_epoch(1712930420.0).unwrap());
+        assert_eq!(strike.distance_km, 27.0);
+        assert_eq!(strike.energy, 3848.0);
+
+        let event = match fixture("evt_precip") {
+            Message::EvtPrecip { evt, .. } => convert_precip(&evt).unwrap(),
+            other => panic!("evt_precip parsed as {other:?}"),
+        };
+        assert_eq!(event.event_time, from_epoch(1712930390.0).unwrap());
+
+        assert!(convert_strike(&[1712930420.0]).is_none());
+    }
+}
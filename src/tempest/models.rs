@@ -0,0 +1,53 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+//Hub broadcast, see https://weatherflow.github.io/Tempest/api/udp/v171/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Message {
+    #[serde(rename = "obs_st")]
+    ObsSt { serial_number: String, obs: Vec<Vec<Option<f64>>> },
+    #[serde(rename = "rapid_wind")]
+    RapidWind { serial_number: String, ob: Vec<f64> },
+    #[serde(rename = "evt_strike")]
+    EvtStrike { serial_number: String, evt: Vec<f64> },
+    #[serde(rename = "evt_precip")]
+    EvtPrecip { serial_number: String, evt: Vec<f64> },
+    #[serde(other)]
+    Other,
+}
+
+//obs_st field positions
+pub const OBS_EPOCH: usize = 0;
+pub const OBS_WIND_AVG: usize = 2;
+pub const OBS_WIND_GUST: usize = 3;
+pub const OBS_WIND_DIR: usize = 4;
+pub const OBS_STATION_PRESSURE: usize = 6;
+pub const OBS_AIR_TEMP: usize = 7;
+pub const OBS_HUMIDITY: usize = 8;
+pub const OBS_UV: usize = 10;
+pub const OBS_SOLAR_RADIATION: usize = 11;
+pub const OBS_RAIN_ACCUMULATED: usize = 12;
+pub const OBS_REPORT_INTERVAL: usize = 17;
+
+//DB Models
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LightningStrike {
+    pub strike_time: chrono::DateTime<Utc>,
+    pub distance_km: f64,
+    pub energy: f64,
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecipEvent {
+    pub event_time: chrono::DateTime<Utc>,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Events {
+    pub lightning_strikes: Vec<LightningStrike>,
+    pub precip_events: Vec<PrecipEvent>,
+}
@@ -0,0 +1,295 @@
+use crate::{ climacell::models::{ DailyWeather, HourlyWeather }, error::Error, scheduler::Dataset };
+use chrono::{ DateTime, Duration, Utc };
+use serde::Serialize;
+use std::{ ops::RangeInclusive, sync::Mutex };
+
+//Checks between a provider's response and the cache. A response that fails them never replaces
+//the current snapshot, the reason is kept for /forecast/healthcheck instead
+
+//Fahrenheit, a little past the recorded extremes
+const TEMP_RANGE: RangeInclusive<f64> = -90.0..=140.0;
+const PERCENT_RANGE: RangeInclusive<f64> = 0.0..=100.0;
+
+pub fn hourly(hourly: &[HourlyWeather], now: DateTime<Utc>, horizon_hours: i64) -> Result<(), Error> {
+    if hourly.is_empty() {
+        return Err(Error::Validation("No hourly intervals".to_string()));
+    }
+
+    //The first interval is the hour we're in, which may have started a while ago
+    check_times("hourly", hourly.iter().map(|h| h.weather_time), now - Duration::hours(2), now + Duration::hours(horizon_hours + 2))?;
+
+    for h in hourly {
+        check_range("temp", h.temp, &TEMP_RANGE, h.weather_time)?;
+        check_range("feels_like", h.feels_like, &TEMP_RANGE, h.weather_time)?;
+        check_range("precipitation_chance", h.precipitation_chance, &PERCENT_RANGE, h.weather_time)?;
+
+        if let Some(dew_point) = h.dew_point {
+            check_range("dew_point", dew_point, &TEMP_RANGE, h.weather_time)?;
+        }
+        if let Some(humidity) = h.humidity {
+            check_range("humidity", humidity, &PERCENT_RANGE, h.weather_time)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn daily(daily: &[DailyWeather], now: DateTime<Utc>, horizon_days: i64) -> Result<(), Error> {
+    if daily.is_empty() {
+        return Err(Error::Validation("No daily intervals".to_string()));
+    }
+
+    //Days start at local midnight (or 6am for tomorrow.io), today's can be most of a day old in UTC
+    check_times("daily", daily.iter().map(|d| d.weather_time), now - Duration::days(2), now + Duration::days(horizon_days + 2))?;
+
+    for d in daily {
+        check_range("high", d.high, &TEMP_RANGE, d.weather_time)?;
+        check_range("low", d.low, &TEMP_RANGE, d.weather_time)?;
+
+        if let (Some(sunrise), Some(sunset)) = (d.sunrise_time, d.sunset_time) {
+            if sunrise >= sunset {
+                return Err(Error::Validation(format!("Sunrise {sunrise} isn't before sunset {sunset} for {}", d.weather_time)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+//Strictly increasing and inside the window
+fn check_times(
+    kind: &str,
+    times: impl Iterator<Item = DateTime<Utc>>,
+    earliest: DateTime<Utc>,
+    latest: DateTime<Utc>
+) -> Result<(), Error> {
+    let mut previous: Option<DateTime<Utc>> = None;
+
+    for time in times {
+        if time < earliest || time > latest {
+            return Err(Error::Validation(format!("{kind} time {time} outside {earliest} - {latest}")));
+        }
+
+        if let Some(previous) = previous {
+            if time <= previous {
+                return Err(Error::Validation(format!("{kind} times not increasing, {time} follows {previous}")));
+            }
+        }
+
+        previous = Some(time);
+    }
+
+    Ok(())
+}
+
+fn check_range(field: &str, value: f64, range: &RangeInclusive<f64>, at: DateTime<Utc>) -> Result<(), Error> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(Error::Validation(format!("Implausible {field} {value} for {at}")))
+    }
+}
+
+//Latest validation result per forecast dataset of a location
+pub struct ValidationLog {
+    statuses: Mutex<Vec<ValidationStatus>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationStatus {
+    pub dataset: &'static str,
+    //"rejected" while the newest response failed validation and an older snapshot is being served
+    pub status: &'static str,
+    pub last_accepted: Option<DateTime<Utc>>,
+    pub last_rejected: Option<DateTime<Utc>>,
+    pub rejection: Option<String>,
+}
+
+impl ValidationLog {
+    pub fn new() -> ValidationLog {
+        let statuses = [Dataset::Hourly, Dataset::Daily]
+            .into_iter()
+            .map(|dataset| ValidationStatus {
+                dataset: dataset.name(),
+                status: "ok",
+                last_accepted: None,
+                last_rejected: None,
+                rejection: None,
+            })
+            .collect();
+
+        ValidationLog { statuses: Mutex::new(statuses) }
+    }
+
+    pub fn accepted(&self, dataset: Dataset) {
+        self.update(dataset, |status| {
+            status.status = "ok";
+            status.last_accepted = Some(Utc::now());
+        });
+    }
+
+    pub fn rejected(&self, dataset: Dataset, reason: &Error) {
+        self.update(dataset, |status| {
+            status.status = "rejected";
+            status.last_rejected = Some(Utc::now());
+            status.rejection = Some(reason.to_string());
+        });
+    }
+
+    pub fn statuses(&self) -> Vec<ValidationStatus> {
+        self.statuses.lock().unwrap().clone()
+    }
+
+    fn update(&self, dataset: Dataset, update: impl FnOnce(&mut ValidationStatus)) {
+        if let Some(status) = self.statuses.lock().unwrap().iter_mut().find(|status| status.dataset == dataset.name()) {
+            update(status);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-04-12T14:20:00Z").unwrap().into()
+    }
+
+    fn hours(count: i64) -> Vec<HourlyWeather> {
+        let start = now() - Duration::minutes(20);
+
+        (0..count)
+            .map(|i| HourlyWeather {
+                weather_time: start + Duration::hours(i),
+                temp: 60.0 + i as f64,
+                feels_like: 60.0 + i as f64,
+                precipitation_chance: 10.0,
+                humidity: Some(55.0),
+                dew_point: Some(44.0),
+                ..HourlyWeather::default()
+            })
+            .collect()
+    }
+
+    fn days(count: i64) -> Vec<DailyWeather> {
+        let start = now() - Duration::hours(10);
+
+        (0..count)
+            .map(|i| DailyWeather {
+                weather_time: start + Duration::days(i),
+                high: 75.0,
+                low: 50.0,
+                sunrise_time: Some(start + Duration::hours(6)),
+                sunset_time: Some(start + Duration::hours(19)),
+                ..DailyWeather::default()
+            })
+            .collect()
+    }
+
+    fn rejection(result: Result<(), Error>) -> String {
+        match result {
+            Err(Error::Validation(reason)) => reason,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn good_batches_pass() {
+        assert!(hourly(&hours(24), now(), 24).is_ok());
+        assert!(daily(&days(5), now(), 5).is_ok());
+    }
+
+    #[test]
+    fn empty_batches_are_rejected() {
+        assert_eq!(rejection(hourly(&[], now(), 24)), "No hourly intervals");
+        assert_eq!(rejection(daily(&[], now(), 5)), "No daily intervals");
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let mut batch = hours(3);
+        batch[1].humidity = Some(140.0);
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("Implausible humidity 140"));
+
+        let mut batch = hours(3);
+        batch[2].precipitation_chance = -5.0;
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("Implausible precipitation_chance -5"));
+
+        let mut batch = days(3);
+        batch[0].low = -120.0;
+        assert!(rejection(daily(&batch, now(), 5)).starts_with("Implausible low -120"));
+    }
+
+    #[test]
+    fn wrong_units_are_caught() {
+        //Kelvin where Fahrenheit belongs
+        let mut batch = hours(3);
+        batch[0].temp = 288.7;
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("Implausible temp 288.7"));
+
+        //A 0-1 fraction scaled to per mille instead of percent
+        let mut batch = hours(3);
+        batch[0].precipitation_chance = 400.0;
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("Implausible precipitation_chance 400"));
+
+        //Metric readings converted to Fahrenheit twice come out far too hot
+        let mut batch = days(2);
+        batch[1].high = crate::calc::c_to_f(95.0);
+        assert!(rejection(daily(&batch, now(), 5)).starts_with("Implausible high"));
+    }
+
+    #[test]
+    fn one_bad_interval_rejects_the_batch() {
+        let mut batch = hours(24);
+        batch[17].dew_point = Some(-150.0);
+
+        let reason = rejection(hourly(&batch, now(), 24));
+        assert!(reason.contains("dew_point -150"));
+        assert!(reason.contains(&batch[17].weather_time.to_string()));
+    }
+
+    #[test]
+    fn times_must_increase_and_stay_in_the_window() {
+        let mut batch = hours(5);
+        batch[3].weather_time = batch[2].weather_time;
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("hourly times not increasing"));
+
+        //A stale response from last week
+        let batch: Vec<HourlyWeather> = hours(5)
+            .into_iter()
+            .map(|mut h| {
+                h.weather_time -= Duration::days(7);
+                h
+            })
+            .collect();
+        assert!(rejection(hourly(&batch, now(), 24)).starts_with("hourly time"));
+
+        //Longer than asked for
+        assert!(hourly(&hours(30), now(), 24).is_err());
+        assert!(daily(&days(9), now(), 5).is_err());
+    }
+
+    #[test]
+    fn sunrise_must_come_before_sunset() {
+        let mut batch = days(2);
+        batch[1].sunrise_time = batch[1].sunset_time;
+
+        assert!(rejection(daily(&batch, now(), 5)).starts_with("Sunrise"));
+    }
+
+    #[test]
+    fn log_keeps_the_latest_result() {
+        let log = ValidationLog::new();
+
+        log.rejected(Dataset::Hourly, &Error::Validation("bad".to_string()));
+        let hourly = log.statuses().into_iter().find(|s| s.dataset == "hourly").unwrap();
+        assert_eq!((hourly.status, hourly.rejection.as_deref()), ("rejected", Some("bad")));
+
+        log.accepted(Dataset::Hourly);
+        let statuses = log.statuses();
+        assert_eq!(statuses.iter().map(|s| s.status).collect::<Vec<_>>(), vec!["ok", "ok"]);
+        assert!(statuses[0].last_accepted.is_some() && statuses[0].last_rejected.is_some());
+        assert!(statuses[1].last_accepted.is_none());
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{ calc, config::Units, error::Error, forecast_db, zone };
+use chrono::{ NaiveDate, NaiveTime };
+use serde::Serialize;
+
+//Lead time buckets for the hourly scores unless ?lead_bucket_hours= says otherwise
+pub const DEFAULT_LEAD_BUCKET_HOURS: i32 = 6;
+
+//A day is only scored against the station's high and low once it has observations in this many of its hours
+pub const MIN_DAILY_OBSERVED_HOURS: i64 = 20;
+
+//Forecast minus observed over every matched pair. None when nothing was matched
+#[derive(Debug, Default, Serialize)]
+pub struct Scores {
+    pub count: i64,
+    pub bias: Option<f64>,
+    pub mae: Option<f64>,
+    pub rmse: Option<f64>,
+}
+
+//precipitation_chance against whether the station saw any rain in the hour
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecipScores {
+    pub count: i64,
+    pub brier: Option<f64>,
+    //How often it actually rained in the matched hours, the brier score of always forecasting this is the baseline
+    pub observed_frequency: Option<f64>,
+}
+
+//Hourly forecasts issued between lead_hours_from and lead_hours_to before their hour
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HourlyVerification {
+    pub provider: String,
+    pub lead_hours_from: i32,
+    pub lead_hours_to: i32,
+    pub temp: Scores,
+    pub dew_point: Scores,
+    pub humidity: Scores,
+    pub precipitation: PrecipScores,
+}
+
+//Daily forecasts issued lead_days calendar days before their day
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyVerification {
+    pub provider: String,
+    pub lead_days: i32,
+    pub high: Scores,
+    pub low: Scores,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Verification<'a> {
+    pub location: &'a str,
+    pub units: &'static str,
+    pub hourly: Vec<HourlyVerification>,
+    pub daily: Vec<DailyVerification>,
+}
+
+//Inclusive local dates the forecast targets fall in, either end open
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Period {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl Period {
+    pub fn new(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<Period, Error> {
+        match (from, to) {
+            (Some(from), Some(to)) if from > to => Err(Error::Validation(format!("from ({from}) is after to ({to})"))),
+            _ => Ok(Period { from, to }),
+        }
+    }
+
+    //Local midnight bounds as weather_time stores them, the end exclusive
+    pub fn bounds(&self) -> (Option<chrono::NaiveDateTime>, Option<chrono::NaiveDateTime>) {
+        (
+            self.from.map(|from| from.and_time(NaiveTime::MIN)),
+            self.to.map(|to| (to + chrono::Duration::days(1)).and_time(NaiveTime::MIN)),
+        )
+    }
+}
+
+impl Scores {
+    //Errors are temperature differences, so only the scale changes
+    fn in_units(mut self, units: Units) -> Scores {
+        if units == Units::Metric {
+            self.bias = self.bias.map(calc::f_delta_to_c);
+            self.mae = self.mae.map(calc::f_delta_to_c);
+            self.rmse = self.rmse.map(calc::f_delta_to_c);
+        }
+
+        self
+    }
+}
+
+//Scores every stored forecast run for the location against its station observations. Hours and days are
+//matched on local time, the same clock weather_time and obs_time_local are stored in
+pub fn verify(location: &str, period: Period, lead_bucket_hours: i32, units: Units) -> Result<Verification<'_>, Error> {
+    let now = zone::of(location).now();
+
+    let hourly = forecast_db::get_hourly_verification_db(location, period, lead_bucket_hours, now)?
+        .into_iter()
+        .map(|mut v| {
+            v.temp = v.temp.in_units(units);
+            v.dew_point = v.dew_point.in_units(units);
+            v
+        })
+        .collect();
+
+    let daily = forecast_db::get_daily_verification_db(location, period, now)?
+        .into_iter()
+        .map(|mut v| {
+            v.high = v.high.in_units(units);
+            v.low = v.low.in_units(units);
+            v
+        })
+        .collect();
+
+    Ok(Verification {
+        location,
+        units: match units {
+            Units::Imperial => "imperial",
+            Units::Metric => "metric",
+        },
+        hourly,
+        daily,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ climacell::models::HourlyWeather, wunder::models::InstModel };
+    use chrono::{ Local, TimeZone, Utc };
+
+    fn observation(location: &str, time: chrono::DateTime<Utc>, temp: i32) {
+        let inst = InstModel { obs_time_utc: time, obs_time_local: time.with_timezone(&Local).naive_local(), temp, ..InstModel::default() };
+        forecast_db::dump_inst_db(location, &inst).unwrap();
+    }
+
+    #[test]
+    fn forecast_hours_are_scored_against_the_same_hour_observed() {
+        if !forecast_db::test_db() {
+            return;
+        }
+
+        let location = forecast_db::test_location("verification");
+        let hour = Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap();
+
+        forecast_db::dump_hourly_db(&location, "test", &[HourlyWeather { weather_time: hour, temp: 70.0, ..HourlyWeather::default() }]).unwrap();
+        forecast_db::connect()
+            .unwrap()
+            .execute("UPDATE forecast_runs SET issued_at = $2 WHERE location = $1", &[&location, &(hour - chrono::Duration::hours(8))])
+            .unwrap();
+
+        //Only the first two fall in the forecast hour, the others are the neighbouring hours and a UTC offset away
+        observation(&location, hour + chrono::Duration::minutes(10), 67);
+        observation(&location, hour + chrono::Duration::minutes(40), 69);
+        observation(&location, hour - chrono::Duration::minutes(10), 40);
+        observation(&location, hour + chrono::Duration::minutes(70), 40);
+        observation(&location, hour + chrono::Duration::hours(5), 40);
+
+        let verification = verify(&location, Period::default(), DEFAULT_LEAD_BUCKET_HOURS, Units::Imperial).unwrap();
+        let scored = &verification.hourly[0];
+
+        assert_eq!(verification.hourly.len(), 1);
+        assert_eq!((scored.lead_hours_from, scored.lead_hours_to), (6, 12));
+        assert_eq!(scored.temp.count, 1);
+        assert_eq!(scored.temp.bias, Some(2.0));
+    }
+}
@@ -1,146 +1,329 @@
-use crate::climacell::{ models::{ DailyWeather, HourlyWeather }, webmodels::{ DailyRoot, HourlyRoot } };
-use crate::{ forecast_db::{ self, HistoricalSearchType }, wunder };
-use nickel::{ hyper::header::AccessControlAllowOrigin, status::StatusCode, MediaType, QueryString };
+use crate::climacell::models::{ DailyWeather, HourlyWeather };
+use crate::{ config, error::Error, forecast_db::{ self, HistoricalRange, HistoricalSearchType }, location::{ Location, Locations }, metrics, pws, quota, wunder, zone };
+use crate::{ correction::CorrectedHourly, refresh::RefreshOutcome, scheduler::Dataset, summary::DailySummary, validate::ValidationStatus, verification::{ self, Period } };
+use nickel::{ hyper::header::{ AccessControlAllowOrigin, Authorization, Bearer }, status::StatusCode, FormBody, MediaType, QueryString };
+use chrono::{ DateTime, DurationRound, NaiveDate, Utc };
+use serde::Serialize;
 use std::str::FromStr;
+use std::time::Duration;
 
-pub fn get_hist(_request: &mut nickel::Request, _response: &mut nickel::Response) -> String {
-    match _request.query().get("search_type") {
-        Some(search_type) => {
-            match HistoricalSearchType::from_str(search_type) {
-                Ok(decoded_search_type) => {
-                    match decoded_search_type {
-                        HistoricalSearchType::Daily => {
-                            let day = match _request.query().get("day") {
-                                Some(d) => d,
-                                None => {
-                                    _response.set(StatusCode::BadRequest);
-                                    return "missing day".to_string();
-                                }
-                            };
-                            //Get historical data points, using single day
-                            match forecast_db::get_historical_db(day) {
-                                Ok(historical_data) => { serde_json::to_string(&historical_data).unwrap() }
-                                Err(e) => { serde_json::to_string(&e).unwrap() }
-                            }
-                        }
-
-                        HistoricalSearchType::TimeRange => {
-                            let query = _request.query();
-
-                            let day1 = match query.get("day1") {
-                                Some(d1) => d1,
-                                None => {
-                                    _response.set(StatusCode::BadRequest);
-                                    return "missing day1".to_string();
-                                }
-                            };
-
-                            let day2 = match query.get("day2") {
-                                Some(d2) => d2,
-                                None => {
-                                    _response.set(StatusCode::BadRequest);
-                                    return "missing day2".to_string();
-                                }
-                            };
-                            //Do a time-range call
-                            match forecast_db::get_historical_range_db(day1, day2) {
-                                Ok(historical_data) => { serde_json::to_string(&historical_data).unwrap() }
-                                Err(e) => { serde_json::to_string(&e).unwrap() }
-                            }
-                        }
-                    }
-                }
-                Err(_) => {
-                    _response.set(StatusCode::BadRequest);
-                    format!("unknown search_type provided")
-                }
+//Longest a refresh request holds one of the server's threads. A pull that takes longer is answered
+//with 202 and carries on, the new data shows up on the usual routes
+const REFRESH_TIMEOUT_SECS: u64 = 10;
+
+//Sets the status, counts the error and answers with its ErrorBody
+fn fail(response: &mut nickel::Response, e: Error) -> String {
+    metrics::count_error(&e);
+    response.set(e.status());
+
+    serde_json::to_string(&e.body()).unwrap()
+}
+
+//Shared by get_hist and get_events: search_type=daily with day, or search_type=timerange with day1 and day2
+fn hist_range(request: &mut nickel::Request) -> Result<HistoricalRange, Error> {
+    let query = request.query();
+
+    parse_hist_range(query.get("search_type"), query.get("day"), query.get("day1"), query.get("day2"))
+}
+
+fn parse_hist_range(search_type: Option<&str>, day: Option<&str>, day1: Option<&str>, day2: Option<&str>) -> Result<HistoricalRange, Error> {
+    match search_type.map(HistoricalSearchType::from_str) {
+        Some(Ok(HistoricalSearchType::Daily)) =>
+            match day {
+                Some(day) => HistoricalRange::day(day),
+                None => Err(Error::Validation("missing day".to_string())),
+            }
+        Some(Ok(HistoricalSearchType::TimeRange)) =>
+            match (day1, day2) {
+                (Some(day1), Some(day2)) => HistoricalRange::between(day1, day2),
+                (None, _) => Err(Error::Validation("missing day1".to_string())),
+                (_, None) => Err(Error::Validation("missing day2".to_string())),
             }
+        Some(Err(_)) => Err(Error::Validation("unknown search_type provided".to_string())),
+        None => Err(Error::Validation("missing search_type".to_string())),
+    }
+}
+
+pub fn get_hist(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let range = match hist_range(request) {
+        Ok(range) => range,
+        Err(e) => {
+            return fail(response, e);
         }
-        None => "missing search_type".to_string(),
+    };
+
+    match forecast_db::get_historical_db(&location.name, &range) {
+        Ok(historical_data) => serde_json::to_string(&in_units_hist(historical_data)).unwrap(),
+        Err(e) => fail(response, e),
     }
 }
 
-pub fn get_lat_long() -> String {
-    std::env::var("RUSTYFORECAST_LATLONG").expect("Lat Long not set")
+fn in_units_hist(historical_data: Vec<wunder::models::InstModel>) -> Vec<wunder::models::InstModel> {
+    let units = config::get().units;
+
+    historical_data.into_iter().map(|inst| inst.in_units(units)).collect()
+}
+
+//Tempest lightning/rain-start events, same search_type/day/day1/day2 parameters as get_hist
+pub fn get_events(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let range = match hist_range(request) {
+        Ok(range) => range,
+        Err(e) => {
+            return fail(response, e);
+        }
+    };
+
+    match forecast_db::get_events_db(&location.name, &range) {
+        Ok(events) => serde_json::to_string(&events).unwrap(),
+        Err(e) => fail(response, e),
+    }
 }
 
-pub fn get_hourly_web(hourly_model: &mut Vec<HourlyWeather>) -> Result<String, String> {
-    println!("hitting hourly web API");
-    let client = reqwest::blocking::Client::new();
-    let lat_long = get_lat_long();
+#[derive(Serialize)]
+struct Evolution<'a, T, R> {
+    location: &'a str,
+    target: T,
+    revisions: Vec<R>,
+}
 
-    let params = [
-        ("location", lat_long.as_str()),
-        ("fields", "temperature,temperatureApparent,weatherCode,precipitationType,precipitationProbability,humidity,dewPoint"),
-        ("timesteps", "1h"),
-        ("endTime", &forecast_db::get_hourly_timestamp()),
-        ("units", "imperial"),
-        ("apikey", &std::env::var("RUSTYFORECAST_climacellApi").unwrap()),
-    ];
+//?time= an RFC 3339 datetime, rounded down to the hour. How each stored run forecast that hour
+pub fn get_hourly_evolution(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let target = match evolution_hour(request.query().get("time")) {
+        Ok(target) => target,
+        Err(e) => {
+            return fail(response, e);
+        }
+    };
 
-    match client.get("https://api.tomorrow.io/v4/timelines").query(&params).send() {
-        Ok(resp) => {
-            //Fix error handling, don't overwrite data
-            match resp.json::<HourlyRoot>() {
-                Ok(root) => {
-                    let hourly = HourlyWeather::convert(root);
-                    *hourly_model = hourly;
+    let units = config::get().units;
 
-                    Ok("Successfully pulled hourly data from Web".to_string())
-                }
-                Err(e) => Err(format!("Couldn't deserialize hourly data from web, Reason: {e}")),
-            }
+    match forecast_db::get_hourly_evolution_db(&location.name, target) {
+        Ok(revisions) => {
+            let revisions = revisions.into_iter().map(|r| r.in_units(units)).collect();
+            serde_json::to_string(&Evolution { location: &location.name, target, revisions }).unwrap()
         }
-        Err(e) => Err(format!("Couldn't retrieve hourly data from Web, Reason: {e}")),
+        Err(e) => fail(response, e),
     }
 }
 
-pub fn get_daily_web(daily_model: &mut Vec<DailyWeather>) -> Result<String, String> {
-    println!("hitting daily web API");
-    let client = reqwest::blocking::Client::new();
-    let lat_long = get_lat_long();
+fn evolution_hour(time: Option<&str>) -> Result<DateTime<Utc>, Error> {
+    match time.map(DateTime::parse_from_rfc3339) {
+        //Rounding fails past what nanosecond timestamps reach, in 2262
+        Some(Ok(time)) =>
+            time.with_timezone(&Utc)
+                .duration_trunc(chrono::Duration::hours(1))
+                .map_err(|e| Error::Validation(format!("time can't be rounded to the hour: {e}"))),
+        Some(Err(_)) => Err(Error::Validation("time must be a datetime with a timezone (2024-05-01T15:00:00Z)".to_string())),
+        None => Err(Error::Validation("missing time".to_string())),
+    }
+}
 
-    let params = [
-        ("location", lat_long.as_str()),
-        ("units", "imperial"),
-        ("timesteps", "1d"),
-        ("endTime", &forecast_db::get_weekly_timestamp()),
-        ("fields", "temperatureMin,temperatureMax,moonPhase,weatherCode,sunsetTime,sunriseTime"),
-        ("apikey", &std::env::var("RUSTYFORECAST_climacellApi").unwrap()),
-    ];
+//?day= a local date. How each stored run forecast that day, e.g. Saturday's high 5, 3 and 1 days out
+pub fn get_daily_evolution(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let day = match request.query().get("day").map(|day| NaiveDate::parse_from_str(day, "%Y-%m-%d")) {
+        Some(Ok(day)) => day,
+        Some(Err(_)) => {
+            return fail(response, Error::Validation("day must be a date (2024-05-01)".to_string()));
+        }
+        None => {
+            return fail(response, Error::Validation("missing day".to_string()));
+        }
+    };
 
-    match client.get("https://api.tomorrow.io/v4/timelines").query(&params).send() {
-        Ok(resp) => {
-            match resp.json::<DailyRoot>() {
-                Ok(root) => {
-                    let daily = DailyWeather::convert(root);
-                    *daily_model = daily;
+    let units = config::get().units;
 
-                    Ok("Successfully pulled daily data from Web".to_string())
-                }
-                Err(e) => Err(format!("Couldn't deserialize daily data from web, Reason: {e}")),
-            }
+    match forecast_db::get_daily_evolution_db(&location.name, day) {
+        Ok(revisions) => {
+            let revisions = revisions.into_iter().map(|r| r.in_units(units)).collect();
+            serde_json::to_string(&Evolution { location: &location.name, target: day, revisions }).unwrap()
         }
-        Err(e) => Err(format!("Couldn't retrieve daily data from Web, Reason: {e}")),
+        Err(e) => fail(response, e),
     }
 }
 
-pub fn poke() -> String {
-    let dailies = forecast_db::get_daily_db();
+//Optional date parameter, 400 when present but not YYYY-MM-DD
+fn date_param(request: &mut nickel::Request, name: &str) -> Result<Option<NaiveDate>, Error> {
+    match request.query().get(name) {
+        Some(value) =>
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(Some)
+                .map_err(|_| Error::Validation(format!("{name} must be a date (2024-05-01), got {value:?}"))),
+        None => Ok(None),
+    }
+}
 
-    for daily in dailies.iter() {
-        println!("{daily:?}");
+//?from= and ?to=, inclusive local dates. One row per day from the rollup instead of every observation
+pub fn get_summary(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let (from, to) = match (date_param(request, "from"), date_param(request, "to")) {
+        (Ok(Some(from)), Ok(Some(to))) if from > to => {
+            return fail(response, Error::Validation(format!("from ({from}) is after to ({to})")));
+        }
+        (Ok(Some(from)), Ok(Some(to))) => (from, to),
+        (Ok(None), _) => {
+            return fail(response, Error::Validation("missing from".to_string()));
+        }
+        (_, Ok(None)) => {
+            return fail(response, Error::Validation("missing to".to_string()));
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            return fail(response, e);
+        }
+    };
+
+    let units = config::get().units;
+
+    match forecast_db::get_daily_summary_db(&location.name, from, to) {
+        Ok(summaries) => serde_json::to_string(&summaries.into_iter().map(|s| s.in_units(units)).collect::<Vec<DailySummary>>()).unwrap(),
+        Err(e) => fail(response, e),
     }
+}
 
-    let hourlies = forecast_db::get_hourly_db();
+//Forecast accuracy against the location's station observations, by provider and lead time.
+//?from= and ?to= limit it to forecasts for those local dates, ?lead_bucket_hours= sizes the hourly buckets
+pub fn get_verification(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let period = match (date_param(request, "from"), date_param(request, "to")) {
+        (Ok(from), Ok(to)) => Period::new(from, to),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    let period = match period {
+        Ok(period) => period,
+        Err(e) => {
+            return fail(response, e);
+        }
+    };
+
+    let lead_bucket_hours = match request.query().get("lead_bucket_hours").map(i32::from_str) {
+        None => verification::DEFAULT_LEAD_BUCKET_HOURS,
+        Some(Ok(hours)) if hours >= 1 => hours,
+        Some(_) => {
+            return fail(response, Error::Validation("lead_bucket_hours must be a whole number of hours, at least 1".to_string()));
+        }
+    };
 
-    for hourly in hourlies.iter() {
-        println!("{hourly:?}");
+    match verification::verify(&location.name, period, lead_bucket_hours, config::get().units) {
+        Ok(verification) => serde_json::to_string(&verification).unwrap(),
+        Err(e) => fail(response, e),
     }
+}
+
+pub fn poke(response: &mut nickel::Response, location: &Location) -> String {
+    let dailies = match forecast_db::get_daily_db(&location.name) {
+        Ok(dailies) => dailies,
+        Err(e) => {
+            return fail(response, e);
+        }
+    };
+
+    let hourlies = match forecast_db::get_hourly_db(&location.name) {
+        Ok(hourlies) => hourlies,
+        Err(e) => {
+            return fail(response, e);
+        }
+    };
 
     serde_json::to_string(&hourlies).unwrap() + &serde_json::to_string(&dailies).unwrap()
 }
 
+#[derive(Serialize)]
+struct RefreshResponse<'a> {
+    location: &'a str,
+    dataset: &'static str,
+    #[serde(flatten)]
+    outcome: RefreshOutcome,
+}
+
+//Looks at every byte whatever it finds, so how long a wrong token takes doesn't tell how much of it was right
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+//POST ?dataset=instant|hourly|daily with Authorization: Bearer {server.refresh_token}.
+//Answers once the location's cache loop has pulled it, requests arriving meanwhile share the pull
+pub fn refresh(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let authorized = match (&config::get().server.refresh_token, request.origin.headers.get::<Authorization<Bearer>>()) {
+        (Some(token), Some(Authorization(bearer))) => same_token(&bearer.token, token),
+        _ => false,
+    };
+
+    if !authorized {
+        return fail(response, Error::Unauthorized("missing or wrong refresh token".to_string()));
+    }
+
+    let dataset = match request.query().get("dataset").map(Dataset::from_str) {
+        Some(Ok(dataset)) => dataset,
+        Some(Err(e)) => {
+            return fail(response, Error::Validation(e));
+        }
+        None => {
+            return fail(response, Error::Validation("missing dataset".to_string()));
+        }
+    };
+
+    if dataset == Dataset::Instant && !location.pulls_instant {
+        return fail(response, Error::Validation(format!("{} has no observation provider to pull instant data from", location.name)));
+    }
+
+    println!("Refresh of {} data for {} requested", dataset.name(), location.name);
+
+    let outcome = location.refresh.request(dataset, Duration::from_secs(REFRESH_TIMEOUT_SECS)).unwrap_or(RefreshOutcome::Pending);
+
+    response.set(match outcome {
+        RefreshOutcome::Ok => StatusCode::Ok,
+        RefreshOutcome::ProviderError(_) | RefreshOutcome::Rejected(_) => StatusCode::BadGateway,
+        RefreshOutcome::DbError(_) => StatusCode::InternalServerError,
+        RefreshOutcome::QuotaExceeded(_) => StatusCode::TooManyRequests,
+        RefreshOutcome::Pending => StatusCode::Accepted,
+    });
+
+    serde_json::to_string(&RefreshResponse { location: &location.name, dataset: dataset.name(), outcome }).unwrap()
+}
+
+#[derive(Serialize)]
+struct LocationHealth<'a> {
+    location: &'a str,
+    datasets: Vec<ValidationStatus>,
+}
+
+#[derive(Serialize)]
+struct Health<'a> {
+    //"degraded" while any location is serving an older snapshot because its newest response was rejected,
+    //or while the DB is unreachable
+    status: &'static str,
+    locations: Vec<LocationHealth<'a>>,
+    database: Option<forecast_db::PoolStats>,
+}
+
+pub fn healthcheck(locations: &Locations) -> String {
+    let locations: Vec<LocationHealth> = locations
+        .iter()
+        .map(|location| LocationHealth { location: &location.name, datasets: location.validation.statuses() })
+        .collect();
+
+    let database = forecast_db::pool_stats();
+
+    //The pool always keeps one connection open, none means the DB can't be reached
+    let db_down = database.as_ref().is_some_and(|database| database.connections == 0);
+
+    let status = if db_down || locations.iter().flat_map(|location| location.datasets.iter()).any(|dataset| dataset.status == "rejected") {
+        "degraded"
+    } else {
+        "ok"
+    };
+
+    println!("Healthcheck({status})");
+    serde_json::to_string(&Health { status, locations, database }).unwrap()
+}
+
+//Requests made to each provider this hour and today, against the configured limits
+pub fn get_quota() -> String {
+    serde_json::to_string(&quota::get().status()).unwrap()
+}
+
+pub fn get_metrics() -> String {
+    serde_json::to_string(&metrics::get()).unwrap()
+}
+
 pub fn echo(request: &mut nickel::Request) -> String {
     const ERROR_STR: &str = "error";
 
@@ -161,20 +344,167 @@ pub fn set_default_headers<'mw>(_req: &mut nickel::Request, mut res: nickel::Res
     res.next_middleware()
 }
 
-pub fn get_cached_daily(_request: &mut nickel::Request, daily: &mut Vec<DailyWeather>) -> String {
-    println!("hitting daily cache");
+//The :location segment of the per-location routes, Err is the response body
+pub fn find_location<'a>(request: &nickel::Request, response: &mut nickel::Response, locations: &'a Locations) -> Result<&'a Location, String> {
+    let name = request.param("location").unwrap_or_default();
+
+    match locations.get(name) {
+        Some(location) => Ok(location),
+        None => Err(fail(response, Error::NotFound(format!("unknown location '{name}'")))),
+    }
+}
+
+pub fn get_cached_daily(_request: &mut nickel::Request, location: &Location) -> String {
+    println!("hitting daily cache for {}", location.name);
+    let units = config::get().units;
+    let daily = location.daily.load();
+
+    serde_json::to_string(&daily.iter().map(|d| d.clone().in_units(units)).collect::<Vec<DailyWeather>>()).unwrap()
+}
+
+pub fn get_cached_hourly(_request: &mut nickel::Request, location: &Location) -> String {
+    println!("hitting hourly cache for {}", location.name);
+    let units = config::get().units;
+    let hourly = location.hourly.load();
+
+    serde_json::to_string(&hourly.iter().map(|h| h.clone().in_units(units)).collect::<Vec<HourlyWeather>>()).unwrap()
+}
+
+//The hourly snapshot adjusted by what the location's station history says about the provider
+pub fn get_corrected_hourly(_request: &mut nickel::Request, location: &Location) -> String {
+    println!("hitting corrected hourly cache for {}", location.name);
+    let units = config::get().units;
+    let corrections = location.corrections.load();
+
+    serde_json::to_string(&location.hourly.load().iter().map(|h| corrections.apply(h, units)).collect::<Vec<CorrectedHourly>>()).unwrap()
+}
+
+//{"observations":[...]} as wunderground answers, what /forecast/instant served before observation providers
+pub fn get_cached_inst(_request: &mut nickel::Request, location: &Location) -> String {
+    println!("hitting Inst cache for {}", location.name);
 
-    serde_json::to_string(&daily).unwrap()
+    //Every Location is built from its config
+    let root = config::get().find_location(&location.name).map(|config| location.sink.cached().to_root(config)).unwrap_or_default();
+
+    serde_json::to_string(&root).unwrap()
 }
 
-pub fn get_cached_hourly(_request: &mut nickel::Request, hourly: &mut Vec<HourlyWeather>) -> String {
-    println!("hitting hourly cache");
+//The cached observation flattened and in the configured units, served under /v2
+pub fn get_cached_inst_v2(_request: &mut nickel::Request, location: &Location) -> String {
+    println!("hitting Inst cache for {}", location.name);
 
-    serde_json::to_string(&hourly).unwrap()
+    serde_json::to_string(&location.sink.cached().in_units(config::get().units)).unwrap()
 }
 
-pub fn get_cached_inst(_request: &mut nickel::Request, inst: &mut wunder::models::Root) -> String {
-    println!("hitting Inst cache");
+//Wunderground-protocol upload from a local station, the station only cares that it gets "success" back.
+//The upload's station ID picks the location
+pub fn ingest_wunderground(request: &mut nickel::Request, response: &mut nickel::Response, locations: &Locations) -> String {
+    let params = request.query();
+
+    let parsed = locations.for_station(params.get("ID")).and_then(|location| Ok((location, pws::from_wunderground(params, config::get().pws.password.as_deref(), zone::of(&location.name))?)));
+
+    match parsed {
+        Ok((location, inst)) => ingest_inst(inst, response, location),
+        Err(e) => {
+            println!("Rejected Wunderground upload, Reason: {e}");
+            fail(response, e)
+        }
+    }
+}
 
-    serde_json::to_string(&inst).unwrap()
+pub fn ingest_ecowitt(request: &mut nickel::Request, response: &mut nickel::Response, location: &Location) -> String {
+    let parsed = match request.form_body() {
+        Ok(params) => pws::from_ecowitt(params, config::get().pws.ecowitt_passkey.as_deref(), zone::of(&location.name)),
+        Err((_, e)) => Err(Error::Validation(format!("Couldn't read Ecowitt form body, Reason: {e:?}"))),
+    };
+
+    match parsed {
+        Ok(inst) => ingest_inst(inst, response, location),
+        Err(e) => {
+            println!("Rejected Ecowitt upload, Reason: {e}");
+            fail(response, e)
+        }
+    }
+}
+
+fn ingest_inst(inst: wunder::models::InstModel, response: &mut nickel::Response, location: &Location) -> String {
+    match location.sink.accept(inst) {
+        Ok(_) => "success".to_string(),
+        Err(e) => {
+            //Still cached, only the DB write failed
+            println!("Failed to persist uploaded Inst data, Reason: {e}");
+            fail(response, e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(same_token("s3cret", "s3cret"));
+        assert!(!same_token("s3cres", "s3cret"));
+        assert!(!same_token("s3cre", "s3cret"));
+        assert!(!same_token("", "s3cret"));
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    fn rejected(result: Result<HistoricalRange, Error>) -> String {
+        match result {
+            Err(Error::Validation(message)) => message,
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn evolution_times_round_down_to_the_hour() {
+        assert_eq!(evolution_hour(Some("2024-05-01T11:45:30-04:00")).unwrap(), date(1).and_hms_opt(15, 0, 0).unwrap().and_utc());
+
+        assert!(matches!(evolution_hour(Some("2024-05-01 15:00")), Err(Error::Validation(_))));
+        assert!(matches!(evolution_hour(Some("2300-01-01T00:00:00Z")), Err(Error::Validation(_))));
+        assert!(matches!(evolution_hour(None), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn hist_ranges_parse_dates_and_datetimes() {
+        assert!(matches!(parse_hist_range(Some("daily"), Some("2024-05-01"), None, None), Ok(HistoricalRange::Days(a, b)) if a == date(1) && b == date(1)));
+        assert!(matches!(parse_hist_range(Some("timerange"), None, Some("2024-05-01"), Some("2024-05-03")), Ok(HistoricalRange::Days(a, b)) if a == date(1) && b == date(3)));
+
+        let times = parse_hist_range(Some("timerange"), None, Some("2024-05-01T06:00:00-04:00"), Some("2024-05-01T12:00:00Z"));
+        assert!(matches!(times, Ok(HistoricalRange::Times(a, b)) if a == date(1).and_hms_opt(10, 0, 0).unwrap().and_utc() && b == date(1).and_hms_opt(12, 0, 0).unwrap().and_utc()));
+
+        //A one-day range is fine either way
+        assert!(parse_hist_range(Some("timerange"), None, Some("2024-05-01"), Some("2024-05-01")).is_ok());
+    }
+
+    #[test]
+    fn bad_dates_are_rejected() {
+        for bad in ["2024-02-30", "05/01/2024", "2024-05-01T06:00:00", "2024-05-01' OR '1'='1", ""] {
+            assert!(rejected(parse_hist_range(Some("daily"), Some(bad), None, None)).contains("day must be a date"), "{bad}");
+            assert!(rejected(parse_hist_range(Some("timerange"), None, Some("2024-05-01"), Some(bad))).contains("day2 must be a date"), "{bad}");
+        }
+
+        assert!(rejected(parse_hist_range(Some("daily"), Some("2024-05-01T06:00:00Z"), None, None)).contains("search_type=timerange"));
+        assert!(rejected(parse_hist_range(Some("timerange"), None, Some("2024-05-01"), Some("2024-05-02T00:00:00Z"))).contains("both be dates or both be datetimes"));
+    }
+
+    #[test]
+    fn reversed_ranges_are_rejected() {
+        assert_eq!(rejected(parse_hist_range(Some("timerange"), None, Some("2024-05-03"), Some("2024-05-01"))), "day1 (2024-05-03) is after day2 (2024-05-01)");
+        assert!(rejected(parse_hist_range(Some("timerange"), None, Some("2024-05-01T12:00:00Z"), Some("2024-05-01T06:00:00-04:00"))).contains("is after"));
+    }
+
+    #[test]
+    fn open_ended_ranges_are_rejected() {
+        assert_eq!(rejected(parse_hist_range(Some("timerange"), None, Some("2024-05-01"), None)), "missing day2");
+        assert_eq!(rejected(parse_hist_range(Some("timerange"), None, None, Some("2024-05-01"))), "missing day1");
+        assert_eq!(rejected(parse_hist_range(Some("daily"), None, Some("2024-05-01"), Some("2024-05-02"))), "missing day");
+        assert_eq!(rejected(parse_hist_range(None, Some("2024-05-01"), None, None)), "missing search_type");
+        assert_eq!(rejected(parse_hist_range(Some("weekly"), Some("2024-05-01"), None, None)), "unknown search_type provided");
+    }
 }
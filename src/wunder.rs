@@ -1,31 +1,41 @@
 pub mod models;
 
-fn get_station_id() -> String {
-    std::env::var("RUSTYFORECAST_StationID").expect("Station ID not set")
+use crate::{ config::{ Config, LocationConfig }, error::Error, provider::{ self, ObservationProvider }, quota };
+use models::InstModel;
+
+//Weather Underground PWS current observations (api.weather.com)
+pub struct WunderProvider {
+    station_id: String,
+    api_key: String,
 }
 
-pub fn get_inst_web(inst_model: &mut models::Root) -> Result<String, String> {
-    let client = reqwest::blocking::Client::new();
-
-    let station_id = get_station_id();
-
-    let params = [
-        ("stationId", station_id.as_str()),
-        ("format", "json"),
-        ("units", "e"),
-        ("apiKey", &std::env::var("RUSTYFORECAST_wunderApi").unwrap()),
-    ];
-
-    match client.get("https://api.weather.com/v2/pws/observations/current").query(&params).send() {
-        Ok(resp) => {
-            match resp.json::<models::Root>() {
-                Ok(model) => {
-                    *inst_model = model;
-                    Ok("Successfully pulled and deserialized Inst data from Wunderground".to_string())
-                }
-                Err(e) => Err(format!("Couldn't deserialize Inst data from wunderground, Reason: {e}")),
-            }
-        }
-        Err(e) => Err(format!("Couldn't pull Inst data from wunderground, Reason: {e}")),
+impl WunderProvider {
+    pub fn from_config(config: &Config, location: &LocationConfig) -> Result<WunderProvider, Error> {
+        Ok(WunderProvider {
+            station_id: location.station_id.clone().ok_or_else(|| Error::Config("Station ID not set".to_string()))?,
+            api_key: config.providers.wunder_api_key.clone().ok_or_else(|| Error::Config("Wunderground API key not set".to_string()))?,
+        })
+    }
+}
+
+impl ObservationProvider for WunderProvider {
+    fn name(&self) -> &'static str {
+        "wunder"
+    }
+
+    fn get_inst(&self) -> Result<InstModel, Error> {
+        let client = reqwest::blocking::Client::new();
+
+        let params = [
+            ("stationId", self.station_id.as_str()),
+            ("format", "json"),
+            ("units", "e"),
+            ("apiKey", &self.api_key),
+        ];
+
+        let resp = quota::send(self.name(), client.get("https://api.weather.com/v2/pws/observations/current").query(&params))?;
+        let model = provider::parse_json::<models::Root>(self.name(), resp)?;
+
+        InstModel::convert(&model)
     }
 }
@@ -1,3 +1,4 @@
+use crate::{calc, config::{LocationConfig, Units}, error::Error, provider};
 use chrono::{DateTime, Utc,NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -46,7 +47,7 @@ pub struct Imperial {
 }
 
 //DB Model
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InstModel {
     pub obs_time_utc: chrono::DateTime<Utc>,
@@ -67,17 +68,16 @@ pub struct InstModel {
 }
 
 impl InstModel {
-    pub fn convert(inst: &mut Root) -> InstModel {
-        let obs: &Observation = &inst.observations[0];
+    pub fn convert(inst: &Root) -> Result<InstModel, Error> {
+        let obs: &Observation = inst.observations.first().ok_or_else(|| Error::upstream_decode("wunder", "Wunderground returned no observations"))?;
         let imp = &obs.imperial;
 
-        InstModel {
+        Ok(InstModel {
             obs_time_utc: DateTime::parse_from_rfc3339(&obs.obs_time_utc)
-                .unwrap()
+                .map_err(|e| Error::upstream_decode("wunder", format!("Couldn't parse obsTimeUtc '{}', Reason: {e}", obs.obs_time_utc)))?
                 .into(),
             obs_time_local: NaiveDateTime::parse_from_str(&obs.obs_time_local,"%Y-%m-%d %_H:%M:%S")
-                .unwrap()
-                .into(),
+                .map_err(|e| Error::upstream_decode("wunder", format!("Couldn't parse obsTimeLocal '{}', Reason: {e}", obs.obs_time_local)))?,
             temp: imp.temp,
             dewpt: imp.dewpt,
             heat_index: imp.heat_index,
@@ -91,6 +91,104 @@ impl InstModel {
             winddir: obs.winddir,
             wind_speed: imp.wind_speed,
             wind_gust: imp.wind_gust,
+        })
+    }
+
+    //Back into the weather.com shape /forecast/instant has always served, in imperial like the units=e pull
+    //it used to proxy. Where the station is comes from the location's config, fields the cache doesn't keep are
+    //left empty, and so is the list before the first observation
+    pub fn to_root(&self, location: &LocationConfig) -> Root {
+        if self.obs_time_utc == DateTime::<Utc>::default() {
+            return Root::default();
+        }
+
+        //Config has already validated it
+        let (lat, lon) = provider::parse_lat_long(&location.lat_long).unwrap_or_default();
+
+        Root {
+            observations: vec![Observation {
+                station_id: location.station_id.clone().unwrap_or_default(),
+                neighborhood: location.name.clone(),
+                lat,
+                lon,
+                obs_time_utc: self.obs_time_utc.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                obs_time_local: self.obs_time_local.format("%Y-%m-%d %H:%M:%S").to_string(),
+                solar_radiation: self.solar_radiation,
+                epoch: self.obs_time_utc.timestamp() as i32,
+                uv: self.uv,
+                winddir: self.winddir,
+                humidity: self.humidity,
+                imperial: Imperial {
+                    temp: self.temp,
+                    heat_index: self.heat_index,
+                    dewpt: self.dewpt,
+                    wind_chill: self.wind_chill,
+                    wind_speed: self.wind_speed,
+                    wind_gust: self.wind_gust,
+                    pressure: self.pressure,
+                    precip_rate: self.precip_rate,
+                    precip_total: self.precip_total,
+                    ..Imperial::default()
+                },
+                ..Observation::default()
+            }],
         }
     }
-}
\ No newline at end of file
+
+    pub fn in_units(mut self, units: Units) -> InstModel {
+        if units == Units::Metric {
+            let temp = |f: i32| calc::f_to_c(f as f64).round() as i32;
+            let speed = |mph: i32| calc::mph_to_kmh(mph as f64).round() as i32;
+
+            self.temp = temp(self.temp);
+            self.heat_index = temp(self.heat_index);
+            self.wind_chill = temp(self.wind_chill);
+            self.dewpt = temp(self.dewpt);
+            self.wind_speed = speed(self.wind_speed);
+            self.wind_gust = speed(self.wind_gust);
+            self.precip_rate = calc::in_to_mm(self.precip_rate);
+            self.precip_total = calc::in_to_mm(self.precip_total);
+            self.pressure = calc::inhg_to_hpa(self.pressure);
+        }
+
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_observation_is_served_as_wunderground_shape() {
+        let root: Root = serde_json::from_str(
+            r#"{"observations": [{"stationID": "KOFFICE1", "obsTimeUtc": "2024-04-12T14:05:00Z", "obsTimeLocal": "2024-04-12 10:05:00",
+                "neighborhood": "", "softwareType": "", "country": "US", "solarRadiation": 512.3, "lon": -79.05, "realtimeFrequency": null,
+                "epoch": 1712930700, "lat": 35.9, "uv": 4.0, "winddir": 225, "humidity": 61, "qcStatus": 1,
+                "imperial": {"temp": 68, "heatIndex": 68, "dewpt": 54, "windChill": 68, "windSpeed": 7, "windGust": 12,
+                    "pressure": 30.02, "precipRate": 0.0, "precipTotal": 0.11, "elev": 500}}]}"#
+        ).unwrap();
+
+        let location = LocationConfig {
+            name: "office".to_string(),
+            lat_long: "35.9,-79.05".to_string(),
+            station_id: Some("KOFFICE1".to_string()),
+            ..LocationConfig::default()
+        };
+        let served = InstModel::convert(&root).unwrap().to_root(&location);
+        let (obs, sent) = (&served.observations[0], &root.observations[0]);
+
+        assert_eq!(obs.station_id, sent.station_id);
+        assert_eq!(obs.obs_time_utc, sent.obs_time_utc);
+        assert_eq!(obs.obs_time_local, sent.obs_time_local);
+        assert_eq!(obs.epoch, sent.epoch);
+        assert_eq!(obs.imperial.temp, sent.imperial.temp);
+        assert_eq!(obs.imperial.precip_total, sent.imperial.precip_total);
+        assert_eq!(obs.winddir, sent.winddir);
+        assert_eq!((obs.lat, obs.lon), (sent.lat, sent.lon));
+        assert_eq!(obs.neighborhood, "office");
+
+        //Nothing cached yet
+        assert!(InstModel::default().to_root(&location).observations.is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{ config::Config, error::Error };
+use chrono::{ DateTime, FixedOffset, Local, NaiveDateTime, Offset, TimeZone, Utc };
+use chrono_tz::Tz;
+use std::{ collections::HashMap, sync::OnceLock };
+
+//The clock a location's local times are kept in, its configured timezone or this host's when it has none.
+//Observed and forecast rows are stored as wall time on it, so a location's hours and days pair up in SQL
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Zone {
+    #[default]
+    Host,
+    Named(Tz),
+}
+
+static ZONES: OnceLock<HashMap<String, Zone>> = OnceLock::new();
+
+//Config has already validated the names
+pub fn init(config: &Config) {
+    let zones = config
+        .locations
+        .iter()
+        .filter_map(|location| Some((location.name.clone(), Zone::parse(location.timezone.as_deref()?).ok()?)))
+        .collect();
+
+    let _ = ZONES.set(zones);
+}
+
+//Host for locations without a timezone, and before init as in the tests
+pub fn of(location: &str) -> Zone {
+    ZONES.get().and_then(|zones| zones.get(location)).copied().unwrap_or(Zone::Host)
+}
+
+impl Zone {
+    //IANA names like "America/New_York"
+    pub fn parse(name: &str) -> Result<Zone, String> {
+        name.parse::<Tz>().map(Zone::Named).map_err(|_| format!("unknown timezone '{name}'"))
+    }
+
+    pub fn at(&self, time: DateTime<Utc>) -> DateTime<FixedOffset> {
+        let offset = match self {
+            Zone::Host => time.with_timezone(&Local).offset().fix(),
+            Zone::Named(tz) => time.with_timezone(tz).offset().fix(),
+        };
+
+        time.with_timezone(&offset)
+    }
+
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        self.at(Utc::now())
+    }
+
+    pub fn wall_time(&self, time: DateTime<Utc>) -> NaiveDateTime {
+        self.at(time).naive_local()
+    }
+
+    //An hour repeated by the fall DST change reads as its first occurrence, one skipped by the spring change
+    //doesn't exist
+    pub fn instant(&self, wall_time: NaiveDateTime) -> Result<DateTime<Utc>, Error> {
+        let time = match self {
+            Zone::Host => Local.from_local_datetime(&wall_time).earliest().map(|t| t.with_timezone(&Utc)),
+            Zone::Named(tz) => tz.from_local_datetime(&wall_time).earliest().map(|t| t.with_timezone(&Utc)),
+        };
+
+        time.ok_or_else(|| Error::Validation(format!("{wall_time} doesn't exist in the location's timezone")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_zones_keep_their_own_clock() {
+        let zone = Zone::parse("America/New_York").unwrap();
+        let summer = Utc.with_ymd_and_hms(2024, 7, 1, 16, 0, 0).unwrap();
+        let winter = Utc.with_ymd_and_hms(2024, 1, 1, 17, 0, 0).unwrap();
+
+        assert_eq!(zone.wall_time(summer).to_string(), "2024-07-01 12:00:00");
+        assert_eq!(zone.wall_time(winter).to_string(), "2024-01-01 12:00:00");
+        assert_eq!(zone.at(summer).offset().local_minus_utc(), -4 * 3600);
+        assert_eq!(zone.instant(zone.wall_time(summer)).unwrap(), summer);
+    }
+
+    #[test]
+    fn dst_changes_resolve_to_the_first_hour() {
+        let zone = Zone::parse("America/New_York").unwrap();
+        let repeated = NaiveDateTime::parse_from_str("2024-11-03 01:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let skipped = NaiveDateTime::parse_from_str("2024-03-10 02:30:00", "%Y-%m-%d %H:%M:%S").unwrap();
+
+        assert_eq!(zone.instant(repeated).unwrap(), Utc.with_ymd_and_hms(2024, 11, 3, 5, 30, 0).unwrap());
+        assert!(matches!(zone.instant(skipped), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn host_zone_is_the_default() {
+        let time = Utc.with_ymd_and_hms(2024, 4, 12, 14, 0, 0).unwrap();
+
+        assert_eq!(of("not-configured"), Zone::Host);
+        assert_eq!(Zone::Host.wall_time(time), time.with_timezone(&Local).naive_local());
+        assert!(Zone::parse("Mars/Olympus_Mons").is_err());
+    }
+}